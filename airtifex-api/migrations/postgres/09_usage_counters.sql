@@ -0,0 +1,11 @@
+CREATE TABLE usage_counters (
+     username VARCHAR NOT NULL,
+     counter VARCHAR NOT NULL,
+     window_start TIMESTAMPTZ NOT NULL,
+     value BIGINT NOT NULL DEFAULT 0,
+     PRIMARY KEY (username, counter, window_start),
+     CONSTRAINT fk_users
+       FOREIGN KEY (username)
+       REFERENCES users (username)
+       ON DELETE CASCADE
+);
@@ -0,0 +1,20 @@
+CREATE TABLE usage (
+     id UUID PRIMARY KEY NOT NULL,
+     username VARCHAR NOT NULL,
+     model VARCHAR NOT NULL,
+     chat_id UUID,
+     date TIMESTAMPTZ NOT NULL,
+
+     prompt_tokens BIGINT NOT NULL DEFAULT 0,
+     generated_tokens BIGINT NOT NULL DEFAULT 0,
+     n_steps BIGINT NOT NULL DEFAULT 0,
+     num_samples BIGINT NOT NULL DEFAULT 0,
+     wall_time FLOAT NOT NULL,
+     tokens_per_second FLOAT NOT NULL DEFAULT 0,
+     CONSTRAINT fk_users
+       FOREIGN KEY (username)
+       REFERENCES users (username)
+       ON DELETE CASCADE
+);
+
+CREATE INDEX usage_date ON usage (date);
@@ -0,0 +1,7 @@
+ALTER TABLE images ADD COLUMN input_image_key VARCHAR;
+ALTER TABLE images ADD COLUMN mask_key VARCHAR;
+ALTER TABLE images ADD COLUMN thumbnail_key VARCHAR;
+
+-- The data of samples is moved to the storage on startup
+ALTER TABLE image_samples ALTER COLUMN data DROP NOT NULL;
+ALTER TABLE image_samples ADD COLUMN data_key VARCHAR;
@@ -0,0 +1,20 @@
+CREATE INDEX images_user_date_idx ON images (user_id, create_date);
+CREATE INDEX chats_user_date_idx ON chats (username, start_date);
+CREATE INDEX prompts_user_date_idx ON prompts (username, date);
+CREATE INDEX chat_entries_chat_idx ON chat_entries (chat_id);
+
+ALTER TABLE images ADD COLUMN search tsvector
+    GENERATED ALWAYS AS (to_tsvector('english', prompt || ' ' || coalesce(negative_prompt, ''))) STORED;
+CREATE INDEX images_search_idx ON images USING GIN (search);
+
+ALTER TABLE prompts ADD COLUMN search tsvector
+    GENERATED ALWAYS AS (to_tsvector('english', prompt || ' ' || response)) STORED;
+CREATE INDEX prompts_search_idx ON prompts USING GIN (search);
+
+ALTER TABLE chats ADD COLUMN search tsvector
+    GENERATED ALWAYS AS (to_tsvector('english', coalesce(title, ''))) STORED;
+CREATE INDEX chats_search_idx ON chats USING GIN (search);
+
+ALTER TABLE chat_entries ADD COLUMN search tsvector
+    GENERATED ALWAYS AS (to_tsvector('english', coalesce(content, ''))) STORED;
+CREATE INDEX chat_entries_search_idx ON chat_entries USING GIN (search);
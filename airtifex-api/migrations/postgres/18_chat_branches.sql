@@ -0,0 +1,20 @@
+ALTER TABLE chat_entries ADD COLUMN parent_entry_id UUID REFERENCES chat_entries (entry_id) ON DELETE CASCADE;
+ALTER TABLE chats ADD COLUMN active_entry_id UUID;
+
+-- existing chats are a single branch with every entry following the previous one
+UPDATE chat_entries SET parent_entry_id = (
+    SELECT p.entry_id FROM chat_entries p
+    WHERE p.chat_id = chat_entries.chat_id
+      AND (p.entry_date < chat_entries.entry_date
+           OR (p.entry_date = chat_entries.entry_date AND p.entry_type < chat_entries.entry_type))
+    ORDER BY p.entry_date DESC, p.entry_type DESC
+    LIMIT 1
+);
+UPDATE chats SET active_entry_id = (
+    SELECT e.entry_id FROM chat_entries e
+    WHERE e.chat_id = chats.id
+    ORDER BY e.entry_date DESC, e.entry_type DESC
+    LIMIT 1
+);
+
+CREATE INDEX chat_entries_parent_idx ON chat_entries (parent_entry_id);
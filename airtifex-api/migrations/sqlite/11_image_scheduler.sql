@@ -0,0 +1 @@
+ALTER TABLE images ADD COLUMN scheduler VARCHAR NOT NULL DEFAULT 'ddim';
@@ -0,0 +1,2 @@
+ALTER TABLE images ADD COLUMN status VARCHAR NOT NULL DEFAULT 'completed';
+UPDATE images SET status = 'queued' WHERE processing = true;
@@ -0,0 +1,22 @@
+ALTER TABLE images ADD COLUMN input_image_key VARCHAR;
+ALTER TABLE images ADD COLUMN mask_key VARCHAR;
+ALTER TABLE images ADD COLUMN thumbnail_key VARCHAR;
+
+-- The data of samples is moved to the storage on startup, SQLite can't drop the NOT NULL
+-- constraint so the table is recreated
+CREATE TABLE image_samples_new (
+     sample_id UUID PRIMARY KEY NOT NULL,
+     image_id UUID NOT NULL,
+     n INTEGER NOT NULL,
+     data BLOB,
+     data_key VARCHAR,
+
+     CONSTRAINT fk_image
+       FOREIGN KEY (image_id)
+       REFERENCES images (id)
+       ON DELETE CASCADE
+);
+INSERT INTO image_samples_new (sample_id, image_id, n, data)
+SELECT sample_id, image_id, n, data FROM image_samples;
+DROP TABLE image_samples;
+ALTER TABLE image_samples_new RENAME TO image_samples;
@@ -0,0 +1 @@
+ALTER TABLE images ADD COLUMN visibility VARCHAR NOT NULL DEFAULT 'private';
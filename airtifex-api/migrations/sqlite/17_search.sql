@@ -0,0 +1,48 @@
+CREATE INDEX images_user_date_idx ON images (user_id, create_date);
+CREATE INDEX chats_user_date_idx ON chats (username, start_date);
+CREATE INDEX prompts_user_date_idx ON prompts (username, date);
+CREATE INDEX chat_entries_chat_idx ON chat_entries (chat_id);
+
+-- Full-text indexes kept up to date by triggers. The IDs are stored in the index as the
+-- implicit rowids of the tables can change on VACUUM.
+CREATE VIRTUAL TABLE images_fts USING fts5(id UNINDEXED, prompt, negative_prompt, tokenize = 'porter unicode61');
+INSERT INTO images_fts (id, prompt, negative_prompt) SELECT id, prompt, negative_prompt FROM images;
+CREATE TRIGGER images_fts_insert AFTER INSERT ON images BEGIN
+    INSERT INTO images_fts (id, prompt, negative_prompt) VALUES (new.id, new.prompt, new.negative_prompt);
+END;
+CREATE TRIGGER images_fts_delete AFTER DELETE ON images BEGIN
+    DELETE FROM images_fts WHERE id = old.id;
+END;
+
+CREATE VIRTUAL TABLE prompts_fts USING fts5(id UNINDEXED, prompt, response, tokenize = 'porter unicode61');
+INSERT INTO prompts_fts (id, prompt, response) SELECT id, prompt, response FROM prompts;
+CREATE TRIGGER prompts_fts_insert AFTER INSERT ON prompts BEGIN
+    INSERT INTO prompts_fts (id, prompt, response) VALUES (new.id, new.prompt, new.response);
+END;
+CREATE TRIGGER prompts_fts_delete AFTER DELETE ON prompts BEGIN
+    DELETE FROM prompts_fts WHERE id = old.id;
+END;
+
+CREATE VIRTUAL TABLE chats_fts USING fts5(id UNINDEXED, title, tokenize = 'porter unicode61');
+INSERT INTO chats_fts (id, title) SELECT id, title FROM chats;
+CREATE TRIGGER chats_fts_insert AFTER INSERT ON chats BEGIN
+    INSERT INTO chats_fts (id, title) VALUES (new.id, new.title);
+END;
+CREATE TRIGGER chats_fts_update AFTER UPDATE OF title ON chats BEGIN
+    UPDATE chats_fts SET title = new.title WHERE id = old.id;
+END;
+CREATE TRIGGER chats_fts_delete AFTER DELETE ON chats BEGIN
+    DELETE FROM chats_fts WHERE id = old.id;
+END;
+
+CREATE VIRTUAL TABLE chat_entries_fts USING fts5(entry_id UNINDEXED, chat_id UNINDEXED, content, tokenize = 'porter unicode61');
+INSERT INTO chat_entries_fts (entry_id, chat_id, content) SELECT entry_id, chat_id, content FROM chat_entries;
+CREATE TRIGGER chat_entries_fts_insert AFTER INSERT ON chat_entries BEGIN
+    INSERT INTO chat_entries_fts (entry_id, chat_id, content) VALUES (new.entry_id, new.chat_id, new.content);
+END;
+CREATE TRIGGER chat_entries_fts_update AFTER UPDATE OF content ON chat_entries BEGIN
+    UPDATE chat_entries_fts SET content = new.content WHERE entry_id = old.entry_id;
+END;
+CREATE TRIGGER chat_entries_fts_delete AFTER DELETE ON chat_entries BEGIN
+    DELETE FROM chat_entries_fts WHERE entry_id = old.entry_id;
+END;
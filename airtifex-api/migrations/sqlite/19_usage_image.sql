@@ -0,0 +1,5 @@
+-- usage rows get their own IDs so that a retried image can be recorded again
+ALTER TABLE usage ADD COLUMN image_id UUID;
+UPDATE usage SET image_id = id WHERE n_steps > 0;
+
+CREATE INDEX usage_image_idx ON usage (image_id);
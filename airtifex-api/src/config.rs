@@ -1,6 +1,9 @@
-use crate::{Error, Result};
+use crate::{queue::Priority, Error, Result};
 
-use airtifex_core::image::ImageModelFeatures;
+use airtifex_core::{
+    image::{ImageModelFeatures, ImageScheduler},
+    user::AccountType,
+};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, env, path::PathBuf};
 
@@ -14,6 +17,14 @@ struct RawConfig {
     llms: Vec<LlmConfig>,
     #[serde(default)]
     stable_diffusion: Vec<StableDiffusionConfig>,
+    #[serde(default)]
+    priorities: Priorities,
+    #[serde(default)]
+    quotas: Quotas,
+    #[serde(default)]
+    residency: ResidencyConfig,
+    #[serde(default)]
+    storage: StorageConfig,
 }
 
 fn default_num_ctx_tokens() -> usize {
@@ -44,7 +55,7 @@ fn default_num_threads() -> usize {
     num_cpus::get_physical()
 }
 
-#[derive(Clone, Copy, Deserialize, Serialize)]
+#[derive(Clone, Copy, Deserialize, Serialize, PartialEq)]
 pub enum LlmType {
     #[serde(alias = "bloom")]
     Bloom,
@@ -63,7 +74,7 @@ pub enum LlmType {
     Neox,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize, PartialEq)]
 pub struct LlmConfig {
     pub model_description: Option<String>,
     pub model_path: std::path::PathBuf,
@@ -91,6 +102,8 @@ pub struct LlmConfig {
     #[serde(default = "default_max_inference_sessions")]
     // Maximum concurent sessions for inference
     pub max_inference_sessions: usize,
+    /// Maximum concurrent sessions for inference of a single user, unlimited if not set
+    pub max_sessions_per_user: Option<usize>,
     #[serde(rename = "type")]
     pub type_: LlmType,
 }
@@ -102,6 +115,146 @@ pub struct Config {
     pub jwt_secret: String,
     pub llms: HashMap<String, LlmConfig>,
     pub stable_diffusion: Vec<StableDiffusionConfig>,
+    pub priorities: Priorities,
+    pub quotas: Quotas,
+    pub residency: ResidencyConfig,
+    pub storage: StorageConfig,
+}
+
+fn normal_priority() -> Priority {
+    Priority::Normal
+}
+fn low_priority() -> Priority {
+    Priority::Low
+}
+
+/// Scheduling priority of requests submitted by each account type.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Priorities {
+    #[serde(default = "normal_priority")]
+    pub admin: Priority,
+    #[serde(default = "normal_priority")]
+    pub user: Priority,
+    #[serde(default = "low_priority")]
+    pub service: Priority,
+}
+
+impl Default for Priorities {
+    fn default() -> Self {
+        Self {
+            admin: normal_priority(),
+            user: normal_priority(),
+            service: low_priority(),
+        }
+    }
+}
+
+impl Priorities {
+    pub fn of(&self, account_type: AccountType) -> Priority {
+        match account_type {
+            AccountType::Admin => self.admin,
+            AccountType::User => self.user,
+            AccountType::Service => self.service,
+        }
+    }
+}
+
+/// Generation limits of a single user, every limit is unlimited if not set.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Quota {
+    pub requests_per_minute: Option<u64>,
+    /// Prompt and generated tokens of all language models
+    pub tokens_per_day: Option<u64>,
+    pub image_samples_per_day: Option<u64>,
+}
+
+impl Quota {
+    /// Fills the limits that are not set with the ones from `defaults`.
+    fn or(&self, defaults: &Quota) -> Quota {
+        Quota {
+            requests_per_minute: self.requests_per_minute.or(defaults.requests_per_minute),
+            tokens_per_day: self.tokens_per_day.or(defaults.tokens_per_day),
+            image_samples_per_day: self
+                .image_samples_per_day
+                .or(defaults.image_samples_per_day),
+        }
+    }
+}
+
+/// Quotas of each account type with overrides for single users.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Quotas {
+    #[serde(default)]
+    pub admin: Quota,
+    #[serde(default)]
+    pub user: Quota,
+    #[serde(default)]
+    pub service: Quota,
+    /// Per user quotas, limits that are not set fall back to the quota of the account type
+    #[serde(default)]
+    pub users: HashMap<String, Quota>,
+}
+
+impl Quotas {
+    pub fn of(&self, username: &str, account_type: AccountType) -> Quota {
+        let defaults = match account_type {
+            AccountType::Admin => &self.admin,
+            AccountType::User => &self.user,
+            AccountType::Service => &self.service,
+        };
+        match self.users.get(username) {
+            Some(quota) => quota.or(defaults),
+            None => defaults.clone(),
+        }
+    }
+}
+
+/// Controls which models are kept loaded in memory.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct ResidencyConfig {
+    /// Maximum size of the loaded model weights in megabytes. Least recently used idle models are
+    /// unloaded to make room for a model that is about to be loaded.
+    pub memory_budget_mb: Option<u64>,
+    /// Unloads a model after it didn't process any request for this many seconds.
+    pub idle_timeout_secs: Option<u64>,
+    /// Loads the models on their first request instead of on startup.
+    #[serde(default)]
+    pub lazy_load: bool,
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".into()
+}
+
+/// Where generated images and uploaded inputs are stored.
+#[derive(Clone, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StorageConfig {
+    /// Files in a local directory
+    Local { path: PathBuf },
+    /// A bucket of Amazon S3 or an S3 compatible object store
+    S3 {
+        bucket: String,
+        #[serde(default = "default_s3_region")]
+        region: String,
+        /// URL of an S3 compatible object store, Amazon S3 is used if not set
+        endpoint: Option<String>,
+        /// Credentials are read from the environment if not set
+        access_key: Option<String>,
+        secret_key: Option<String>,
+        /// Address the bucket as part of the path instead of the domain, required by most
+        /// self-hosted object stores
+        #[serde(default)]
+        path_style: bool,
+    },
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig::Local {
+            path: PathBuf::from("storage"),
+        }
+    }
 }
 
 impl Config {
@@ -151,6 +304,10 @@ impl Config {
             jwt_secret,
             llms,
             stable_diffusion: config.stable_diffusion,
+            priorities: config.priorities,
+            quotas: config.quotas,
+            residency: config.residency,
+            storage: config.storage,
         })
     }
 }
@@ -159,7 +316,7 @@ fn default_is_cpu() -> bool {
     true
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize, PartialEq)]
 pub enum StableDiffusionVersion {
     #[serde(rename = "v1.5")]
     V1_5,
@@ -194,7 +351,7 @@ fn on() -> bool {
     true
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize, PartialEq)]
 pub struct StableDiffusionConfig {
     pub name: Option<String>,
     pub model_description: Option<String>,
@@ -211,10 +368,17 @@ pub struct StableDiffusionConfig {
     pub unet_cpu: bool,
     #[serde(default = "default_max_image_gen_sessions")]
     pub max_image_gen_sessions: usize,
+    /// Maximum concurrent image generation sessions of a single user, unlimited if not set
+    pub max_sessions_per_user: Option<usize>,
     #[serde(default = "default_max_timesteps")]
     pub max_timesteps: usize,
     #[serde(default = "default_max_guidance_scale")]
     pub max_guidance_scale: f32,
+    /// Scheduler used by requests that don't select one
+    #[serde(default)]
+    pub scheduler: ImageScheduler,
+    /// Send a preview of the image to progress streams every N steps, disabled if not set
+    pub preview_interval: Option<usize>,
 
     #[serde(default = "off")]
     pub feature_inpaint: bool,
@@ -225,6 +389,13 @@ pub struct StableDiffusionConfig {
 }
 
 impl StableDiffusionConfig {
+    /// Name under which the model is available, derived from the version if not set.
+    pub fn name(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("stable-diffusion-{}", self.version.as_ref()))
+    }
+
     pub fn features(&self) -> ImageModelFeatures {
         ImageModelFeatures {
             inpaint: self.feature_inpaint,
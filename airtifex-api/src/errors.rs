@@ -35,7 +35,23 @@ pub enum Error {
     #[error("failed to deserialize configuration file as yaml - {0}")]
     ConfigDeserializeFailed(serde_yaml::Error),
     #[error("Failed to send token to receiver - {0}")]
-    InferenceSend(flume::SendError<airtifex_core::llm::ChatStreamResult>),
+    InferenceSend(flume::SendError<airtifex_core::llm::InferenceEvent>),
     #[error(transparent)]
     InferenceError(#[from] llm::InferenceError),
+    #[error("Failed to submit request {0}, the model is no longer accepting requests")]
+    QueueClosed(String),
+    #[error("The model failed to load and is unavailable - {0}")]
+    ModelUnavailable(String),
+    #[error("Failed to load model - {0}")]
+    ModelLoadError(#[from] llm::LoadError),
+    #[error("Model file `{}` does not exist", .0.display())]
+    ModelFileMissing(std::path::PathBuf),
+    #[error("Model worker panicked while loading the model")]
+    ModelPanicked,
+    #[error(transparent)]
+    StorageError(#[from] crate::storage::StorageError),
+    #[error("Failed to write the archive - {0}")]
+    ArchiveError(#[from] zip::result::ZipError),
+    #[error("Failed to serialize data - {0}")]
+    SerializationError(#[from] serde_json::Error),
 }
@@ -1,11 +1,22 @@
+pub mod progress;
 pub mod sd;
 
 use std::{collections::HashMap, sync::Arc};
 
+use airtifex_core::image::ImageScheduler;
 use serde::{Deserialize, Serialize};
 use tokio::runtime::Runtime;
 
-use crate::{config::Config, models::image_model::ImageModel, DbPool, Result};
+use crate::{
+    config::{Config, StableDiffusionConfig},
+    gen::{image::progress::ImageProgressRegistry, residency::Residency, ModelHandle},
+    models::image_model::ImageModel,
+    queue::{Priority, QueueItem},
+    storage::SharedStorage,
+    DbPool, Result,
+};
+
+pub type ImageModelHandle = ModelHandle<StableDiffusionConfig, GenerateImageRequest>;
 
 pub enum GenerateImageRequest {
     TextToImage(BaseImageData),
@@ -21,18 +32,44 @@ impl GenerateImageRequest {
             Self::Inpaint(data) => &data.data.id,
         }
     }
+
+    pub fn data(&self) -> &BaseImageData {
+        match self {
+            Self::TextToImage(data) => data,
+            Self::ImageToImage(data) => &data.data,
+            Self::Inpaint(data) => &data.data,
+        }
+    }
+}
+
+impl QueueItem for GenerateImageRequest {
+    fn item_id(&self) -> String {
+        self.id().to_string()
+    }
+
+    fn owner(&self) -> &str {
+        &self.data().user
+    }
+
+    fn priority(&self) -> Priority {
+        self.data().priority
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct BaseImageData {
     pub id: String,
+    pub user: String,
+    pub priority: Priority,
     pub prompt: String,
+    pub negative_prompt: Option<String>,
     pub width: i64,
     pub height: i64,
     pub n_steps: usize,
     pub seed: i64,
     pub num_samples: i64,
     pub guidance_scale: f64,
+    pub scheduler: ImageScheduler,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -68,31 +105,64 @@ pub async fn initialize_models(
     db: Arc<DbPool>,
     config: &Config,
     runtime: Arc<Runtime>,
-) -> Result<HashMap<String, flume::Sender<GenerateImageRequest>>> {
+    progress: ImageProgressRegistry,
+    storage: SharedStorage,
+    residency: Residency,
+) -> Result<HashMap<String, ImageModelHandle>> {
     tch::maybe_init_cuda();
     log::info!("Cuda available: {}", tch::Cuda::is_available());
     log::info!("Cudnn available: {}", tch::Cuda::cudnn_is_available());
     log::info!("MPS available: {}", tch::utils::has_mps());
-    let mut txs = HashMap::new();
+    let mut handles = HashMap::new();
     for model_config in config.stable_diffusion.iter() {
-        let model = model_config
-            .name
-            .clone()
-            .unwrap_or_else(|| format!("stable-diffusion-{}", model_config.version.as_ref()));
-        let exists = ImageModel::get_by_name(&db, &model).await.is_ok();
-
-        log::info!("initializing image model {model}, exists in db: {exists}");
-
-        if !exists {
-            let image_model = ImageModel::new(
-                model.clone(),
-                model_config.model_description.clone(),
-                model_config.features(),
-            );
-            image_model.create(&db).await?;
-        }
-        let tx_inference_req = sd::initialize(db.clone(), model_config.clone(), runtime.clone());
-        txs.insert(model.clone(), tx_inference_req);
+        let model = model_config.name();
+        let handle = initialize_model(
+            db.clone(),
+            &model,
+            model_config,
+            runtime.clone(),
+            progress.clone(),
+            storage.clone(),
+            residency.clone(),
+        )
+        .await?;
+        handles.insert(model, handle);
+    }
+    Ok(handles)
+}
+
+/// Registers the model in the database if it doesn't exist yet and starts its generation thread.
+/// Fails if a file of the model is missing.
+pub async fn initialize_model(
+    db: Arc<DbPool>,
+    model: &str,
+    model_config: &StableDiffusionConfig,
+    runtime: Arc<Runtime>,
+    progress: ImageProgressRegistry,
+    storage: SharedStorage,
+    residency: Residency,
+) -> Result<ImageModelHandle> {
+    sd::validate_files(model_config)?;
+
+    let exists = ImageModel::get_by_name(&db, model).await.is_ok();
+
+    log::info!("initializing image model {model}, exists in db: {exists}");
+
+    if !exists {
+        let image_model = ImageModel::new(
+            model.to_owned(),
+            model_config.model_description.clone(),
+            model_config.features(),
+        );
+        image_model.create(&db).await?;
     }
-    Ok(txs)
+    Ok(sd::initialize(
+        model.to_owned(),
+        db,
+        model_config.clone(),
+        runtime,
+        progress,
+        storage,
+        residency,
+    ))
 }
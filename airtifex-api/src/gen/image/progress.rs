@@ -0,0 +1,80 @@
+use airtifex_core::image::ImageProgress;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+use tokio::sync::watch;
+
+#[derive(Debug)]
+struct ImageJob {
+    tx_progress: watch::Sender<ImageProgress>,
+    /// Set when the job should stop before its next timestep
+    cancelled: bool,
+}
+
+/// Keeps track of the progress of queued and running image generation jobs so that clients can
+/// follow or cancel them. Only the latest progress of each job is kept.
+#[derive(Clone, Debug, Default)]
+pub struct ImageProgressRegistry(Arc<Mutex<HashMap<String, ImageJob>>>);
+
+impl ImageProgressRegistry {
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, ImageJob>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Registers a job with its initial progress, replacing any previous entry with the same ID.
+    pub fn register(&self, progress: ImageProgress) {
+        let (tx_progress, _) = watch::channel(progress.clone());
+        self.lock().insert(
+            progress.image_id,
+            ImageJob {
+                tx_progress,
+                cancelled: false,
+            },
+        );
+    }
+
+    pub fn subscribe(&self, id: &str) -> Option<watch::Receiver<ImageProgress>> {
+        self.lock().get(id).map(|job| job.tx_progress.subscribe())
+    }
+
+    /// Whether anyone is following the progress of the job.
+    pub fn has_subscribers(&self, id: &str) -> bool {
+        self.lock()
+            .get(id)
+            .map_or(false, |job| job.tx_progress.receiver_count() > 0)
+    }
+
+    pub fn update(&self, progress: ImageProgress) {
+        if let Some(job) = self.lock().get(&progress.image_id) {
+            job.tx_progress.send_replace(progress);
+        }
+    }
+
+    /// Asks the worker to stop the job, returns `false` if the job isn't known.
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.lock().get_mut(id) {
+            Some(job) => {
+                job.cancelled = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_cancelled(&self, id: &str) -> bool {
+        self.lock().get(id).map_or(false, |job| job.cancelled)
+    }
+
+    /// Sends the final progress of the job and removes it, ending all progress streams.
+    pub fn finish(&self, id: &str) {
+        if let Some(job) = self.lock().remove(id) {
+            job.tx_progress.send_modify(|progress| {
+                progress.queued = false;
+                progress.finished = true;
+                progress.preview = None;
+            });
+        }
+    }
+}
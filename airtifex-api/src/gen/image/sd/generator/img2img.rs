@@ -1,7 +1,6 @@
 use crate::{
-    config::StableDiffusionConfig,
     gen::image::{
-        sd::generator::{BaseImageGenerator, ImageGenerator},
+        sd::generator::{BaseImageGenerator, ImageGenerator, ModelWeights},
         ImageToImageData, SaveImageFsResult,
     },
     Result,
@@ -9,8 +8,8 @@ use crate::{
 
 use diffusers::models::vae::DiagonalGaussianDistribution;
 use flume::Sender;
-use std::path::Path;
-use tch::{Device, Tensor};
+use std::{path::Path, rc::Rc};
+use tch::Tensor;
 
 use super::{GenImageError, LATENTS_SCALE};
 
@@ -24,10 +23,7 @@ pub struct ImageToImageGenerator {
 impl ImageToImageGenerator {
     pub fn new(
         request: ImageToImageData,
-        config: &StableDiffusionConfig,
-        clip_device: Device,
-        unet_device: Device,
-        vae_device: Device,
+        weights: Rc<ModelWeights>,
         tx_results: Sender<SaveImageFsResult>,
         save_dir: impl AsRef<Path>,
     ) -> Result<Self> {
@@ -36,19 +32,10 @@ impl ImageToImageGenerator {
             input_image,
             strength,
         } = request;
-        let base_generator = BaseImageGenerator::new(
-            data,
-            config,
-            clip_device,
-            unet_device,
-            vae_device,
-            tx_results,
-            save_dir,
-            1,
-        )?;
+        let base_generator = BaseImageGenerator::new(data, weights, tx_results, save_dir, 1)?;
         let init_image = image_preprocess(&input_image[..])?;
-        let init_image = init_image.to(vae_device);
-        let init_latent_dist = base_generator.vae.encode(&init_image);
+        let init_image = init_image.to(base_generator.vae_device);
+        let init_latent_dist = base_generator.weights.vae.encode(&init_image);
 
         let t_start = base_generator.request.n_steps
             - (base_generator.request.n_steps as f64 * strength) as usize;
@@ -73,7 +60,7 @@ impl ImageToImageGenerator {
         self.latents = self.base_generator.scheduler.add_noise(
             &latents,
             noise,
-            self.base_generator.scheduler.timesteps()[self.t_start],
+            self.base_generator.scheduler.timestep(self.t_start).unwrap_or_default(),
         );
     }
 }
@@ -87,6 +74,10 @@ impl ImageGenerator for ImageToImageGenerator {
         &self.base_generator
     }
 
+    fn latents(&self) -> &Tensor {
+        &self.latents
+    }
+
     fn type_(&self) -> &'static str {
         "img2img"
     }
@@ -113,7 +104,7 @@ impl ImageGenerator for ImageToImageGenerator {
             self.init_latents();
         }
 
-        let Some(&timestep) = self.base_generator.scheduler.timesteps().get(self.base_generator.processed_timesteps) else {
+        let Some(timestep) = self.base_generator.scheduler.timestep(self.base_generator.processed_timesteps) else {
             return false
         };
 
@@ -123,9 +114,9 @@ impl ImageGenerator for ImageToImageGenerator {
             .base_generator
             .scheduler
             .scale_model_input(latent_model_input, timestep);
-        let noise_pred = self.base_generator.unet.forward(
+        let noise_pred = self.base_generator.weights.unet.forward(
             &latent_model_input,
-            timestep as f64,
+            timestep,
             &self.base_generator.text_embeddings,
         );
         let noise_pred = noise_pred.chunk(2, 0);
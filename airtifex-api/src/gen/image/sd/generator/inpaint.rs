@@ -1,7 +1,6 @@
 use crate::{
-    config::StableDiffusionConfig,
     gen::image::{
-        sd::generator::{BaseImageGenerator, GenImageError, ImageGenerator},
+        sd::generator::{BaseImageGenerator, GenImageError, ImageGenerator, ModelWeights},
         InpaintData, SaveImageFsResult,
     },
     Result,
@@ -9,8 +8,8 @@ use crate::{
 
 use diffusers::models::vae::DiagonalGaussianDistribution;
 use flume::Sender;
-use std::path::Path;
-use tch::{Device, Kind, Tensor};
+use std::{path::Path, rc::Rc};
+use tch::{Kind, Tensor};
 
 use super::LATENTS_SCALE;
 
@@ -25,10 +24,7 @@ pub struct InpaintImageGenerator {
 impl InpaintImageGenerator {
     pub fn new(
         request: InpaintData,
-        config: &StableDiffusionConfig,
-        clip_device: Device,
-        unet_device: Device,
-        vae_device: Device,
+        weights: Rc<ModelWeights>,
         tx_results: Sender<SaveImageFsResult>,
         save_dir: impl AsRef<Path>,
     ) -> Result<Self> {
@@ -37,16 +33,7 @@ impl InpaintImageGenerator {
             input_image,
             mask,
         } = request;
-        let base_generator = BaseImageGenerator::new(
-            data,
-            config,
-            clip_device,
-            unet_device,
-            vae_device,
-            tx_results,
-            save_dir,
-            1,
-        )?;
+        let base_generator = BaseImageGenerator::new(data, weights, tx_results, save_dir, 1)?;
 
         let (mask, masked_image) = prepare_mask_and_masked_image(&input_image, &mask)?;
         let mask = mask.upsample_nearest2d(
@@ -57,10 +44,11 @@ impl InpaintImageGenerator {
             None,
             None,
         );
-        let mask = Tensor::cat(&[&mask, &mask], 0).to_device(unet_device);
+        let mask = Tensor::cat(&[&mask, &mask], 0).to_device(base_generator.unet_device);
         let masked_image_dist = base_generator
+            .weights
             .vae
-            .encode(&masked_image.to_device(vae_device));
+            .encode(&masked_image.to_device(base_generator.vae_device));
 
         let mut g = Self {
             base_generator,
@@ -105,6 +93,10 @@ impl ImageGenerator for InpaintImageGenerator {
         &self.base_generator
     }
 
+    fn latents(&self) -> &Tensor {
+        &self.latents
+    }
+
     fn process_next_timestep(&mut self) -> bool {
         if self.is_finished() {
             return false;
@@ -127,7 +119,7 @@ impl ImageGenerator for InpaintImageGenerator {
             self.init_latents();
         }
 
-        let Some(&timestep) = self.base_generator.scheduler.timesteps().get(self.base_generator.processed_timesteps) else {
+        let Some(timestep) = self.base_generator.scheduler.timestep(self.base_generator.processed_timesteps) else {
             return false
         };
 
@@ -144,9 +136,9 @@ impl ImageGenerator for InpaintImageGenerator {
             1,
         );
         self.log(log::Level::Debug, "got concatenated latent_model_input");
-        let noise_pred = self.base_generator.unet.forward(
+        let noise_pred = self.base_generator.weights.unet.forward(
             &latent_model_input,
-            timestep as f64,
+            timestep,
             &self.base_generator.text_embeddings,
         );
         self.log(log::Level::Debug, "got noise_pred");
@@ -1,25 +1,42 @@
 pub mod img2img;
 pub mod inpaint;
+pub mod prompt;
+pub mod scheduler;
 pub mod txt2img;
 
 use crate::{
     config::{StableDiffusionConfig, StableDiffusionVersion},
-    gen::image::{BaseImageData, SaveImageFsResult},
+    gen::image::{
+        sd::generator::{prompt::WeightedPrompt, scheduler::Scheduler},
+        BaseImageData, SaveImageFsResult,
+    },
     Result,
 };
+use airtifex_core::image::ImageProgress;
 
+use base64::Engine as _;
 use diffusers::{
     models::{unet_2d::UNet2DConditionModel, vae::AutoEncoderKL},
     pipelines::stable_diffusion,
-    schedulers::ddim::DDIMScheduler,
     transformers::clip,
 };
 use flume::Sender;
 use log::Level;
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+};
 use tch::{nn::Module, Device, Kind, Tensor};
 
 pub const LATENTS_SCALE: f64 = 0.18215;
+/// Approximate contribution of each latent channel to the RGB channels of the decoded image
+const LATENT_RGB_FACTORS: [f32; 12] = [
+    0.298, 0.207, 0.208, //
+    0.187, 0.286, 0.173, //
+    -0.158, 0.189, 0.264, //
+    -0.184, -0.271, -0.473,
+];
 
 #[derive(Debug, thiserror::Error)]
 pub enum GenImageError {
@@ -27,8 +44,6 @@ pub enum GenImageError {
     ClipTokenizerInit(anyhow::Error),
     #[error("failed to encode prompt - {0}")]
     PromptEncode(anyhow::Error),
-    #[error("failed to encode tokens - {0}")]
-    TokensEncode(anyhow::Error),
     #[error("failed to build CLIP Transformer - {0}")]
     ClipTransformerBuild(anyhow::Error),
     #[error("failed to build autoencoder - {0}")]
@@ -50,6 +65,7 @@ pub trait ImageGenerator {
     fn base_generator(&self) -> &BaseImageGenerator;
     fn is_finished(&self) -> bool;
     fn process_next_timestep(&mut self) -> bool;
+    fn latents(&self) -> &Tensor;
 
     fn log_timestep(&self) {
         self.base_generator().log_timestep(self.type_());
@@ -58,14 +74,120 @@ pub trait ImageGenerator {
     fn log(&self, level: Level, msg: &str) {
         self.base_generator().log(level, self.type_(), msg);
     }
+
+    fn progress(&self, with_preview: bool) -> ImageProgress {
+        let base_generator = self.base_generator();
+        let mut progress = base_generator.progress();
+        if with_preview && !progress.finished {
+            progress.preview = base_generator.preview(self.latents());
+        }
+        progress
+    }
 }
 
-pub struct BaseImageGenerator {
+/// Weights of a Stable Diffusion model loaded once by the worker and shared by all generators it
+/// runs.
+pub struct ModelWeights {
+    version: StableDiffusionVersion,
+    tokenizer: Arc<clip::Tokenizer>,
+    text_model: clip::ClipTextTransformer,
     vae: AutoEncoderKL,
-    vae_device: Device,
     unet: UNet2DConditionModel,
+    clip_device: Device,
+    vae_device: Device,
+    unet_device: Device,
+}
+
+impl ModelWeights {
+    pub fn load(
+        config: &StableDiffusionConfig,
+        clip_device: Device,
+        vae_device: Device,
+        unet_device: Device,
+    ) -> Result<Self> {
+        // The weights don't depend on the size of the generated image
+        let sd_config = Self::sd_config(&config.version, None, None);
+        let tokenizer = prompt::tokenizer(config)?;
+
+        let _no_grad_guard = tch::no_grad_guard();
+
+        log::debug!("Building the Clip transformer.");
+        let text_model = sd_config
+            .build_clip_transformer(&config.clip_weights_path.to_string_lossy(), clip_device)
+            .map_err(GenImageError::ClipTransformerBuild)?;
+
+        log::debug!("Building the autoencoder");
+        let vae = sd_config
+            .build_vae(&config.vae_weights_path.to_string_lossy(), vae_device)
+            .map_err(GenImageError::VaeBuild)?;
+
+        log::debug!("Building unet");
+        let unet = sd_config
+            .build_unet(&config.unet_weights_path.to_string_lossy(), unet_device, 4)
+            .map_err(GenImageError::UnetBuild)?;
+
+        Ok(Self {
+            version: config.version.clone(),
+            tokenizer,
+            text_model,
+            vae,
+            unet,
+            clip_device,
+            vae_device,
+            unet_device,
+        })
+    }
+
+    fn sd_config(
+        version: &StableDiffusionVersion,
+        height: Option<i64>,
+        width: Option<i64>,
+    ) -> stable_diffusion::StableDiffusionConfig {
+        match version {
+            StableDiffusionVersion::V2_1 => {
+                stable_diffusion::StableDiffusionConfig::v2_1(None, height, width)
+            }
+            _ => stable_diffusion::StableDiffusionConfig::v1_5(None, height, width),
+        }
+    }
+
+    fn tokenize_prompt(&self, prompt: &str) -> Result<WeightedPrompt> {
+        WeightedPrompt::tokenize(&self.tokenizer, prompt)
+    }
+
+    /// Encodes the prompt chunk by chunk concatenating the embeddings. Token embeddings are
+    /// scaled by their weights and then rescaled so that the mean of the chunk stays the same.
+    fn encode_prompt(&self, prompt: &WeightedPrompt, n_chunks: usize) -> Tensor {
+        let chunks = (0..n_chunks)
+            .map(|idx| {
+                let (tokens, weights) = prompt.chunk(idx);
+                let tokens = Tensor::from_slice(&tokens)
+                    .view((1, -1))
+                    .to(self.clip_device);
+                let embeddings = self.text_model.forward(&tokens);
+                if weights.iter().all(|&weight| weight == 1.0) {
+                    return embeddings;
+                }
+
+                let weights = Tensor::from_slice(&weights)
+                    .view((1, -1, 1))
+                    .to_kind(embeddings.kind())
+                    .to(self.clip_device);
+                let mean = embeddings.mean(Kind::Float);
+                let weighted = embeddings * weights;
+                let scale = mean / weighted.mean(Kind::Float);
+                weighted * scale
+            })
+            .collect::<Vec<_>>();
+        Tensor::cat(&chunks, 1)
+    }
+}
+
+pub struct BaseImageGenerator {
+    weights: Rc<ModelWeights>,
+    vae_device: Device,
     unet_device: Device,
-    scheduler: DDIMScheduler,
+    scheduler: Scheduler,
     text_embeddings: Tensor,
     tx_results: Sender<SaveImageFsResult>,
     request: BaseImageData,
@@ -77,78 +199,52 @@ pub struct BaseImageGenerator {
 }
 
 impl BaseImageGenerator {
-    #[allow(clippy::too_many_arguments)]
     pub fn new(
         request: BaseImageData,
-        config: &StableDiffusionConfig,
-        clip_device: Device,
-        unet_device: Device,
-        vae_device: Device,
+        weights: Rc<ModelWeights>,
         tx_results: Sender<SaveImageFsResult>,
         save_dir: impl AsRef<Path>,
         bsize: i64,
     ) -> Result<Self> {
-        let sd_config = match config.version {
-            StableDiffusionVersion::V2_1 => stable_diffusion::StableDiffusionConfig::v2_1(
-                None,
-                Some(request.height),
-                Some(request.width),
-            ),
-            _ => stable_diffusion::StableDiffusionConfig::v1_5(
-                None,
-                Some(request.height),
-                Some(request.width),
-            ),
-        };
-        let scheduler = sd_config.build_scheduler(request.n_steps);
-        let tokenizer = clip::Tokenizer::create(&config.vocab_file, &sd_config.clip)
-            .map_err(GenImageError::ClipTokenizerInit)?;
+        let sd_config =
+            ModelWeights::sd_config(&weights.version, Some(request.height), Some(request.width));
+        let scheduler = Scheduler::new(request.scheduler, &sd_config, request.n_steps);
 
         log::debug!(
             "[{}] Generating image for prompt \"{}\".",
             request.id,
             request.prompt
         );
-        let tokens = tokenizer
-            .encode(&request.prompt)
-            .map_err(GenImageError::PromptEncode)?;
-
-        let tokens: Vec<i64> = tokens.into_iter().map(|x| x as i64).collect();
-        let tokens = Tensor::from_slice(&tokens).view((1, -1)).to(clip_device);
-        let uncond_tokens = tokenizer.encode("").map_err(GenImageError::TokensEncode)?;
-        let uncond_tokens: Vec<i64> = uncond_tokens.into_iter().map(|x| x as i64).collect();
-        let uncond_tokens = Tensor::from_slice(&uncond_tokens)
-            .view((1, -1))
-            .to(clip_device);
 
         let no_grad_guard = tch::no_grad_guard();
 
-        log::debug!("[{}] Building the Clip transformer.", request.id);
-        let text_model = sd_config
-            .build_clip_transformer(&config.clip_weights_path.to_string_lossy(), clip_device)
-            .map_err(GenImageError::ClipTransformerBuild)?;
-
-        let text_embeddings = text_model.forward(&tokens);
-        let uncond_embeddings = text_model.forward(&uncond_tokens);
-        let text_embeddings = Tensor::cat(&[uncond_embeddings, text_embeddings], 0).to(unet_device);
-
-        log::debug!("[{}] Building the autoencoder", request.id);
-        let vae = sd_config
-            .build_vae(&config.vae_weights_path.to_string_lossy(), vae_device)
-            .map_err(GenImageError::VaeBuild)?;
+        let prompt = weights.tokenize_prompt(&request.prompt)?;
+        // the negative prompt replaces the empty unconditional prompt in classifier-free guidance
+        let negative_prompt =
+            weights.tokenize_prompt(request.negative_prompt.as_deref().unwrap_or_default())?;
+        for (name, prompt) in [("prompt", &prompt), ("negative prompt", &negative_prompt)] {
+            if prompt.truncated() > 0 {
+                log::warn!(
+                    "[{}] ignoring the last {} tokens of the {name}",
+                    request.id,
+                    prompt.truncated()
+                );
+            }
+        }
 
-        log::debug!("[{}] Building unet", request.id);
-        let unet = sd_config
-            .build_unet(&config.unet_weights_path.to_string_lossy(), unet_device, 4)
-            .map_err(GenImageError::UnetBuild)?;
+        // both embeddings need the same length to be batched together
+        let n_chunks = prompt.n_chunks().max(negative_prompt.n_chunks());
+        let text_embeddings = weights.encode_prompt(&prompt, n_chunks);
+        let uncond_embeddings = weights.encode_prompt(&negative_prompt, n_chunks);
+        let text_embeddings =
+            Tensor::cat(&[uncond_embeddings, text_embeddings], 0).to(weights.unet_device);
 
         drop(no_grad_guard);
 
         Ok(Self {
-            vae,
-            vae_device,
-            unet,
-            unet_device,
+            vae_device: weights.vae_device,
+            unet_device: weights.unet_device,
+            weights,
             scheduler,
             text_embeddings,
             tx_results,
@@ -169,8 +265,59 @@ impl BaseImageGenerator {
         self.processed_samples as i64 >= self.request.num_samples
     }
 
+    pub fn progress(&self) -> ImageProgress {
+        ImageProgress {
+            image_id: self.request.id.clone(),
+            queued: false,
+            sample: (self.sample_idx() + 1).min(self.request.num_samples),
+            num_samples: self.request.num_samples,
+            timestep: self.processed_timesteps,
+            n_steps: self.request.n_steps,
+            finished: self.is_finished(),
+            preview: None,
+        }
+    }
+
+    /// Approximates the image with a linear projection of the latents instead of running the
+    /// autoencoder. The preview has the resolution of the latents, an eighth of the image size,
+    /// and is returned as a PNG `data:` URL.
+    pub fn preview(&self, latents: &Tensor) -> Option<String> {
+        let _no_grad_guard = tch::no_grad_guard();
+        let factors = Tensor::from_slice(&LATENT_RGB_FACTORS)
+            .view((4, 3))
+            .to_device(latents.device());
+        let rgb = latents
+            .get(0)
+            .to_kind(Kind::Float)
+            .permute([1, 2, 0])
+            .matmul(&factors)
+            .permute([2, 0, 1]);
+        let image = (((rgb + 1.) / 2.).clamp(0., 1.) * 255.)
+            .to_kind(Kind::Uint8)
+            .to_device(Device::Cpu);
+
+        let path = self
+            .save_dir
+            .join(format!("{}-preview.png", self.request.id));
+        let preview = tch::vision::image::save(&image, &path)
+            .map_err(|e| e.to_string())
+            .and_then(|_| std::fs::read(&path).map_err(|e| e.to_string()));
+        // the file is only needed to encode the PNG
+        let _ = std::fs::remove_file(&path);
+        match preview {
+            Ok(preview) => {
+                let encoded = base64::engine::general_purpose::STANDARD.encode(preview);
+                Some(format!("data:image/png;base64,{encoded}"))
+            }
+            Err(e) => {
+                log::warn!("[{}] failed to generate preview - {e}", self.request.id);
+                None
+            }
+        }
+    }
+
     pub fn decode_latents(&self, latents: &Tensor) -> Tensor {
-        let decoded = self.vae.decode(&(latents / LATENTS_SCALE));
+        let decoded = self.weights.vae.decode(&(latents / LATENTS_SCALE));
         let decoded = (decoded / 2 + 0.5).clamp(0., 1.).to_device(Device::Cpu);
         (decoded * 255.).to_kind(Kind::Uint8)
     }
@@ -227,6 +374,12 @@ impl BaseImageGenerator {
                 self.request.num_samples
             );
         }
+        // multistep schedulers keep state between steps so each sample needs a fresh one
+        self.scheduler = Scheduler::new(
+            self.request.scheduler,
+            &self.sd_config,
+            self.request.n_steps,
+        );
         self.processed_timesteps = 0;
         self.processed_samples += 1;
     }
@@ -0,0 +1,389 @@
+//! Parsing of prompts with weighted terms and splitting of long prompts into chunks that fit the
+//! context window of CLIP.
+//!
+//! `(term)` increases the attention paid to `term` by 10% and `[term]` decreases it by the same
+//! amount, brackets can be nested. `(term:1.3)` sets an explicit weight. Brackets can be escaped
+//! with a backslash to be used literally, e.g. `\(`.
+
+use super::{GenImageError, ModelWeights};
+use crate::{config::StableDiffusionConfig, Result};
+
+use diffusers::transformers::clip;
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex, PoisonError, Weak},
+};
+
+/// Number of tokens CLIP encodes at once including the start and end tokens
+const CLIP_MAX_TOKENS: usize = 77;
+/// Number of prompt tokens in a single chunk
+const CHUNK_TOKENS: usize = CLIP_MAX_TOKENS - 2;
+/// Maximum number of chunks a prompt is split into, tokens past the limit are ignored
+const MAX_CHUNKS: usize = 3;
+/// Weight multiplier of a single level of `()` or `[]`
+const EMPHASIS: f64 = 1.1;
+
+/// Tokenizers in use, they are kept alive by the [`ModelWeights`] of the loaded models and freed
+/// together with the weights.
+static TOKENIZERS: Lazy<Mutex<HashMap<(PathBuf, String), Weak<clip::Tokenizer>>>> =
+    Lazy::new(Default::default);
+
+/// Returns the tokenizer for the model, a tokenizer is shared by the API and the worker while
+/// the model is loaded.
+pub fn tokenizer(config: &StableDiffusionConfig) -> Result<Arc<clip::Tokenizer>> {
+    let sd_config = ModelWeights::sd_config(&config.version, None, None);
+    let key = (
+        config.vocab_file.clone(),
+        config.version.as_ref().to_string(),
+    );
+
+    let mut tokenizers = TOKENIZERS.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(tokenizer) = tokenizers.get(&key).and_then(Weak::upgrade) {
+        return Ok(tokenizer);
+    }
+    let tokenizer = Arc::new(
+        clip::Tokenizer::create(&config.vocab_file, &sd_config.clip)
+            .map_err(GenImageError::ClipTokenizerInit)?,
+    );
+    tokenizers.retain(|_, tokenizer| tokenizer.strong_count() > 0);
+    tokenizers.insert(key, Arc::downgrade(&tokenizer));
+    Ok(tokenizer)
+}
+
+/// Returns warnings about parts of the prompts that will be ignored because they are too long.
+pub fn truncation_warnings(
+    config: &StableDiffusionConfig,
+    prompt: &str,
+    negative_prompt: Option<&str>,
+) -> Result<Vec<String>> {
+    let tokenizer = tokenizer(config)?;
+    let mut warnings = vec![];
+    for (name, prompt) in [
+        ("prompt", Some(prompt)),
+        ("negative prompt", negative_prompt),
+    ] {
+        let Some(prompt) = prompt else {
+            continue;
+        };
+        let truncated = WeightedPrompt::tokenize(&tokenizer, prompt)?.truncated;
+        if truncated > 0 {
+            warnings.push(format!(
+                "{name} is longer than {} tokens, the last {truncated} tokens will be ignored",
+                CHUNK_TOKENS * MAX_CHUNKS
+            ));
+        }
+    }
+    Ok(warnings)
+}
+
+/// A part of the prompt sharing the same weight.
+#[derive(Debug)]
+pub struct PromptSegment {
+    pub text: String,
+    pub weight: f64,
+}
+
+/// Splits the prompt into segments with their weights. Unmatched closing brackets are kept as
+/// text and brackets that are never closed apply to the rest of the prompt.
+pub fn parse(prompt: &str) -> Vec<PromptSegment> {
+    let chars = prompt.chars().collect::<Vec<_>>();
+    let mut segments: Vec<PromptSegment> = vec![];
+    // opening brackets with the index of the first segment they apply to
+    let mut open: Vec<(char, usize)> = vec![];
+    let mut text = String::new();
+
+    let flush = |text: &mut String, segments: &mut Vec<PromptSegment>| {
+        if !text.is_empty() {
+            segments.push(PromptSegment {
+                text: std::mem::take(text),
+                weight: 1.0,
+            });
+        }
+    };
+    let apply = |segments: &mut Vec<PromptSegment>, start: usize, weight: f64| {
+        for segment in &mut segments[start..] {
+            segment.weight *= weight;
+        }
+    };
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' if i + 1 < chars.len() => {
+                text.push(chars[i + 1]);
+                i += 1;
+            }
+            '(' | '[' => {
+                flush(&mut text, &mut segments);
+                open.push((c, segments.len()));
+            }
+            ':' if matches!(open.last(), Some(('(', _))) => {
+                let weight = chars[i + 1..]
+                    .iter()
+                    .position(|&c| c == ')')
+                    .and_then(|end| {
+                        let weight = chars[i + 1..i + 1 + end].iter().collect::<String>();
+                        weight
+                            .trim()
+                            .parse::<f64>()
+                            .ok()
+                            .map(|weight| (end, weight))
+                    });
+                match weight {
+                    Some((end, weight)) => {
+                        flush(&mut text, &mut segments);
+                        if let Some((_, start)) = open.pop() {
+                            apply(&mut segments, start, weight);
+                        }
+                        i += end + 1;
+                    }
+                    None => text.push(c),
+                }
+            }
+            ')' if matches!(open.last(), Some(('(', _))) => {
+                flush(&mut text, &mut segments);
+                if let Some((_, start)) = open.pop() {
+                    apply(&mut segments, start, EMPHASIS);
+                }
+            }
+            ']' if matches!(open.last(), Some(('[', _))) => {
+                flush(&mut text, &mut segments);
+                if let Some((_, start)) = open.pop() {
+                    apply(&mut segments, start, 1.0 / EMPHASIS);
+                }
+            }
+            c => text.push(c),
+        }
+        i += 1;
+    }
+    flush(&mut text, &mut segments);
+    while let Some((c, start)) = open.pop() {
+        let weight = if c == '(' { EMPHASIS } else { 1.0 / EMPHASIS };
+        apply(&mut segments, start, weight);
+    }
+
+    let mut merged: Vec<PromptSegment> = vec![];
+    for segment in segments {
+        match merged.last_mut() {
+            Some(last) if last.weight == segment.weight => last.text.push_str(&segment.text),
+            _ => merged.push(segment),
+        }
+    }
+    merged
+}
+
+/// Tokens of a prompt with the weight of each token.
+pub struct WeightedPrompt {
+    tokens: Vec<usize>,
+    weights: Vec<f64>,
+    /// Number of tokens ignored because the prompt is too long
+    truncated: usize,
+    start_token: usize,
+    end_token: usize,
+    pad_token: usize,
+}
+
+impl WeightedPrompt {
+    pub fn tokenize(tokenizer: &clip::Tokenizer, prompt: &str) -> Result<Self> {
+        // an empty prompt encodes to the start and end tokens followed by padding
+        let special = tokenizer
+            .encode_pad("", Some(CLIP_MAX_TOKENS))
+            .map_err(GenImageError::PromptEncode)?;
+
+        let mut tokens = vec![];
+        let mut weights = vec![];
+        for segment in parse(prompt) {
+            let segment_tokens = tokenizer
+                .encode_pad(&segment.text, None)
+                .map_err(GenImageError::PromptEncode)?;
+            // strip the start and end tokens
+            let segment_tokens = &segment_tokens[1..segment_tokens.len() - 1];
+            tokens.extend_from_slice(segment_tokens);
+            weights.extend(std::iter::repeat(segment.weight).take(segment_tokens.len()));
+        }
+
+        Ok(Self::new(
+            tokens,
+            weights,
+            [special[0], special[1], special[2]],
+        ))
+    }
+
+    /// Creates the prompt from the tokens with their weights truncating it to the maximum number
+    /// of chunks. `special` are the start, end and padding tokens.
+    fn new(mut tokens: Vec<usize>, mut weights: Vec<f64>, special: [usize; 3]) -> Self {
+        let truncated = tokens.len().saturating_sub(CHUNK_TOKENS * MAX_CHUNKS);
+        tokens.truncate(CHUNK_TOKENS * MAX_CHUNKS);
+        weights.truncate(CHUNK_TOKENS * MAX_CHUNKS);
+
+        let [start_token, end_token, pad_token] = special;
+        Self {
+            tokens,
+            weights,
+            truncated,
+            start_token,
+            end_token,
+            pad_token,
+        }
+    }
+
+    pub fn truncated(&self) -> usize {
+        self.truncated
+    }
+
+    /// Number of chunks needed to encode the prompt, at least one.
+    pub fn n_chunks(&self) -> usize {
+        self.tokens.len().div_ceil(CHUNK_TOKENS).max(1)
+    }
+
+    /// Returns the tokens and weights of the chunk padded to the context window of CLIP. Chunks
+    /// past the end of the prompt are empty.
+    pub fn chunk(&self, idx: usize) -> (Vec<i64>, Vec<f32>) {
+        let start = (idx * CHUNK_TOKENS).min(self.tokens.len());
+        let end = ((idx + 1) * CHUNK_TOKENS).min(self.tokens.len());
+
+        let mut tokens = Vec::with_capacity(CLIP_MAX_TOKENS);
+        tokens.push(self.start_token as i64);
+        tokens.extend(self.tokens[start..end].iter().map(|&token| token as i64));
+        tokens.push(self.end_token as i64);
+        tokens.resize(CLIP_MAX_TOKENS, self.pad_token as i64);
+
+        let mut weights = Vec::with_capacity(CLIP_MAX_TOKENS);
+        weights.push(1.0);
+        weights.extend(self.weights[start..end].iter().map(|&weight| weight as f32));
+        weights.resize(CLIP_MAX_TOKENS, 1.0);
+
+        (tokens, weights)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: usize = 49406;
+    const END: usize = 49407;
+    const PAD: usize = 0;
+
+    fn assert_segments(prompt: &str, expected: &[(&str, f64)]) {
+        let segments = parse(prompt);
+        assert_eq!(
+            segments.len(),
+            expected.len(),
+            "segments of `{prompt}`: {segments:?}"
+        );
+        for (segment, (text, weight)) in segments.iter().zip(expected) {
+            assert_eq!(segment.text, *text, "segments of `{prompt}`: {segments:?}");
+            assert!(
+                (segment.weight - weight).abs() < 1e-9,
+                "segments of `{prompt}`: {segments:?}"
+            );
+        }
+    }
+
+    fn weighted_prompt(n_tokens: usize) -> WeightedPrompt {
+        let tokens = (1..=n_tokens).collect();
+        let weights = vec![1.5; n_tokens];
+        WeightedPrompt::new(tokens, weights, [START, END, PAD])
+    }
+
+    #[test]
+    fn parses_plain_prompt() {
+        assert_segments("a photo of a cat", &[("a photo of a cat", 1.0)]);
+        assert!(parse("").is_empty());
+    }
+
+    #[test]
+    fn parses_nested_brackets() {
+        assert_segments("a (cat)", &[("a ", 1.0), ("cat", EMPHASIS)]);
+        assert_segments("((cat))", &[("cat", EMPHASIS * EMPHASIS)]);
+        assert_segments("[cat]", &[("cat", 1.0 / EMPHASIS)]);
+        assert_segments(
+            "a (red [fluffy] cat) sitting",
+            &[
+                ("a ", 1.0),
+                ("red ", EMPHASIS),
+                ("fluffy", 1.0),
+                (" cat", EMPHASIS),
+                (" sitting", 1.0),
+            ],
+        );
+    }
+
+    #[test]
+    fn parses_explicit_weights() {
+        assert_segments(
+            "a (cat:1.5) dog",
+            &[("a ", 1.0), ("cat", 1.5), (" dog", 1.0)],
+        );
+        assert_segments("((cat:0.5))", &[("cat", 0.5 * EMPHASIS)]);
+        // a weight that isn't a number is kept as text
+        assert_segments("(cat:big)", &[("cat:big", EMPHASIS)]);
+    }
+
+    #[test]
+    fn keeps_unbalanced_brackets() {
+        assert_segments("a cat)", &[("a cat)", 1.0)]);
+        assert_segments("a (cat", &[("a ", 1.0), ("cat", EMPHASIS)]);
+        assert_segments("[a cat)", &[("a cat)", 1.0 / EMPHASIS)]);
+        assert_segments("(a] cat)", &[("a] cat", EMPHASIS)]);
+    }
+
+    #[test]
+    fn parses_escaped_brackets() {
+        assert_segments(r"\(cat\)", &[("(cat)", 1.0)]);
+        assert_segments(r"(a \[cat\])", &[("a [cat]", EMPHASIS)]);
+        assert_segments(r"cat\", &[(r"cat\", 1.0)]);
+    }
+
+    #[test]
+    fn fits_single_chunk() {
+        let prompt = weighted_prompt(CHUNK_TOKENS);
+        assert_eq!(prompt.n_chunks(), 1);
+        assert_eq!(prompt.truncated(), 0);
+
+        let (tokens, weights) = prompt.chunk(0);
+        assert_eq!(tokens.len(), CLIP_MAX_TOKENS);
+        assert_eq!(weights.len(), CLIP_MAX_TOKENS);
+        assert_eq!(tokens[0], START as i64);
+        assert_eq!(tokens[1], 1);
+        assert_eq!(tokens[CHUNK_TOKENS], CHUNK_TOKENS as i64);
+        assert_eq!(tokens[CLIP_MAX_TOKENS - 1], END as i64);
+        assert_eq!(weights[0], 1.0);
+        assert_eq!(weights[1], 1.5);
+        assert_eq!(weights[CLIP_MAX_TOKENS - 1], 1.0);
+    }
+
+    #[test]
+    fn splits_chunks_at_token_limit() {
+        let prompt = weighted_prompt(CHUNK_TOKENS + 1);
+        assert_eq!(prompt.n_chunks(), 2);
+
+        let (tokens, weights) = prompt.chunk(1);
+        assert_eq!(tokens.len(), CLIP_MAX_TOKENS);
+        assert_eq!(tokens[0], START as i64);
+        assert_eq!(tokens[1], (CHUNK_TOKENS + 1) as i64);
+        assert_eq!(tokens[2], END as i64);
+        assert!(tokens[3..].iter().all(|&token| token == PAD as i64));
+        assert_eq!(weights[1], 1.5);
+        assert!(weights[2..].iter().all(|&weight| weight == 1.0));
+
+        // chunks past the end of the prompt are empty
+        let (tokens, _) = prompt.chunk(2);
+        assert_eq!(&tokens[..3], &[START as i64, END as i64, PAD as i64]);
+    }
+
+    #[test]
+    fn truncates_long_prompts() {
+        let prompt = weighted_prompt(CHUNK_TOKENS * MAX_CHUNKS + 10);
+        assert_eq!(prompt.n_chunks(), MAX_CHUNKS);
+        assert_eq!(prompt.truncated(), 10);
+
+        let empty = weighted_prompt(0);
+        assert_eq!(empty.n_chunks(), 1);
+        assert_eq!(empty.truncated(), 0);
+    }
+}
@@ -0,0 +1,136 @@
+use airtifex_core::image::ImageScheduler;
+
+use diffusers::{
+    pipelines::stable_diffusion::StableDiffusionConfig,
+    schedulers::{
+        ddim::DDIMScheduler,
+        dpmsolver_multistep::{DPMSolverMultistepScheduler, DPMSolverMultistepSchedulerConfig},
+        euler_ancestral_discrete::{
+            EulerAncestralDiscreteScheduler, EulerAncestralDiscreteSchedulerConfig,
+        },
+        euler_discrete::{EulerDiscreteScheduler, EulerDiscreteSchedulerConfig},
+        pndm::{PNDMScheduler, PNDMSchedulerConfig},
+    },
+};
+use tch::Tensor;
+
+/// A diffusion scheduler selected by the request. Some schedulers use continuous timesteps so
+/// all timesteps are passed around as `f64`.
+pub enum Scheduler {
+    Ddim(DDIMScheduler),
+    Euler(EulerDiscreteScheduler),
+    EulerAncestral(EulerAncestralDiscreteScheduler),
+    DpmSolverPp(DPMSolverMultistepScheduler),
+    Pndm(PNDMScheduler),
+}
+
+impl Scheduler {
+    /// Builds the scheduler with the noise schedule the model was trained with.
+    pub fn new(
+        scheduler: ImageScheduler,
+        sd_config: &StableDiffusionConfig,
+        n_steps: usize,
+    ) -> Self {
+        let base = &sd_config.scheduler;
+        match scheduler {
+            ImageScheduler::Ddim => Self::Ddim(sd_config.build_scheduler(n_steps)),
+            ImageScheduler::Euler => Self::Euler(EulerDiscreteScheduler::new(
+                n_steps,
+                EulerDiscreteSchedulerConfig {
+                    beta_start: base.beta_start,
+                    beta_end: base.beta_end,
+                    beta_schedule: base.beta_schedule,
+                    train_timesteps: base.train_timesteps,
+                    prediction_type: base.prediction_type,
+                    ..Default::default()
+                },
+            )),
+            ImageScheduler::EulerAncestral => {
+                Self::EulerAncestral(EulerAncestralDiscreteScheduler::new(
+                    n_steps,
+                    EulerAncestralDiscreteSchedulerConfig {
+                        beta_start: base.beta_start,
+                        beta_end: base.beta_end,
+                        beta_schedule: base.beta_schedule,
+                        train_timesteps: base.train_timesteps,
+                        prediction_type: base.prediction_type,
+                        ..Default::default()
+                    },
+                ))
+            }
+            ImageScheduler::DpmSolverPp => Self::DpmSolverPp(DPMSolverMultistepScheduler::new(
+                n_steps,
+                DPMSolverMultistepSchedulerConfig {
+                    beta_start: base.beta_start,
+                    beta_end: base.beta_end,
+                    beta_schedule: base.beta_schedule,
+                    train_timesteps: base.train_timesteps,
+                    prediction_type: base.prediction_type,
+                    ..Default::default()
+                },
+            )),
+            ImageScheduler::Pndm => Self::Pndm(PNDMScheduler::new(
+                n_steps,
+                PNDMSchedulerConfig {
+                    beta_start: base.beta_start,
+                    beta_end: base.beta_end,
+                    beta_schedule: base.beta_schedule,
+                    train_timesteps: base.train_timesteps,
+                    prediction_type: base.prediction_type,
+                    ..Default::default()
+                },
+            )),
+        }
+    }
+
+    /// Returns the timestep of the `n`-th denoising step.
+    pub fn timestep(&self, n: usize) -> Option<f64> {
+        match self {
+            Self::Ddim(s) => s.timesteps().get(n).map(|&t| t as f64),
+            Self::Euler(s) => s.timesteps().get(n).copied(),
+            Self::EulerAncestral(s) => s.timesteps().get(n).copied(),
+            Self::DpmSolverPp(s) => s.timesteps().get(n).map(|&t| t as f64),
+            Self::Pndm(s) => s.timesteps().get(n).map(|&t| t as f64),
+        }
+    }
+
+    pub fn init_noise_sigma(&self) -> f64 {
+        match self {
+            Self::Ddim(s) => s.init_noise_sigma(),
+            Self::Euler(s) => s.init_noise_sigma(),
+            Self::EulerAncestral(s) => s.init_noise_sigma(),
+            Self::DpmSolverPp(s) => s.init_noise_sigma(),
+            Self::Pndm(s) => s.init_noise_sigma(),
+        }
+    }
+
+    pub fn scale_model_input(&self, sample: Tensor, timestep: f64) -> Tensor {
+        match self {
+            Self::Ddim(s) => s.scale_model_input(sample, timestep as usize),
+            Self::Euler(s) => s.scale_model_input(sample, timestep),
+            Self::EulerAncestral(s) => s.scale_model_input(sample, timestep),
+            Self::DpmSolverPp(s) => s.scale_model_input(sample, timestep as usize),
+            Self::Pndm(s) => s.scale_model_input(sample, timestep as usize),
+        }
+    }
+
+    pub fn step(&mut self, model_output: &Tensor, timestep: f64, sample: &Tensor) -> Tensor {
+        match self {
+            Self::Ddim(s) => s.step(model_output, timestep as usize, sample),
+            Self::Euler(s) => s.step(model_output, timestep, sample),
+            Self::EulerAncestral(s) => s.step(model_output, timestep, sample),
+            Self::DpmSolverPp(s) => s.step(model_output, timestep as usize, sample),
+            Self::Pndm(s) => s.step(model_output, timestep as usize, sample),
+        }
+    }
+
+    pub fn add_noise(&self, original: &Tensor, noise: Tensor, timestep: f64) -> Tensor {
+        match self {
+            Self::Ddim(s) => s.add_noise(original, noise, timestep as usize),
+            Self::Euler(s) => s.add_noise(original, noise, timestep),
+            Self::EulerAncestral(s) => s.add_noise(original, noise, timestep),
+            Self::DpmSolverPp(s) => s.add_noise(original, noise, timestep as usize),
+            Self::Pndm(s) => s.add_noise(original, noise, timestep as usize),
+        }
+    }
+}
@@ -1,15 +1,14 @@
 use crate::{
-    config::StableDiffusionConfig,
     gen::image::{
-        sd::generator::{BaseImageGenerator, ImageGenerator},
+        sd::generator::{BaseImageGenerator, ImageGenerator, ModelWeights},
         BaseImageData, SaveImageFsResult,
     },
     Result,
 };
 
 use flume::Sender;
-use std::path::Path;
-use tch::{Device, Kind, Tensor};
+use std::{path::Path, rc::Rc};
+use tch::{Kind, Tensor};
 
 pub struct TextToImageGenerator {
     base_generator: BaseImageGenerator,
@@ -19,23 +18,11 @@ pub struct TextToImageGenerator {
 impl TextToImageGenerator {
     pub fn new(
         request: BaseImageData,
-        config: &StableDiffusionConfig,
-        clip_device: Device,
-        unet_device: Device,
-        vae_device: Device,
+        weights: Rc<ModelWeights>,
         tx_results: Sender<SaveImageFsResult>,
         save_dir: impl AsRef<Path>,
     ) -> Result<Self> {
-        let base_generator = BaseImageGenerator::new(
-            request,
-            config,
-            clip_device,
-            unet_device,
-            vae_device,
-            tx_results,
-            save_dir,
-            1,
-        )?;
+        let base_generator = BaseImageGenerator::new(request, weights, tx_results, save_dir, 1)?;
 
         let mut g = Self {
             base_generator,
@@ -75,6 +62,10 @@ impl ImageGenerator for TextToImageGenerator {
         &self.base_generator
     }
 
+    fn latents(&self) -> &Tensor {
+        &self.latents
+    }
+
     fn process_next_timestep(&mut self) -> bool {
         if self.is_finished() {
             return false;
@@ -97,7 +88,7 @@ impl ImageGenerator for TextToImageGenerator {
             self.init_latents();
         }
 
-        let Some(&timestep) = self.base_generator.scheduler.timesteps().get(self.base_generator.processed_timesteps) else {
+        let Some(timestep) = self.base_generator.scheduler.timestep(self.base_generator.processed_timesteps) else {
             return false
         };
 
@@ -107,9 +98,9 @@ impl ImageGenerator for TextToImageGenerator {
             .base_generator
             .scheduler
             .scale_model_input(latent_model_input, timestep);
-        let noise_pred = self.base_generator.unet.forward(
+        let noise_pred = self.base_generator.weights.unet.forward(
             &latent_model_input,
-            timestep as f64,
+            timestep,
             &self.base_generator.text_embeddings,
         );
         let noise_pred = noise_pred.chunk(2, 0);
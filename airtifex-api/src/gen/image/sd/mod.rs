@@ -1,113 +1,142 @@
 mod generator;
 
-pub use generator::GenImageError;
+pub use generator::{prompt, GenImageError};
 
 use crate::{
     config::StableDiffusionConfig,
-    gen::image::{GenerateImageRequest, SaveImageFsResult},
-    models::{image::Image, image_sample::ImageSample},
-    queue,
+    gen::{
+        image::{
+            progress::ImageProgressRegistry, GenerateImageRequest, ImageModelHandle,
+            SaveImageFsResult,
+        },
+        residency::{self, Residency},
+        ModelHandle, ModelName, ModelState, SharedModelState,
+    },
+    id::Uuid,
+    metrics::ImageMetrics,
+    models::{image::Image, image_sample::ImageSample, usage::Usage},
+    queue::{Queue, QueueItem, QueueStats},
+    storage::{self, SharedStorage},
 };
 use generator::{
     img2img::ImageToImageGenerator, inpaint::InpaintImageGenerator, txt2img::TextToImageGenerator,
+    ModelWeights,
 };
 
-use flume::Sender;
-use std::sync::Arc;
+use airtifex_core::image::ImageStatus;
+use std::{panic::AssertUnwindSafe, rc::Rc, sync::Arc, time::Instant};
 use tokio::runtime::Runtime;
 
 use self::generator::ImageGenerator;
 
+struct RunningSession {
+    started: Instant,
+    id: String,
+    user: String,
+    n_steps: usize,
+    num_samples: i64,
+    /// Timesteps processed since the session started, used to schedule previews
+    steps: usize,
+    generator: Box<dyn ImageGenerator>,
+}
+
 pub fn initialize(
+    model: ModelName,
     db: Arc<crate::DbPool>,
     config: StableDiffusionConfig,
     runtime: Arc<Runtime>,
-) -> Sender<GenerateImageRequest> {
-    let request_queue = queue::empty_queue();
-    let save_data_queue = queue::empty_queue();
+    progress: ImageProgressRegistry,
+    storage: SharedStorage,
+    residency: Residency,
+) -> ImageModelHandle {
+    let request_queue = Queue::new();
+    let stats = Arc::new(QueueStats::new(config.max_image_gen_sessions));
+    let metrics = ImageMetrics::new(&model);
 
+    let state = SharedModelState::default();
     let queue = request_queue.clone();
-    let tx_request = queue::start_queue_thread::<GenerateImageRequest>(queue);
-
-    let queue = save_data_queue.clone();
-    let tx_results = queue::start_queue_thread::<SaveImageFsResult>(queue);
-
-    // Create thread responsible for saving images to database
-    std::thread::spawn(move || loop {
-        let save_data_request = save_data_queue
-            .write()
-            .ok()
-            .and_then(|mut queue| queue.pop_front());
-        if let Some(save_data_request) = save_data_request {
+    let resident = residency.register(
+        model.clone(),
+        residency::weights_size([
+            config.clip_weights_path.as_path(),
+            config.vae_weights_path.as_path(),
+            config.unet_weights_path.as_path(),
+        ]),
+        move || queue.notify(),
+    );
+    let handle = ModelHandle {
+        config: config.clone(),
+        queue: request_queue.clone(),
+        stats: stats.clone(),
+        state: state.clone(),
+        resident: resident.clone(),
+    };
+
+    let (tx_results, rx_results) = flume::unbounded::<SaveImageFsResult>();
+    let worker_db = db.clone();
+    let worker_runtime = runtime.clone();
+
+    // Create thread responsible for saving images to the storage
+    std::thread::spawn(move || {
+        while let Ok(save_data_request) = rx_results.recv() {
             let db = db.clone();
-            if let Ok(data) = std::fs::read(&save_data_request.path) {
-                let thumbnail = std::fs::read(&save_data_request.thumbnail);
-                // TODO: verify the result of this
-                runtime.spawn(async move {
-                    log::debug!(
-                        "[{}][{}] saving image to DB",
-                        save_data_request.id,
-                        save_data_request.n_sample
-                    );
-                    let entry = ImageSample::new(
-                        save_data_request.id.parse().unwrap(),
-                        save_data_request.n_sample,
-                        data,
-                    );
-                    if let Err(e) = entry.create(&db).await {
-                        log::error!(
-                            "[{}][{}] failed to save image data- {e}",
-                            save_data_request.id,
-                            save_data_request.n_sample
-                        )
+            let storage = storage.clone();
+            let id = save_data_request.id.clone();
+            let n_sample = save_data_request.n_sample;
+            let Ok(image_id) = id.parse::<Uuid>() else {
+                log::error!("[{id}][{n_sample}] invalid image id");
+                continue;
+            };
+            let data = std::fs::read(&save_data_request.path);
+            let thumbnail = std::fs::read(&save_data_request.thumbnail);
+            runtime.spawn(async move {
+                log::debug!("[{id}][{n_sample}] saving image to storage");
+                let saved = async {
+                    let key = storage::sample_key(&image_id, n_sample);
+                    let data = data.map_err(|e| format!("failed to read generated image - {e}"))?;
+                    storage.put(&key, data).await.map_err(|e| e.to_string())?;
+                    ImageSample::new(image_id, n_sample, key)
+                        .create(&db)
+                        .await
+                        .map_err(|e| e.to_string())
+                };
+                if let Err(e) = saved.await {
+                    log::error!("[{id}][{n_sample}] failed to save image data - {e}");
+                    let error = format!("failed to save sample {n_sample} - {e}");
+                    if let Err(e) = Image::update_failed(&db, &image_id, &error).await {
+                        log::error!("[{id}] failed to update image status - {e}")
                     }
+                    return;
+                }
 
-                    // save thumbnail from first sample
-                    if save_data_request.n_sample == 1 {
-                        if let Ok(id) = save_data_request.id.parse() {
-                            match thumbnail {
-                                Ok(thumbnail) => {
-                                    if let Err(e) =
-                                        Image::update_thumbnail(&db, &id, &thumbnail).await
-                                    {
-                                        log::error!(
-                                            "[{}][{}] failed to update image thumbnail- {e}",
-                                            save_data_request.id,
-                                            save_data_request.n_sample
-                                        )
-                                    }
-                                }
-                                Err(e) => {
-                                    log::error!(
-                                        "[{}][{}] failed to read thumbnail- {e}",
-                                        save_data_request.id,
-                                        save_data_request.n_sample
-                                    )
-                                }
-                            }
-                        }
+                // save thumbnail from first sample
+                if n_sample == 1 {
+                    let key = storage::thumbnail_key(&image_id);
+                    let saved = match thumbnail {
+                        Ok(thumbnail) => match storage.put(&key, thumbnail).await {
+                            Ok(()) => Image::update_thumbnail(&db, &image_id, &key)
+                                .await
+                                .map_err(|e| e.to_string()),
+                            Err(e) => Err(e.to_string()),
+                        },
+                        Err(e) => Err(format!("failed to read thumbnail - {e}")),
+                    };
+                    if let Err(e) = saved {
+                        log::error!("[{id}][{n_sample}] failed to update image thumbnail - {e}")
                     }
-                    // update image processing status
-                    if save_data_request.is_last {
-                        log::debug!(
-                            "[{}][{}] updating image processing to false",
-                            save_data_request.id,
-                            save_data_request.n_sample
-                        );
-                        if let Ok(id) = save_data_request.id.parse() {
-                            if let Err(e) = Image::update_is_processing(&db, &id, false).await {
-                                log::error!(
-                                    "[{}][{}] failed to update image processing status - {e}",
-                                    save_data_request.id,
-                                    save_data_request.n_sample
-                                )
-                            }
-                        }
+                }
+                // update image processing status
+                if save_data_request.is_last {
+                    log::debug!("[{id}][{n_sample}] marking image as completed");
+                    if let Err(e) =
+                        Image::update_status(&db, &image_id, ImageStatus::Completed).await
+                    {
+                        log::error!(
+                            "[{id}][{n_sample}] failed to update image processing status - {e}"
+                        )
                     }
-                });
-            }
-        } else {
-            std::thread::sleep(std::time::Duration::from_millis(100));
+                }
+            });
         }
     });
 
@@ -132,116 +161,283 @@ pub fn initialize(
         let vae_device = device_setup.get("vae");
         let unet_device = device_setup.get("unet");
 
-        let tmp = tempfile::TempDir::new().expect("temporary directory for images");
+        let set_status = |id: &str, status: ImageStatus| {
+            let Ok(id) = id.parse() else {
+                return;
+            };
+            let db = worker_db.clone();
+            worker_runtime.spawn(async move {
+                if let Err(e) = Image::update_status(&db, &id, status).await {
+                    log::error!("[{id}] failed to update image status - {e}")
+                }
+            });
+        };
+        let set_failed = |id: &str, error: String| {
+            progress.finish(id);
+            let Ok(id) = id.parse() else {
+                return;
+            };
+            let db = worker_db.clone();
+            worker_runtime.spawn(async move {
+                if let Err(e) = Image::update_failed(&db, &id, &error).await {
+                    log::error!("[{id}] failed to update image status - {e}")
+                }
+            });
+        };
+
+        let fail_queued = |e: &str| {
+            let error = format!("image model {model} is unavailable - {e}");
+            for request in request_queue.remove_where(|_| true) {
+                log::error!("[{}] dropping request of unavailable model", request.id());
+                set_failed(request.id(), error.clone());
+            }
+        };
+        let fail = |e: String| {
+            log::error!("failed to initialize image model {model} - {e}");
+            resident.unregister();
+            request_queue.close();
+            fail_queued(&e);
+            state.set(ModelState::Failed(e));
+        };
+
+        let tmp = match tempfile::TempDir::new() {
+            Ok(tmp) => tmp,
+            Err(e) => {
+                fail(e.to_string());
+                return;
+            }
+        };
+        let load = || {
+            // a worker stopped while waiting for memory has nothing left to load the model for
+            if !resident.acquire(|| request_queue.is_finished()) {
+                return None;
+            }
+            state.set(ModelState::Loading);
+            log::info!("loading weights of image model {model}");
+            // Loading a corrupted weights file may panic, treat it the same as a failed load
+            let weights = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                ModelWeights::load(&config, clip_device, vae_device, unet_device)
+            }))
+            .unwrap_or_else(|_| Err(crate::Error::ModelPanicked));
+            match weights {
+                Ok(weights) => {
+                    resident.loaded();
+                    state.set(ModelState::Ready);
+                    Some(Rc::new(weights))
+                }
+                // the worker stays alive and tries to load the weights again on the next request
+                Err(e) => {
+                    log::error!("failed to load weights of image model {model} - {e}");
+                    resident.release();
+                    let e = e.to_string();
+                    fail_queued(&e);
+                    state.set(ModelState::Failed(e));
+                    None
+                }
+            }
+        };
+
+        let mut weights = if resident.is_lazy() {
+            state.set(ModelState::Unloaded);
+            None
+        } else {
+            load()
+        };
 
         let mut running_sessions = Vec::new();
 
         loop {
-            let free_spots = config.max_image_gen_sessions - running_sessions.len();
-
-            if free_spots > 0 {
-                if let Ok(mut queue) = request_queue.try_write() {
-                    'inner: while let Some(request) = queue.pop_front() {
-                        let id = request.id().to_string();
-                        let generator = match request {
-                            GenerateImageRequest::ImageToImage(data) => {
-                                if config.feature_image_to_image {
-                                    match ImageToImageGenerator::new(
-                                        data,
-                                        &config,
-                                        clip_device,
-                                        unet_device,
-                                        vae_device,
-                                        tx_results.clone(),
-                                        tmp.path(),
-                                    ) {
-                                        Ok(generator) => {
-                                            Box::new(generator) as Box<dyn ImageGenerator>
-                                        }
-                                        Err(e) => {
-                                            log::error!("[{id}] {e}");
-                                            continue 'inner;
-                                        }
-                                    }
-                                } else {
-                                    log::error!("[{id}] feature image-to-image is disabled");
-                                    continue 'inner;
-                                    // # TODO return an error somehow
-                                }
-                            }
-                            GenerateImageRequest::Inpaint(data) => {
-                                if config.feature_inpaint {
-                                    match InpaintImageGenerator::new(
-                                        data,
-                                        &config,
-                                        clip_device,
-                                        unet_device,
-                                        vae_device,
-                                        tx_results.clone(),
-                                        tmp.path(),
-                                    ) {
-                                        Ok(generator) => {
-                                            Box::new(generator) as Box<dyn ImageGenerator>
-                                        }
-                                        Err(e) => {
-                                            log::error!("[{id}] {e}");
-                                            continue;
-                                        }
-                                    }
-                                } else {
-                                    log::error!(
-                                        "[{id}] feature inpaint is disabled for this model"
-                                    );
-                                    continue 'inner;
-                                    // # TODO return an error somehow
-                                }
-                            }
-                            GenerateImageRequest::TextToImage(data) => {
-                                if config.feature_text_to_image {
-                                    match TextToImageGenerator::new(
-                                        data,
-                                        &config,
-                                        clip_device,
-                                        unet_device,
-                                        vae_device,
-                                        tx_results.clone(),
-                                        tmp.path(),
-                                    ) {
-                                        Ok(generator) => {
-                                            Box::new(generator) as Box<dyn ImageGenerator>
-                                        }
-                                        Err(e) => {
-                                            log::error!("[{id}] {e}");
-                                            continue;
-                                        }
-                                    }
-                                } else {
-                                    log::error!(
-                                        "[{id}] feature text-to-image is disabled for this model"
-                                    );
-                                    continue 'inner;
-                                    // # TODO return an error somehow
-                                }
-                            }
-                        };
-                        running_sessions.push(generator);
+            if running_sessions.is_empty() {
+                resident.set_busy(false);
+                if weights.is_some() && resident.should_unload() {
+                    log::info!("unloading image model {model}");
+                    weights = None;
+                    resident.release();
+                    state.set(ModelState::Unloaded);
+                }
 
-                        if free_spots == 0 {
-                            break 'inner;
-                        }
-                    }
+                // Sleep until a request arrives or the model should be unloaded
+                if !request_queue.wait(resident.idle_timeout()) {
+                    log::info!("request queue closed, stopping image generation thread");
+                    break;
+                }
+                if request_queue.is_empty() {
+                    continue;
                 }
             }
 
-            for session in &mut running_sessions {
-                session.process_next_timestep();
+            if weights.is_none() {
+                weights = load();
+            }
+            let Some(model_weights) = &weights else {
+                continue;
+            };
+            resident.set_busy(true);
+
+            // A model that should be unloaded finishes the running sessions without starting new
+            // ones
+            let free_spots = if resident.is_evicting() {
+                0
+            } else {
+                config.max_image_gen_sessions - running_sessions.len()
+            };
+            let new_requests = request_queue.pop_batch(
+                free_spots,
+                running_sessions.iter().map(|s| s.user.as_str()),
+                config.max_sessions_per_user,
+            );
+
+            metrics.queue_depth.set(request_queue.len() as i64);
+            stats.set_running(running_sessions.len() + new_requests.len());
+            for request in new_requests {
+                let id = request.id().to_string();
+                let user = request.owner().to_string();
+                let n_steps = request.data().n_steps;
+                let num_samples = request.data().num_samples;
+                let generator = match request {
+                    GenerateImageRequest::ImageToImage(_) if !config.feature_image_to_image => {
+                        Err("feature image-to-image is disabled for this model".to_string())
+                    }
+                    GenerateImageRequest::Inpaint(_) if !config.feature_inpaint => {
+                        Err("feature inpaint is disabled for this model".to_string())
+                    }
+                    GenerateImageRequest::TextToImage(_) if !config.feature_text_to_image => {
+                        Err("feature text-to-image is disabled for this model".to_string())
+                    }
+                    GenerateImageRequest::ImageToImage(data) => ImageToImageGenerator::new(
+                        data,
+                        model_weights.clone(),
+                        tx_results.clone(),
+                        tmp.path(),
+                    )
+                    .map(|generator| Box::new(generator) as Box<dyn ImageGenerator>)
+                    .map_err(|e| e.to_string()),
+                    GenerateImageRequest::Inpaint(data) => InpaintImageGenerator::new(
+                        data,
+                        model_weights.clone(),
+                        tx_results.clone(),
+                        tmp.path(),
+                    )
+                    .map(|generator| Box::new(generator) as Box<dyn ImageGenerator>)
+                    .map_err(|e| e.to_string()),
+                    GenerateImageRequest::TextToImage(data) => TextToImageGenerator::new(
+                        data,
+                        model_weights.clone(),
+                        tx_results.clone(),
+                        tmp.path(),
+                    )
+                    .map(|generator| Box::new(generator) as Box<dyn ImageGenerator>)
+                    .map_err(|e| e.to_string()),
+                };
+                let generator = match generator {
+                    Ok(generator) => generator,
+                    Err(e) => {
+                        log::error!("[{id}] {e}");
+                        set_failed(&id, e);
+                        continue;
+                    }
+                };
+                progress.update(generator.progress(false));
+                set_status(&id, ImageStatus::Running);
+                running_sessions.push(RunningSession {
+                    started: Instant::now(),
+                    id,
+                    user,
+                    n_steps,
+                    num_samples,
+                    steps: 0,
+                    generator,
+                });
             }
 
-            running_sessions.retain(|s| !s.is_finished());
+            // Cancelled sessions stop between timesteps keeping the samples they already saved
+            running_sessions.retain(|s| {
+                if !progress.is_cancelled(&s.id) {
+                    return true;
+                }
+                log::info!("[{}] image generation cancelled", s.id);
+                progress.finish(&s.id);
+                set_status(&s.id, ImageStatus::Cancelled);
+                if let Ok(id) = s.id.parse() {
+                    let db = worker_db.clone();
+                    let usage = Usage::image(
+                        id,
+                        s.user.clone(),
+                        model.clone(),
+                        s.n_steps,
+                        s.generator.base_generator().sample_idx(),
+                        s.started.elapsed(),
+                    );
+                    worker_runtime.spawn(async move {
+                        if let Err(e) = usage.create(&db).await {
+                            log::error!("[{id}] failed to record usage - {e}")
+                        }
+                    });
+                }
+                false
+            });
+
+            for session in &mut running_sessions {
+                let start = Instant::now();
+                session.generator.process_next_timestep();
+                metrics.timesteps.inc();
+                metrics
+                    .timestep_duration
+                    .observe(start.elapsed().as_secs_f64());
 
-            std::thread::sleep(std::time::Duration::from_millis(10));
+                session.steps += 1;
+                // previews are only generated for jobs someone is following
+                let with_preview = config.preview_interval.map_or(false, |interval| {
+                    interval > 0 && session.steps % interval == 0
+                }) && progress.has_subscribers(&session.id);
+                progress.update(session.generator.progress(with_preview));
+            }
+
+            running_sessions.retain(|s| {
+                if s.generator.is_finished() {
+                    progress.finish(&s.id);
+                    stats.record_job(s.started.elapsed());
+                    if let Ok(id) = s.id.parse() {
+                        let db = worker_db.clone();
+                        let usage = Usage::image(
+                            id,
+                            s.user.clone(),
+                            model.clone(),
+                            s.n_steps,
+                            s.num_samples,
+                            s.started.elapsed(),
+                        );
+                        worker_runtime.spawn(async move {
+                            if let Err(e) = usage.create(&db).await {
+                                log::error!("[{id}] failed to record usage - {e}")
+                            }
+                        });
+                    }
+                }
+                !s.generator.is_finished()
+            });
+            stats.set_running(running_sessions.len());
+            metrics.active_sessions.set(running_sessions.len() as i64);
         }
+        drop(weights);
+        resident.unregister();
     });
 
-    tx_request
+    handle
+}
+
+/// Verifies that all files required to generate images exist. The weights may only be loaded
+/// once the first request arrives so a missing file would otherwise only show up then.
+pub fn validate_files(config: &StableDiffusionConfig) -> crate::Result<()> {
+    for path in [
+        &config.clip_weights_path,
+        &config.vae_weights_path,
+        &config.unet_weights_path,
+        &config.vocab_file,
+    ] {
+        if !path.is_file() {
+            return Err(crate::Error::ModelFileMissing(path.clone()));
+        }
+    }
+    Ok(())
 }
@@ -1,24 +1,44 @@
 use crate::{
     config::{LlmConfig, LlmType},
-    gen::ModelName,
+    gen::{
+        llm::{CancellationToken, InferenceRegistry, LlmHandle},
+        residency::{self, Residency},
+        ModelHandle, ModelName, ModelState, SharedModelState,
+    },
     id::Uuid,
-    models::{chat_entry::ChatEntry, prompt::Prompt},
-    queue,
+    metrics::LlmMetrics,
+    models::{
+        chat::Chat,
+        chat_entry::ChatEntry,
+        prompt::Prompt,
+        usage::Usage,
+        usage_counter::{UsageCounter, UsageKind},
+    },
+    queue::{Priority, Queue, QueueItem, QueueStats},
+};
+use airtifex_core::llm::{
+    ChatEntryType, DoneEvent, FinishReason, InferenceEvent, InferenceSettings, InferenceUsage,
+    TokenEvent,
 };
-use airtifex_core::llm::{ChatEntryType, ChatStreamResult, InferenceSettings};
 
 use llm::{
     InferenceError, InferenceParameters, InferenceSession, InferenceSessionConfig, LoadProgress,
     Model, ModelKVMemoryType, TokenBias,
 };
 use rand::{rngs::ThreadRng, thread_rng};
-use std::{collections::VecDeque, sync::Arc};
+use std::{
+    collections::VecDeque,
+    panic::AssertUnwindSafe,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::runtime::Runtime;
 
 use flume::{unbounded, Receiver, Sender};
 
 const ANSWER_PREFIX: &str = "Assistant: ";
 const USER_PREFIX: &str = "User: ";
+const CONVERSATION_SECTION: &str = "### Conversation:";
 const CONVERSATION_PROMPT: &str = r#"Your name is Assistant and you are a helpful virtual assistant.
 As Assistant, you fulfill users request in the most effective way and your answer is never empty.
 Below is a dialog between a user and you.
@@ -32,18 +52,52 @@ Write a response to the request in the '### Request:' section that appropriately
 
 ### Response:"#;
 
+/// Returns the default conversation prompt with the assistant preamble replaced by `system`.
+pub fn conversation_prompt_with_system(system: &str) -> String {
+    let (_, template) = CONVERSATION_PROMPT
+        .split_once(CONVERSATION_SECTION)
+        .unwrap_or_default();
+    format!("{system}\n\n{CONVERSATION_SECTION}{template}")
+}
+
 #[derive(Debug)]
 pub struct ChatData {
     pub conversation_id: Uuid,
+    /// Entries of the conversation, the prompt continues the branch ending with the last entry
     pub history: Vec<ChatEntry>,
+    /// Set when regenerating the reply to a prompt that was already saved as this entry
+    pub prompt_entry_id: Option<Uuid>,
+    /// Active entry of the chat when the request was made, the reply only becomes active if it
+    /// didn't change in the meantime
+    pub active_entry_id: Option<Uuid>,
+}
+
+impl ChatData {
+    /// Returns the entries of the branch ending with the last entry of the history, oldest first.
+    pub fn branch(&self) -> Vec<&ChatEntry> {
+        let mut branch = vec![];
+        let mut next = self.history.last();
+        while let Some(entry) = next {
+            branch.push(entry);
+            next = entry
+                .parent_entry_id
+                .and_then(|parent| self.history.iter().find(|e| e.entry_id == parent));
+        }
+        branch.reverse();
+        branch
+    }
 }
 
 #[derive(Debug)]
 pub struct InferenceRequest {
-    /// The channel to send the tokens to.
-    pub tx_tokens: Sender<ChatStreamResult>,
+    /// ID under which this request is tracked in the inference registry.
+    pub id: Uuid,
+    pub cancellation: CancellationToken,
+    /// The channel to send the inference events to.
+    pub tx_tokens: Sender<InferenceEvent>,
 
     pub user: String,
+    pub priority: Priority,
     pub save: bool,
     pub chat_data: Option<ChatData>,
     pub prompt: String,
@@ -51,23 +105,73 @@ pub struct InferenceRequest {
     pub play_back_tokens: bool,
 }
 
+impl InferenceRequest {
+    /// Notifies the receiver that the request was cancelled before it started processing.
+    pub fn cancel_queued(&self) {
+        log::debug!("[{}] dropping cancelled request from queue", self.id);
+        let _ = self.tx_tokens.send(InferenceEvent::Done(DoneEvent {
+            finish_reason: FinishReason::Cancelled,
+            usage: InferenceUsage::default(),
+        }));
+    }
+
+    /// Ends the stream of a request that failed before any inference was done.
+    pub fn fail_queued(&self, error: impl std::fmt::Display) {
+        let _ = self.tx_tokens.send(InferenceEvent::error(error));
+        let _ = self.tx_tokens.send(InferenceEvent::Done(DoneEvent {
+            finish_reason: FinishReason::Error,
+            usage: InferenceUsage::default(),
+        }));
+    }
+}
+
+impl QueueItem for InferenceRequest {
+    fn item_id(&self) -> String {
+        self.id.to_string()
+    }
+
+    fn owner(&self) -> &str {
+        &self.user
+    }
+
+    fn priority(&self) -> Priority {
+        self.priority
+    }
+}
+
 #[derive(Debug)]
 pub enum SaveDataRequest {
     Chat {
         conversation_id: Uuid,
+        /// The entry the prompt follows
+        parent_entry_id: Option<Uuid>,
+        /// The already saved prompt of a regenerated reply
+        prompt_entry_id: Option<Uuid>,
+        /// The active entry of the chat when the request was made
+        active_entry_id: Option<Uuid>,
         input: String,
         output: String,
     },
     Prompt {
+        id: Uuid,
         input: String,
         output: String,
         username: String,
         settings: InferenceSettings,
     },
+    Usage {
+        id: Uuid,
+        username: String,
+        chat_id: Option<Uuid>,
+        prompt_tokens: usize,
+        generated_tokens: usize,
+        wall_time: Duration,
+    },
 }
 
 #[derive(Default)]
 struct InferenceState {
+    pub prompt_tokens: usize,
     pub processed_tokens: usize,
     pub answer: String,
     pub processed_prompt: String,
@@ -79,8 +183,18 @@ pub fn initialize_model_and_handle_inferences(
     db: Arc<crate::DbPool>,
     config: LlmConfig,
     runtime: Arc<Runtime>,
-) -> Sender<InferenceRequest> {
-    let request_queue = queue::empty_queue();
+    registry: InferenceRegistry,
+    residency: Residency,
+) -> LlmHandle {
+    let request_queue = Queue::new();
+    let stats = Arc::new(QueueStats::new(config.max_inference_sessions));
+    let metrics = LlmMetrics::new(&model);
+    let queue = request_queue.clone();
+    let resident = residency.register(
+        model.clone(),
+        residency::weights_size([config.model_path.as_path()]),
+        move || queue.notify(),
+    );
 
     // Create a channel and thread responsible for saving chat entries to database
     let (tx_results, rx_results): (Sender<SaveDataRequest>, Receiver<SaveDataRequest>) =
@@ -90,30 +204,65 @@ pub fn initialize_model_and_handle_inferences(
             match save_data_request {
                 SaveDataRequest::Chat {
                     conversation_id,
+                    parent_entry_id,
+                    prompt_entry_id,
+                    active_entry_id,
                     input,
                     output,
                 } => {
-                    let user = ChatEntry::new_user(conversation_id, input);
-                    let bot = ChatEntry::new_bot(conversation_id, output);
+                    let user = if prompt_entry_id.is_none() {
+                        Some(ChatEntry::new_user(conversation_id, parent_entry_id, input))
+                    } else {
+                        None
+                    };
+                    let bot = ChatEntry::new_bot(
+                        conversation_id,
+                        prompt_entry_id.or(user.as_ref().map(|user| user.entry_id)),
+                        output,
+                    );
                     let db = db.clone();
                     // TODO: store the futures somewhere and await them?
                     runtime.spawn(async move {
-                        if let Err(e) = user.create(&db).await {
-                            log::error!("failed to save user chat entry - {e}")
+                        if let Some(user) = user {
+                            if let Err(e) = user.create(&db).await {
+                                log::error!("failed to save user chat entry - {e}");
+                                return;
+                            }
                         }
                         if let Err(e) = bot.create(&db).await {
-                            log::error!("failed to save bot chat entry - {e}")
+                            log::error!("failed to save bot chat entry - {e}");
+                            return;
+                        }
+                        // the reply ends the branch the conversation continues with unless the user
+                        // selected another branch while it was generated
+                        match Chat::advance_active_entry(
+                            &db,
+                            &conversation_id,
+                            active_entry_id,
+                            &bot.entry_id,
+                        )
+                        .await
+                        {
+                            Ok(true) => {}
+                            Ok(false) => log::debug!(
+                                "[{conversation_id}] another branch was selected, keeping it active"
+                            ),
+                            Err(e) => log::error!("failed to update active chat entry - {e}"),
                         }
                     });
                 }
                 SaveDataRequest::Prompt {
+                    id,
                     input,
                     output,
                     username,
                     settings,
                 } => {
                     let db = db.clone();
-                    let prompt = Prompt::new(username, model.clone(), input, output, settings);
+                    let prompt = Prompt {
+                        id,
+                        ..Prompt::new(username, model.clone(), input, output, settings)
+                    };
                     // TODO: store the futures somewhere and await them?
                     runtime.spawn(async move {
                         if let Err(e) = prompt.create(&db).await {
@@ -121,6 +270,37 @@ pub fn initialize_model_and_handle_inferences(
                         }
                     });
                 }
+                SaveDataRequest::Usage {
+                    id,
+                    username,
+                    chat_id,
+                    prompt_tokens,
+                    generated_tokens,
+                    wall_time,
+                } => {
+                    let db = db.clone();
+                    let usage = Usage::inference(
+                        id,
+                        username,
+                        model.clone(),
+                        chat_id,
+                        prompt_tokens,
+                        generated_tokens,
+                        wall_time,
+                    );
+                    runtime.spawn(async move {
+                        if let Err(e) = usage.create(&db).await {
+                            log::error!("[{id}] failed to record usage - {e}")
+                        }
+                        let tokens = (usage.prompt_tokens + usage.generated_tokens) as u64;
+                        if let Err(e) =
+                            UsageCounter::increment(&db, &usage.username, UsageKind::Tokens, tokens)
+                                .await
+                        {
+                            log::error!("[{id}] failed to update token usage counter - {e}")
+                        }
+                    });
+                }
             }
         } else {
             log::error!("all channels closed");
@@ -128,36 +308,131 @@ pub fn initialize_model_and_handle_inferences(
         }
     });
 
-    // Create a thread that'll receive InferenceRequests
-    let queue = request_queue.clone();
-    let tx_request = queue::start_queue_thread::<InferenceRequest>(queue);
+    let state = SharedModelState::default();
+    let handle = ModelHandle {
+        config: config.clone(),
+        queue: request_queue.clone(),
+        stats: stats.clone(),
+        state: state.clone(),
+        resident: resident.clone(),
+    };
 
     // Create a thread that will handle inference
     std::thread::spawn(move || {
-        let mut inference_session_manager = InferenceSessionManager::new(config);
+        let load = || {
+            // a worker stopped while waiting for memory has nothing left to load the model for
+            if !resident.acquire(|| request_queue.is_finished()) {
+                return None;
+            }
+            state.set(ModelState::Loading);
+            // Loading a corrupted model file may panic, treat it the same as a failed load
+            let manager = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                InferenceSessionManager::new(config.clone(), metrics.clone())
+            }))
+            .unwrap_or_else(|_| Err(crate::Error::ModelPanicked));
+            match manager {
+                Ok(manager) => {
+                    resident.loaded();
+                    state.set(ModelState::Ready);
+                    Some(manager)
+                }
+                // the worker stays alive and tries to load the model again on the next request
+                Err(e) => {
+                    log::error!("failed to load model {} - {e}", config.model_path.display());
+                    resident.release();
+                    state.set(ModelState::Failed(e.to_string()));
+                    for inference_request in request_queue.remove_where(|_| true) {
+                        inference_request.fail_queued(&e);
+                        registry.remove(&inference_request.id);
+                    }
+                    None
+                }
+            }
+        };
+
+        let mut manager = if resident.is_lazy() {
+            state.set(ModelState::Unloaded);
+            None
+        } else {
+            load()
+        };
         let mut running_sessions = VecDeque::new();
         let mut rng = thread_rng();
 
         loop {
-            let mut free_spots =
-                inference_session_manager.config.max_inference_sessions - running_sessions.len();
-            if free_spots > 0 {
-                if let Ok(mut queue) = request_queue.try_write() {
-                    while let Some(inference_request) = queue.pop_front() && free_spots > 0 {
-                        let mut session = inference_session_manager.get_inference_session(inference_request);
-
-                        if let Err(e) = session.feed_prompt(inference_session_manager.model.as_ref()) {
-                            log::error!("failed to initialize inference session - {e}");
-                        } else {
-                            running_sessions.push_back(session);
-                            free_spots -= 1;
-                        }
-                    }
+            if running_sessions.is_empty() {
+                resident.set_busy(false);
+                if manager.is_some() && resident.should_unload() {
+                    log::info!("unloading model {}", config.model_path.display());
+                    manager = None;
+                    resident.release();
+                    state.set(ModelState::Unloaded);
+                }
+
+                // Sleep until a request arrives or the model should be unloaded
+                if !request_queue.wait(resident.idle_timeout()) {
+                    log::info!("request queue closed, stopping inference thread");
+                    break;
+                }
+                if request_queue.is_empty() {
+                    continue;
                 }
             }
+
+            if manager.is_none() {
+                manager = load();
+            }
+            let Some(inference_session_manager) = manager.as_mut() else {
+                continue;
+            };
+            resident.set_busy(true);
+
+            // A model that should be unloaded finishes the running sessions without starting new
+            // ones
+            let free_spots = if resident.is_evicting() {
+                0
+            } else {
+                inference_session_manager.config.max_inference_sessions - running_sessions.len()
+            };
+            let mut new_requests = request_queue.pop_batch(
+                free_spots,
+                running_sessions.iter().map(|s| s.request.user.as_str()),
+                inference_session_manager.config.max_sessions_per_user,
+            );
+            new_requests.retain(|inference_request| {
+                if inference_request.cancellation.is_cancelled() {
+                    inference_request.cancel_queued();
+                    registry.remove(&inference_request.id);
+                    return false;
+                }
+                true
+            });
+            inference_session_manager
+                .metrics
+                .queue_depth
+                .set(request_queue.len() as i64);
+            stats.set_running(running_sessions.len() + new_requests.len());
+            for inference_request in new_requests {
+                let mut session =
+                    inference_session_manager.get_inference_session(inference_request);
+
+                if let Err(e) = session.feed_prompt(inference_session_manager.model.as_ref()) {
+                    log::error!("failed to initialize inference session - {e}");
+                    let _ = session.request.tx_tokens.send(InferenceEvent::error(&e));
+                    session.finish(FinishReason::Error, &tx_results);
+                    registry.remove(&session.request.id);
+                } else {
+                    running_sessions.push_back(session);
+                }
+            }
+            stats.set_running(running_sessions.len());
+
             for session in &mut running_sessions {
-                if session.state.processed_tokens
-                    <= session.request.settings.num_predict.unwrap_or(usize::MAX)
+                if session.request.cancellation.is_cancelled() {
+                    log::debug!("[{}] inference cancelled", session.request.id);
+                    session.finish(FinishReason::Cancelled, &tx_results);
+                } else if session.state.processed_tokens
+                    < session.request.settings.num_predict.unwrap_or(usize::MAX)
                 {
                     if let Err(e) =
                         session.infer_next_token(&inference_session_manager, &mut rng, &tx_results)
@@ -166,26 +441,38 @@ pub fn initialize_model_and_handle_inferences(
                     }
                 } else {
                     log::debug!("already infered max number of tokens for session");
-                    session.state.is_finished = true;
+                    session.finish(FinishReason::Length, &tx_results);
                 }
             }
 
-            running_sessions.retain(|s| !s.state.is_finished);
-
-            std::thread::sleep(std::time::Duration::from_millis(5));
+            running_sessions.retain(|s| {
+                if s.state.is_finished {
+                    registry.remove(&s.request.id);
+                    stats.record_job(s.started.elapsed());
+                }
+                !s.state.is_finished
+            });
+            stats.set_running(running_sessions.len());
+            inference_session_manager
+                .metrics
+                .active_sessions
+                .set(running_sessions.len() as i64);
         }
+        drop(manager);
+        resident.unregister();
     });
 
-    tx_request
+    handle
 }
 
 struct InferenceSessionManager {
     model: Box<dyn llm::Model>,
     config: LlmConfig,
+    metrics: LlmMetrics,
 }
 
 impl InferenceSessionManager {
-    fn new(config: LlmConfig) -> Self {
+    fn new(config: LlmConfig, metrics: LlmMetrics) -> Result<Self, crate::Error> {
         let load_callback = |progress| {
             match progress {
                 LoadProgress::HyperparametersLoaded => {
@@ -223,49 +510,38 @@ impl InferenceSessionManager {
 
         // Load model
         let model = match config.type_ {
-            LlmType::Bloom => Box::new(
-                llm::load::<llm::models::Bloom>(
-                    &config.model_path,
-                    Default::default(),
-                    load_callback,
-                )
-                .expect("Could not load model"),
-            ) as Box<dyn llm::Model>,
-            LlmType::Gpt2 => Box::new(
-                llm::load::<llm::models::Gpt2>(
-                    &config.model_path,
-                    Default::default(),
-                    load_callback,
-                )
-                .expect("Could not load model"),
-            ) as Box<dyn llm::Model>,
-            LlmType::GptJ => Box::new(
-                llm::load::<llm::models::GptJ>(
-                    &config.model_path,
-                    Default::default(),
-                    load_callback,
-                )
-                .expect("Could not load model"),
-            ) as Box<dyn llm::Model>,
-            LlmType::Llama => Box::new(
-                llm::load::<llm::models::Llama>(
-                    &config.model_path,
-                    Default::default(),
-                    load_callback,
-                )
-                .expect("Could not load model"),
-            ) as Box<dyn llm::Model>,
-            LlmType::Neox => Box::new(
-                llm::load::<llm::models::NeoX>(
-                    &config.model_path,
-                    Default::default(),
-                    load_callback,
-                )
-                .expect("Could not load model"),
-            ) as Box<dyn llm::Model>,
+            LlmType::Bloom => Box::new(llm::load::<llm::models::Bloom>(
+                &config.model_path,
+                Default::default(),
+                load_callback,
+            )?) as Box<dyn llm::Model>,
+            LlmType::Gpt2 => Box::new(llm::load::<llm::models::Gpt2>(
+                &config.model_path,
+                Default::default(),
+                load_callback,
+            )?) as Box<dyn llm::Model>,
+            LlmType::GptJ => Box::new(llm::load::<llm::models::GptJ>(
+                &config.model_path,
+                Default::default(),
+                load_callback,
+            )?) as Box<dyn llm::Model>,
+            LlmType::Llama => Box::new(llm::load::<llm::models::Llama>(
+                &config.model_path,
+                Default::default(),
+                load_callback,
+            )?) as Box<dyn llm::Model>,
+            LlmType::Neox => Box::new(llm::load::<llm::models::NeoX>(
+                &config.model_path,
+                Default::default(),
+                load_callback,
+            )?) as Box<dyn llm::Model>,
         };
 
-        Self { model, config }
+        Ok(Self {
+            model,
+            config,
+            metrics,
+        })
     }
 
     fn get_inference_session(&mut self, request: InferenceRequest) -> RunningInferenceSession {
@@ -296,7 +572,7 @@ impl InferenceSessionManager {
         };
 
         let prompt = if let Some(chat) = &request.chat_data {
-            let history = chat.history.iter().fold(String::new(), |mut acc, x| {
+            let history = chat.branch().into_iter().fold(String::new(), |mut acc, x| {
                 let prefix = match x.entry_type {
                     ChatEntryType::Bot => ANSWER_PREFIX,
                     ChatEntryType::User => USER_PREFIX,
@@ -319,7 +595,8 @@ impl InferenceSessionManager {
         };
 
         RunningInferenceSession {
-            id: Uuid::new_v4(),
+            id: request.id,
+            started: Instant::now(),
             session: self.model.start_session(inference_session_params),
             params,
             request,
@@ -333,6 +610,7 @@ impl InferenceSessionManager {
 
 struct RunningInferenceSession {
     pub id: Uuid,
+    pub started: Instant,
     pub session: InferenceSession,
     pub params: InferenceParameters,
     pub request: InferenceRequest,
@@ -347,22 +625,58 @@ impl RunningInferenceSession {
             self.state.processed_prompt
         );
         let id = self.id;
+        let prompt_tokens = &mut self.state.prompt_tokens;
         self.session
             .feed_prompt(
                 model,
                 &self.params,
                 &self.state.processed_prompt,
                 &mut Default::default(),
-                move |b| {
+                |b| {
                     log::trace!("[{}] prompt part: {}", id, String::from_utf8_lossy(b));
+                    *prompt_tokens += 1;
                     Ok::<(), InferenceError>(())
                 },
             )
             .map_err(crate::Error::from)
     }
 
+    /// Sends the final event with usage summary to the receiver and saves the results.
+    fn finish(&mut self, finish_reason: FinishReason, tx_results: &Sender<SaveDataRequest>) {
+        let usage = InferenceUsage {
+            prompt_tokens: self.state.prompt_tokens,
+            generated_tokens: self.state.processed_tokens,
+            total_tokens: self.state.prompt_tokens + self.state.processed_tokens,
+        };
+        log::debug!(
+            "[{}] finished inference ({finish_reason:?}) - {usage:?}",
+            self.id
+        );
+        let event = InferenceEvent::Done(DoneEvent {
+            finish_reason,
+            usage,
+        });
+        let _ = self.request.tx_tokens.send(event);
+        self.save_results(tx_results);
+    }
+
     fn save_results(&mut self, tx_results: &Sender<SaveDataRequest>) {
         self.state.is_finished = true;
+        if let Err(e) = tx_results.try_send(SaveDataRequest::Usage {
+            id: self.id,
+            username: self.request.user.clone(),
+            chat_id: self
+                .request
+                .chat_data
+                .as_ref()
+                .filter(|_| self.request.save)
+                .map(|chat| chat.conversation_id),
+            prompt_tokens: self.state.prompt_tokens,
+            generated_tokens: self.state.processed_tokens,
+            wall_time: self.started.elapsed(),
+        }) {
+            log::error!("[{}] failed to record token usage - {e}", self.id);
+        }
         if self.request.save {
             if let Some(chat) = &self.request.chat_data {
                 log::trace!("saving chat data {}", &chat.conversation_id);
@@ -370,6 +684,9 @@ impl RunningInferenceSession {
                 if !output.is_empty() {
                     if let Err(e) = tx_results.try_send(SaveDataRequest::Chat {
                         conversation_id: chat.conversation_id,
+                        parent_entry_id: chat.history.last().map(|entry| entry.entry_id),
+                        prompt_entry_id: chat.prompt_entry_id,
+                        active_entry_id: chat.active_entry_id,
                         input: self.request.prompt.clone(),
                         output,
                     }) {
@@ -382,6 +699,7 @@ impl RunningInferenceSession {
             } else {
                 log::trace!("[{}] saving inference results", self.id);
                 if let Err(e) = tx_results.try_send(SaveDataRequest::Prompt {
+                    id: self.id,
                     input: self.request.prompt.clone(),
                     output: self.state.answer.clone(),
                     username: self.request.user.clone(),
@@ -409,6 +727,7 @@ impl RunningInferenceSession {
     ) -> Result<(), crate::Error> {
         log::trace!("[{}] infering next valid utf-8 token", self.id);
         let mut buf = llm::TokenUtf8Buffer::new();
+        let start = Instant::now();
 
         loop {
             let token = match self.session.infer_next_token(
@@ -420,17 +739,33 @@ impl RunningInferenceSession {
                 Ok(token) => token,
                 Err(InferenceError::EndOfText) => {
                     log::debug!("[{}] end of inference", self.id);
-                    self.save_results(tx_results);
+                    self.finish(FinishReason::Stop, tx_results);
                     break;
                 }
-                Err(e) => return Err(e.into()),
+                Err(e) => {
+                    let _ = self.request.tx_tokens.send(InferenceEvent::error(&e));
+                    self.finish(FinishReason::Error, tx_results);
+                    return Err(e.into());
+                }
             };
 
             if let Some(valid_token) = buf.push(token) {
+                let metrics = &inference_session_manager.metrics;
+                metrics.generated_tokens.inc();
+                metrics.token_latency.observe(start.elapsed().as_secs_f64());
+                if self.state.processed_tokens == 0 {
+                    metrics
+                        .time_to_first_token
+                        .observe(self.started.elapsed().as_secs_f64());
+                }
                 self.state.answer.push_str(&valid_token);
-                self.state.processed_tokens += 1;
                 log::trace!("[{}] Sending token {} to receiver.", self.id, valid_token);
-                match self.request.tx_tokens.send(Ok(valid_token)) {
+                let event = InferenceEvent::Token(TokenEvent {
+                    index: self.state.processed_tokens,
+                    token: valid_token,
+                });
+                self.state.processed_tokens += 1;
+                match self.request.tx_tokens.send(event) {
                     Ok(_) => {
                         break;
                     }
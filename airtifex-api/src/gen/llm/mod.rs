@@ -1,40 +1,72 @@
 use crate::{
     config::{Config, LlmConfig},
-    gen::ModelName,
+    gen::{residency::Residency, ModelHandle, ModelName},
     models::llm::LargeLanguageModel,
-    DbPool, Result,
+    DbPool, Error, Result,
 };
 
 use std::{collections::HashMap, sync::Arc};
 use tokio::runtime::Runtime;
 
 pub mod inference;
+pub mod registry;
 
 pub use inference::*;
+pub use registry::*;
+
+pub type LlmHandle = ModelHandle<LlmConfig, InferenceRequest>;
 
 pub async fn initialize_models(
     db: Arc<DbPool>,
     config: &Config,
     runtime: Arc<Runtime>,
-) -> Result<HashMap<ModelName, (LlmConfig, flume::Sender<InferenceRequest>)>> {
-    let mut txs = HashMap::new();
+    registry: InferenceRegistry,
+    residency: Residency,
+) -> Result<HashMap<ModelName, LlmHandle>> {
+    let mut handles = HashMap::new();
     for (model, llm_config) in config.llms.iter() {
-        let exists = LargeLanguageModel::get_by_name(&db, model).await.is_ok();
-
-        log::info!("initializing model {model}, exists in db: {exists}");
-
-        if !exists {
-            let llm =
-                LargeLanguageModel::new(model.to_owned(), llm_config.model_description.clone());
-            llm.create(&db).await?;
-        }
-        let tx_inference_req = inference::initialize_model_and_handle_inferences(
-            model.to_owned(),
+        let handle = initialize_model(
             db.clone(),
-            llm_config.clone(),
+            model,
+            llm_config,
             runtime.clone(),
-        );
-        txs.insert(model.clone(), (llm_config.clone(), tx_inference_req));
+            registry.clone(),
+            residency.clone(),
+        )
+        .await?;
+        handles.insert(model.clone(), handle);
+    }
+    Ok(handles)
+}
+
+/// Registers the model in the database if it doesn't exist yet and starts its inference thread.
+/// Fails if the model file is missing.
+pub async fn initialize_model(
+    db: Arc<DbPool>,
+    model: &str,
+    llm_config: &LlmConfig,
+    runtime: Arc<Runtime>,
+    registry: InferenceRegistry,
+    residency: Residency,
+) -> Result<LlmHandle> {
+    if !llm_config.model_path.is_file() {
+        return Err(Error::ModelFileMissing(llm_config.model_path.clone()));
+    }
+
+    let exists = LargeLanguageModel::get_by_name(&db, model).await.is_ok();
+
+    log::info!("initializing model {model}, exists in db: {exists}");
+
+    if !exists {
+        let llm = LargeLanguageModel::new(model.to_owned(), llm_config.model_description.clone());
+        llm.create(&db).await?;
     }
-    Ok(txs)
+    Ok(inference::initialize_model_and_handle_inferences(
+        model.to_owned(),
+        db,
+        llm_config.clone(),
+        runtime,
+        registry,
+        residency,
+    ))
 }
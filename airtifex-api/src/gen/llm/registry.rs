@@ -0,0 +1,59 @@
+use crate::{gen::ModelName, id::Uuid};
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// A flag shared between the API and the inference thread used to stop a request.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct InferenceEntry {
+    pub user: String,
+    pub model: ModelName,
+    pub cancellation: CancellationToken,
+}
+
+/// Keeps track of all queued and running inference requests so that they can be cancelled.
+#[derive(Clone, Debug, Default)]
+pub struct InferenceRegistry(Arc<Mutex<HashMap<Uuid, InferenceEntry>>>);
+
+impl InferenceRegistry {
+    /// Registers a new inference request returning its ID and cancellation token.
+    pub fn register(&self, user: &str, model: &str) -> (Uuid, CancellationToken) {
+        let id = Uuid::new_v4();
+        let cancellation = CancellationToken::default();
+        self.0.lock().unwrap().insert(
+            id,
+            InferenceEntry {
+                user: user.to_string(),
+                model: model.to_string(),
+                cancellation: cancellation.clone(),
+            },
+        );
+        (id, cancellation)
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<InferenceEntry> {
+        self.0.lock().unwrap().get(id).cloned()
+    }
+
+    pub fn remove(&self, id: &Uuid) {
+        self.0.lock().unwrap().remove(id);
+    }
+}
@@ -1,4 +1,158 @@
 pub mod image;
 pub mod llm;
+pub mod residency;
+
+use crate::queue::{Priority, Queue, QueueItem, QueueStats};
+use airtifex_core::{
+    health::{ModelHealth, ModelLoadState, ModelResidency},
+    queue::{QueuePosition, QueueStatus},
+};
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError, RwLock},
+};
 
 pub type ModelName = String;
+
+/// Handles of the loaded models that can be swapped while the server is running.
+pub struct Models<H>(RwLock<HashMap<ModelName, Arc<H>>>);
+
+impl<H> Default for Models<H> {
+    fn default() -> Self {
+        Self(RwLock::new(HashMap::new()))
+    }
+}
+
+impl<H> From<HashMap<ModelName, H>> for Models<H> {
+    fn from(models: HashMap<ModelName, H>) -> Self {
+        Self(RwLock::new(
+            models
+                .into_iter()
+                .map(|(name, handle)| (name, Arc::new(handle)))
+                .collect(),
+        ))
+    }
+}
+
+impl<H> Models<H> {
+    pub fn get(&self, name: &str) -> Option<Arc<H>> {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .cloned()
+    }
+
+    /// Returns all models sorted by name.
+    pub fn all(&self) -> Vec<(ModelName, Arc<H>)> {
+        let mut models = self
+            .0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(name, handle)| (name.clone(), handle.clone()))
+            .collect::<Vec<_>>();
+        models.sort_by(|a, b| a.0.cmp(&b.0));
+        models
+    }
+
+    pub fn names(&self) -> Vec<ModelName> {
+        self.all().into_iter().map(|(name, _)| name).collect()
+    }
+
+    /// Swaps all models at once returning the previous handles.
+    pub fn replace(&self, models: HashMap<ModelName, Arc<H>>) -> HashMap<ModelName, Arc<H>> {
+        std::mem::replace(
+            &mut *self.0.write().unwrap_or_else(PoisonError::into_inner),
+            models,
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub enum ModelState {
+    /// Not loaded yet or unloaded to free memory, loaded again on the next request
+    Unloaded,
+    #[default]
+    Loading,
+    Ready,
+    Failed(String),
+}
+
+/// Load state of a model shared between its worker thread and the API.
+#[derive(Clone, Debug, Default)]
+pub struct SharedModelState(Arc<Mutex<ModelState>>);
+
+impl SharedModelState {
+    pub fn get(&self) -> ModelState {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn set(&self, state: ModelState) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = state;
+    }
+}
+
+/// Handle to a model worker used by the API to submit requests and inspect its queue.
+pub struct ModelHandle<C, R> {
+    pub config: C,
+    pub queue: Queue<R>,
+    pub stats: Arc<QueueStats>,
+    pub state: SharedModelState,
+    pub resident: residency::ResidentModel,
+}
+
+impl<C, R: QueueItem> ModelHandle<C, R> {
+    /// Returns the position a newly submitted request of `user` would take in the queue.
+    pub fn next_position(&self, user: &str, priority: Priority) -> QueuePosition {
+        self.stats
+            .position(self.queue.next_position(user, priority))
+    }
+
+    /// Adds the request to the queue of the model worker. A model that failed to load is loaded
+    /// again for the request.
+    pub fn submit(&self, request: R) -> crate::Result<()> {
+        self.queue
+            .push(request)
+            .map_err(|request| match self.state.get() {
+                ModelState::Failed(reason) => crate::Error::ModelUnavailable(reason),
+                _ => crate::Error::QueueClosed(request.item_id()),
+            })
+    }
+
+    /// Stops accepting new requests, the worker exits once all queued and running requests are
+    /// processed.
+    pub fn stop(&self) {
+        self.queue.close();
+    }
+
+    pub fn queue_status(&self, model: &str, user: &str) -> QueueStatus {
+        self.stats.status(model, user, &self.queue)
+    }
+
+    pub fn health(&self, model: &str) -> ModelHealth {
+        let (state, error) = match self.state.get() {
+            ModelState::Unloaded => (ModelLoadState::Unloaded, None),
+            ModelState::Loading => (ModelLoadState::Loading, None),
+            ModelState::Ready => (ModelLoadState::Ready, None),
+            ModelState::Failed(reason) => (ModelLoadState::Failed, Some(reason)),
+        };
+        ModelHealth {
+            name: model.to_string(),
+            state,
+            error,
+        }
+    }
+    pub fn residency(&self, model: &str) -> ModelResidency {
+        let (size_bytes, idle) = self.resident.usage();
+        ModelResidency {
+            state: self.health(model).state,
+            size_bytes,
+            idle_seconds: idle.map(|idle| idle.as_secs()),
+        }
+    }
+}
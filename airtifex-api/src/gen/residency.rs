@@ -0,0 +1,304 @@
+use crate::config::ResidencyConfig;
+
+use std::{
+    path::Path,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+const MB: u64 = 1024 * 1024;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Status {
+    Unloaded,
+    Loading,
+    Resident,
+}
+
+struct Entry {
+    id: u64,
+    name: String,
+    size: u64,
+    status: Status,
+    busy: bool,
+    /// Set when the model should be unloaded to make room for another one
+    evict: bool,
+    last_used: Instant,
+    /// Wakes up the worker of the model
+    wake: Box<dyn Fn() + Send + Sync>,
+}
+
+struct ResidencyState {
+    config: ResidencyConfig,
+    entries: Vec<Entry>,
+    next_id: u64,
+}
+
+struct ResidencyInner {
+    state: Mutex<ResidencyState>,
+    condvar: Condvar,
+}
+
+/// Keeps track of the models loaded by all workers so that they fit into the configured memory
+/// budget. Workers load their model when it is first needed, unload it after it was idle for too
+/// long and unload the least recently used idle models when another model needs the memory.
+#[derive(Clone)]
+pub struct Residency(Arc<ResidencyInner>);
+
+impl Residency {
+    pub fn new(config: ResidencyConfig) -> Self {
+        Self(Arc::new(ResidencyInner {
+            state: Mutex::new(ResidencyState {
+                config,
+                entries: vec![],
+                next_id: 0,
+            }),
+            condvar: Condvar::new(),
+        }))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ResidencyState> {
+        self.0.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Applies a new configuration waking up all workers so that they pick up the new idle
+    /// timeout.
+    pub fn set_config(&self, config: ResidencyConfig) {
+        let mut state = self.lock();
+        state.config = config;
+        for entry in &state.entries {
+            (entry.wake)();
+        }
+        drop(state);
+        self.0.condvar.notify_all();
+    }
+
+    /// Registers a model worker, `size` is the estimated memory used by the loaded weights and
+    /// `wake` has to wake up the worker if it is waiting for requests.
+    pub fn register(
+        &self,
+        name: impl Into<String>,
+        size: u64,
+        wake: impl Fn() + Send + Sync + 'static,
+    ) -> ResidentModel {
+        let mut state = self.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.entries.push(Entry {
+            id,
+            name: name.into(),
+            size,
+            status: Status::Unloaded,
+            busy: false,
+            evict: false,
+            last_used: Instant::now(),
+            wake: Box::new(wake),
+        });
+        ResidentModel {
+            residency: self.clone(),
+            id,
+        }
+    }
+}
+
+/// Residency of a single model worker.
+#[derive(Clone)]
+pub struct ResidentModel {
+    residency: Residency,
+    id: u64,
+}
+
+impl ResidentModel {
+    fn with_entry<T>(&self, f: impl FnOnce(&ResidencyConfig, &mut Entry) -> T) -> Option<T> {
+        let mut state = self.residency.lock();
+        let ResidencyState {
+            config, entries, ..
+        } = &mut *state;
+        entries
+            .iter_mut()
+            .find(|entry| entry.id == self.id)
+            .map(|entry| f(config, entry))
+    }
+
+    /// Whether the model should only be loaded once a request arrives.
+    pub fn is_lazy(&self) -> bool {
+        self.residency.lock().config.lazy_load
+    }
+
+    /// Blocks until there is enough memory to load the model. Least recently used models are
+    /// asked to unload first, models that are processing requests unload once they finish. If
+    /// the model doesn't fit even with all other models unloaded it's loaded anyway.
+    ///
+    /// A model replacing another one with the same name waits until the worker of the previous
+    /// one exits so that both are never loaded at once. Returns `false` if `stopped` returns
+    /// `true` while waiting, the model shouldn't be loaded then.
+    pub fn acquire(&self, stopped: impl Fn() -> bool) -> bool {
+        let mut state = self.residency.lock();
+        loop {
+            if stopped() {
+                return false;
+            }
+            let Some((name, size)) = state
+                .entries
+                .iter()
+                .find(|entry| entry.id == self.id)
+                .map(|entry| (entry.name.clone(), entry.size))
+            else {
+                return true;
+            };
+            let replacing = state
+                .entries
+                .iter()
+                .any(|entry| entry.id < self.id && entry.name == name);
+            if replacing {
+                state = self.wait(state);
+                continue;
+            }
+            let Some(budget) = state.config.memory_budget_mb.map(|mb| mb * MB) else {
+                break;
+            };
+
+            let others = || state.entries.iter().filter(|entry| entry.id != self.id);
+            let used = others()
+                .filter(|entry| entry.status != Status::Unloaded)
+                .map(|entry| entry.size)
+                .sum::<u64>();
+            if used + size <= budget {
+                break;
+            }
+            // the memory of a model that is being loaded can't be freed yet, wait for it to
+            // finish before deciding what to evict
+            if others().any(|entry| entry.status == Status::Loading) {
+                state = self.wait(state);
+                continue;
+            }
+            if !others().any(|entry| entry.status == Status::Resident) {
+                log::warn!(
+                    "model needs {} MB which exceeds the memory budget of {} MB",
+                    (used + size) / MB,
+                    budget / MB
+                );
+                break;
+            }
+
+            // Ask the least recently used models to unload, preferring the ones that are idle
+            let mut candidates = state
+                .entries
+                .iter_mut()
+                .filter(|entry| entry.id != self.id && entry.status == Status::Resident)
+                .collect::<Vec<_>>();
+            candidates.sort_by_key(|entry| (entry.busy, entry.last_used));
+            let mut freed = 0;
+            for entry in candidates {
+                if used - freed + size <= budget {
+                    break;
+                }
+                freed += entry.size;
+                if !entry.evict {
+                    log::info!("evicting model {} to free memory", entry.name);
+                    entry.evict = true;
+                    (entry.wake)();
+                }
+            }
+
+            state = self.wait(state);
+        }
+
+        if let Some(entry) = state.entries.iter_mut().find(|entry| entry.id == self.id) {
+            entry.status = Status::Loading;
+        }
+        true
+    }
+
+    /// Waits for another model to change its residency, with a timeout so that the `stopped`
+    /// condition of [`ResidentModel::acquire`] is checked regularly.
+    fn wait<'a>(&'a self, state: MutexGuard<'a, ResidencyState>) -> MutexGuard<'a, ResidencyState> {
+        self.residency
+            .0
+            .condvar
+            .wait_timeout(state, Duration::from_secs(1))
+            .unwrap_or_else(PoisonError::into_inner)
+            .0
+    }
+
+    /// Marks the model as loaded.
+    pub fn loaded(&self) {
+        self.with_entry(|_, entry| {
+            entry.status = Status::Resident;
+            entry.last_used = Instant::now();
+        });
+    }
+
+    /// Marks the model as unloaded allowing other models to use the memory.
+    pub fn release(&self) {
+        self.with_entry(|_, entry| {
+            entry.status = Status::Unloaded;
+            entry.evict = false;
+        });
+        self.residency.0.condvar.notify_all();
+    }
+
+    /// Marks whether the model is currently processing requests.
+    pub fn set_busy(&self, busy: bool) {
+        self.with_entry(|_, entry| {
+            if entry.busy || busy {
+                entry.last_used = Instant::now();
+            }
+            entry.busy = busy;
+        });
+    }
+
+    /// Whether the model was asked to unload, the worker should stop starting new requests.
+    pub fn is_evicting(&self) -> bool {
+        self.with_entry(|_, entry| entry.evict).unwrap_or_default()
+    }
+
+    /// Whether the idle model should be unloaded now.
+    pub fn should_unload(&self) -> bool {
+        self.with_entry(|config, entry| {
+            entry.status == Status::Resident
+                && (entry.evict
+                    || config.idle_timeout_secs.map_or(false, |timeout| {
+                        entry.last_used.elapsed() >= Duration::from_secs(timeout)
+                    }))
+        })
+        .unwrap_or_default()
+    }
+
+    /// Time left until the idle model should be unloaded, `None` if it never times out.
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.with_entry(|config, entry| {
+            if entry.status != Status::Resident || entry.busy {
+                return None;
+            }
+            config.idle_timeout_secs.map(|timeout| {
+                Duration::from_secs(timeout).saturating_sub(entry.last_used.elapsed())
+            })
+        })
+        .flatten()
+    }
+
+    /// Returns the estimated size of the model and the time since it last processed a request.
+    pub fn usage(&self) -> (u64, Option<Duration>) {
+        self.with_entry(|_, entry| (entry.size, (!entry.busy).then(|| entry.last_used.elapsed())))
+            .unwrap_or_default()
+    }
+
+    /// Removes the model once its worker exits.
+    pub fn unregister(&self) {
+        self.residency
+            .lock()
+            .entries
+            .retain(|entry| entry.id != self.id);
+        self.residency.0.condvar.notify_all();
+    }
+}
+
+/// Estimates the memory used by model weights from the size of their files.
+pub fn weights_size<'a>(paths: impl IntoIterator<Item = &'a Path>) -> u64 {
+    paths
+        .into_iter()
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
@@ -12,7 +12,6 @@ use axum::{
     Json,
 };
 
-#[allow(unused_macros)]
 macro_rules! with_optional_guard {
     ($req:ident, $db: ident) => {
         crate::guard::auth_guard(
@@ -1,13 +1,16 @@
 #![feature(path_file_prefix)]
 #![feature(let_chains)]
 pub use airtifex_core::api_response::{ApiResponse, ApiVersion};
-use config::LlmConfig;
 pub use errors::Error;
 
 use axum::{extract::FromRef, http::StatusCode, response::Response};
 use axum_extra::extract::cookie::Key;
-use flume::Sender;
-use std::{collections::HashMap, ops::Deref};
+use std::{
+    ops::Deref,
+    path::PathBuf,
+    sync::{PoisonError, RwLock, RwLockReadGuard},
+};
+use tokio::runtime::Runtime;
 
 #[macro_use]
 mod guard;
@@ -17,12 +20,21 @@ pub mod config;
 pub mod errors;
 pub mod gen;
 pub mod id;
+pub mod metrics;
 pub mod models;
 pub mod permissions;
 pub mod queue;
+pub mod quota;
+pub mod reload;
 pub mod routes;
+pub mod storage;
 
-use gen::{image::GenerateImageRequest, llm::InferenceRequest, ModelName};
+use gen::{
+    image::{progress::ImageProgressRegistry, ImageModelHandle},
+    llm::{InferenceRegistry, LlmHandle},
+    residency::Residency,
+    Models,
+};
 
 #[cfg(all(feature = "postgres", not(feature = "sqlite")))]
 pub type DbPool = sqlx::PgPool;
@@ -37,9 +49,22 @@ pub struct InnerAppState {
     pub uuid_context: id::V1Context,
     pub db: std::sync::Arc<crate::DbPool>,
     pub key: Key,
-    pub config: config::Config,
-    pub tx_inference_req: HashMap<ModelName, (LlmConfig, Sender<InferenceRequest>)>,
-    pub tx_image_gen_req: HashMap<ModelName, Sender<GenerateImageRequest>>,
+    pub config: RwLock<config::Config>,
+    /// Path the configuration was read from, used to reload it
+    pub config_path: PathBuf,
+    pub runtime: std::sync::Arc<Runtime>,
+    pub llms: Models<LlmHandle>,
+    pub image_models: Models<ImageModelHandle>,
+    pub inference_registry: InferenceRegistry,
+    pub image_progress: ImageProgressRegistry,
+    pub residency: Residency,
+    pub storage: storage::SharedStorage,
+}
+
+impl InnerAppState {
+    pub fn config(&self) -> RwLockReadGuard<'_, config::Config> {
+        self.config.read().unwrap_or_else(PoisonError::into_inner)
+    }
 }
 
 #[derive(Clone)]
@@ -83,6 +108,22 @@ pub trait ToAxumResponse: Sized {
         self.into_response(StatusCode::BAD_REQUEST)
     }
 
+    fn forbidden(self) -> Response {
+        self.into_response(StatusCode::FORBIDDEN)
+    }
+
+    fn not_found(self) -> Response {
+        self.into_response(StatusCode::NOT_FOUND)
+    }
+
+    fn conflict(self) -> Response {
+        self.into_response(StatusCode::CONFLICT)
+    }
+
+    fn too_many_requests(self) -> Response {
+        self.into_response(StatusCode::TOO_MANY_REQUESTS)
+    }
+
     fn internal_server_error(self) -> Response {
         self.into_response(StatusCode::INTERNAL_SERVER_ERROR)
     }
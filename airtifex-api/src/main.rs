@@ -3,7 +3,7 @@ use airtifex_api::{
     gen,
     id::V1Context as ClockContext,
     models::user::User,
-    routes::{api, r#static},
+    routes::{api, health, metrics, openai, r#static},
     DbPool, Error, InnerAppState, Result, SharedAppState,
 };
 use airtifex_core::user::AccountType;
@@ -11,7 +11,11 @@ use airtifex_core::user::AccountType;
 use axum::{extract::DefaultBodyLimit, Router};
 use axum_extra::extract::cookie::Key;
 use clap::Parser;
-use std::{path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 use tokio::runtime::Runtime;
 use tower_http::classify::ServerErrorsFailureClass;
 use tracing::{Level, Span};
@@ -75,6 +79,9 @@ async fn inner(runtime: Arc<Runtime>) -> Result<()> {
                 sqlx::migrate!("migrations/postgres").run(&*db_pool).await?;
             }
 
+            let storage = airtifex_api::storage::initialize(&config.storage)?;
+            airtifex_api::storage::move_blobs(&db_pool, storage.as_ref()).await?;
+
             let context = ClockContext::new(0);
 
             let user = User::new("admin", "admin", "", AccountType::Admin);
@@ -82,24 +89,72 @@ async fn inner(runtime: Arc<Runtime>) -> Result<()> {
 
             let listen = (config.listen_addr, config.listen_port);
 
-            let tx_inference_req =
-                gen::llm::initialize_models(db_pool.clone(), &config, runtime.clone()).await?;
-            let tx_image_gen_req =
-                gen::image::initialize_models(db_pool.clone(), &config, runtime.clone()).await?;
+            let inference_registry = gen::llm::InferenceRegistry::default();
+            let image_progress = gen::image::progress::ImageProgressRegistry::default();
+            let residency = gen::residency::Residency::new(config.residency.clone());
+            let llms = gen::llm::initialize_models(
+                db_pool.clone(),
+                &config,
+                runtime.clone(),
+                inference_registry.clone(),
+                residency.clone(),
+            )
+            .await?;
+            let image_models = gen::image::initialize_models(
+                db_pool.clone(),
+                &config,
+                runtime.clone(),
+                image_progress.clone(),
+                storage.clone(),
+                residency.clone(),
+            )
+            .await?;
 
             std::env::set_var("JWT_SECRET", &config.jwt_secret);
 
+            let state = SharedAppState::from(Arc::new(InnerAppState {
+                db: db_pool,
+                uuid_context: context,
+                key: Key::generate(),
+                config: RwLock::new(config),
+                config_path: opts.config,
+                runtime,
+                llms: llms.into(),
+                image_models: image_models.into(),
+                inference_registry,
+                image_progress,
+                residency,
+                storage,
+            }));
+
+            #[cfg(unix)]
+            {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    use tokio::signal::unix::{signal, SignalKind};
+                    let mut hangup = match signal(SignalKind::hangup()) {
+                        Ok(hangup) => hangup,
+                        Err(e) => {
+                            tracing::error!("failed to listen for SIGHUP - {e}");
+                            return;
+                        }
+                    };
+                    while hangup.recv().await.is_some() {
+                        tracing::info!("received SIGHUP, reloading configuration");
+                        if let Err(e) = airtifex_api::reload::reload(&state).await {
+                            tracing::error!("failed to reload configuration - {e}");
+                        }
+                    }
+                });
+            }
+
             let app = Router::new()
                 .merge(api::router())
+                .merge(openai::router())
+                .merge(metrics::router())
+                .merge(health::router())
                 .merge(r#static::router())
-                .with_state(SharedAppState::from(Arc::new(InnerAppState {
-                    db: db_pool,
-                    uuid_context: context,
-                    key: Key::generate(),
-                    config,
-                    tx_inference_req,
-                    tx_image_gen_req,
-                })))
+                .with_state(state)
                 .layer(DefaultBodyLimit::max(8 * 1000 * 1000))
                 .layer(
                     tower_http::trace::TraceLayer::new_for_http()
@@ -112,6 +167,7 @@ async fn inner(runtime: Arc<Runtime>) -> Result<()> {
                         .on_response(
                             |rsp: &axum::response::Response, latency: Duration, _span: &Span| {
                                 tracing::info!("{} {}ms", rsp.status(), latency.as_millis());
+                                airtifex_api::metrics::record_response(rsp.status(), latency);
                             },
                         ),
                 );
@@ -0,0 +1,160 @@
+use axum::http::StatusCode;
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, Encoder, Histogram,
+    HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use std::time::Duration;
+
+static QUEUE_DEPTH: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "airtifex_queue_depth",
+        "Number of requests waiting in the queue of a model",
+        &["type", "model"]
+    )
+    .expect("valid metric")
+});
+
+static ACTIVE_SESSIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "airtifex_active_sessions",
+        "Number of requests that are currently being processed by a model",
+        &["type", "model"]
+    )
+    .expect("valid metric")
+});
+
+static GENERATED_TOKENS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "airtifex_generated_tokens_total",
+        "Number of tokens generated by a language model",
+        &["model"]
+    )
+    .expect("valid metric")
+});
+
+static TIME_TO_FIRST_TOKEN: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "airtifex_time_to_first_token_seconds",
+        "Time from the start of processing an inference request until the first token is generated",
+        &["model"],
+        vec![0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0]
+    )
+    .expect("valid metric")
+});
+
+static TOKEN_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "airtifex_token_latency_seconds",
+        "Time it takes to generate a single token",
+        &["model"],
+        vec![0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0]
+    )
+    .expect("valid metric")
+});
+
+static IMAGE_TIMESTEPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "airtifex_image_timesteps_total",
+        "Number of diffusion timesteps processed by an image model",
+        &["model"]
+    )
+    .expect("valid metric")
+});
+
+static IMAGE_TIMESTEP_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "airtifex_image_timestep_duration_seconds",
+        "Time it takes to process a single diffusion timestep",
+        &["model"],
+        vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]
+    )
+    .expect("valid metric")
+});
+
+static HTTP_RESPONSES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "airtifex_http_responses_total",
+        "Number of HTTP responses by status code",
+        &["status"]
+    )
+    .expect("valid metric")
+});
+
+static HTTP_RESPONSE_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "airtifex_http_response_latency_seconds",
+        "Time until the response headers are sent back to the client",
+        &["status"]
+    )
+    .expect("valid metric")
+});
+
+static DB_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "airtifex_db_errors_total",
+        "Number of failed database operations by the affected table",
+        &["table"]
+    )
+    .expect("valid metric")
+});
+
+/// Metrics reported by the inference thread of a single language model.
+#[derive(Clone)]
+pub struct LlmMetrics {
+    pub queue_depth: IntGauge,
+    pub active_sessions: IntGauge,
+    pub generated_tokens: IntCounter,
+    pub time_to_first_token: Histogram,
+    pub token_latency: Histogram,
+}
+
+impl LlmMetrics {
+    pub fn new(model: &str) -> Self {
+        Self {
+            queue_depth: QUEUE_DEPTH.with_label_values(&["llm", model]),
+            active_sessions: ACTIVE_SESSIONS.with_label_values(&["llm", model]),
+            generated_tokens: GENERATED_TOKENS.with_label_values(&[model]),
+            time_to_first_token: TIME_TO_FIRST_TOKEN.with_label_values(&[model]),
+            token_latency: TOKEN_LATENCY.with_label_values(&[model]),
+        }
+    }
+}
+
+/// Metrics reported by the generation thread of a single image model.
+#[derive(Clone)]
+pub struct ImageMetrics {
+    pub queue_depth: IntGauge,
+    pub active_sessions: IntGauge,
+    pub timesteps: IntCounter,
+    pub timestep_duration: Histogram,
+}
+
+impl ImageMetrics {
+    pub fn new(model: &str) -> Self {
+        Self {
+            queue_depth: QUEUE_DEPTH.with_label_values(&["image", model]),
+            active_sessions: ACTIVE_SESSIONS.with_label_values(&["image", model]),
+            timesteps: IMAGE_TIMESTEPS.with_label_values(&[model]),
+            timestep_duration: IMAGE_TIMESTEP_DURATION.with_label_values(&[model]),
+        }
+    }
+}
+
+pub fn record_response(status: StatusCode, latency: Duration) {
+    HTTP_RESPONSES.with_label_values(&[status.as_str()]).inc();
+    HTTP_RESPONSE_LATENCY
+        .with_label_values(&[status.as_str()])
+        .observe(latency.as_secs_f64());
+}
+
+pub fn record_db_error(table: &str) {
+    DB_ERRORS.with_label_values(&[table]).inc();
+}
+
+/// Encodes all registered metrics in the Prometheus text format.
+pub fn gather() -> Result<String, prometheus::Error> {
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
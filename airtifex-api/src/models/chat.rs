@@ -1,28 +1,43 @@
 use crate::{
     id::Uuid,
-    models::{chat_entry::ChatEntry, Error, Result},
+    models::{chat_entry::ChatEntry, listing, Error, Result},
     DbPool,
 };
-use airtifex_core::llm::{InferenceSettings, UserChatCounters};
+use airtifex_core::llm::{ChatListQuery, InferenceSettings, UserChatCounters};
 
 use serde::{Deserialize, Serialize};
 use sqlx::Row;
 use thiserror::Error as ErrorType;
 
+/// Full-text search over the titles and the messages of the chats, bound after the other
+/// parameters.
+#[cfg(feature = "postgres")]
+const SEARCH: &str = "(search @@ plainto_tsquery('english', $7)
+    OR id IN (SELECT chat_id FROM chat_entries WHERE search @@ plainto_tsquery('english', $7)))";
+#[cfg(not(feature = "postgres"))]
+const SEARCH: &str = "id IN (SELECT id FROM chats_fts WHERE chats_fts MATCH $7
+    UNION SELECT chat_id FROM chat_entries_fts WHERE chat_entries_fts MATCH $7)";
+
+/// Comparison that treats two NULL values as equal.
+#[cfg(feature = "postgres")]
+const IS_SAME_AS: &str = "IS NOT DISTINCT FROM";
+#[cfg(not(feature = "postgres"))]
+const IS_SAME_AS: &str = "IS";
+
 #[derive(Debug, ErrorType)]
 pub enum ChatError {
     #[error("failed to create a chat session - {0}")]
-    CreateError(sqlx::Error),
+    CreateError(#[source] sqlx::Error),
     #[error("failed to inspect a chat session - {0}")]
-    InspectError(sqlx::Error),
+    InspectError(#[source] sqlx::Error),
     #[error("failed to delete a chat session - {0}")]
-    DeleteError(sqlx::Error),
+    DeleteError(#[source] sqlx::Error),
     #[error("failed to list chat sessions - {0}")]
-    ListChatsError(sqlx::Error),
+    ListChatsError(#[source] sqlx::Error),
     #[error("failed to aquire chat counters - {0}")]
-    CountersError(sqlx::Error),
+    CountersError(#[source] sqlx::Error),
     #[error("failed to update a chat - {0}")]
-    UpdateError(sqlx::Error),
+    UpdateError(#[source] sqlx::Error),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, sqlx::FromRow)]
@@ -71,6 +86,17 @@ impl Chat {
     pub fn username(&self) -> &str {
         &self.username
     }
+    pub fn settings(&self) -> InferenceSettings {
+        InferenceSettings {
+            num_predict: self.num_predict.map(|n| n as usize),
+            system_prompt: self.system_prompt.clone(),
+            n_batch: self.n_batch.map(|n| n as usize),
+            top_k: self.top_k.map(|n| n as usize),
+            top_p: self.top_p,
+            repeat_penalty: self.repeat_penalty,
+            temp: self.temp,
+        }
+    }
 }
 
 impl Chat {
@@ -121,40 +147,115 @@ impl Chat {
             .map_err(Error::from)
     }
 
-    pub async fn get_chat_for_user(db: &DbPool, username: &str, chat_id: &Uuid) -> Result<Self> {
+    pub async fn get(db: &DbPool, chat_id: &Uuid) -> Result<Self> {
         sqlx::query_as(
             r#"
                     SELECT id, username, title, start_date, model, num_predict, system_prompt, n_batch, top_k, top_p, repeat_penalty, temp
                     FROM chats
-                    WHERE id = $1 AND username = $2
+                    WHERE id = $1
                 "#,
         )
         .bind(chat_id)
-        .bind(username)
         .fetch_one(db)
         .await
         .map_err(ChatError::InspectError)
         .map_err(Error::from)
     }
 
-    pub async fn list_chats_of_user(db: &DbPool, username: &str) -> Result<Vec<Self>> {
-        sqlx::query_as(
+    /// Lists a page of the chats of the user matching the query.
+    pub async fn list_chats_of_user(
+        db: &DbPool,
+        username: &str,
+        query: &ChatListQuery,
+    ) -> Result<Vec<Self>> {
+        let (limit, offset) = listing::limit_offset(query.page, query.page_size);
+        let (from, to) = listing::day_range(query.from, query.to);
+        let search = listing::search_query(query.search.as_deref());
+        let order_by = listing::order_by(query.order_by.unwrap_or_default().as_ref(), query.order);
+        let search_filter = if search.is_some() {
+            format!("AND {SEARCH}")
+        } else {
+            String::new()
+        };
+
+        let sql = format!(
             r#"
                     SELECT id, username, title, start_date, model, num_predict, system_prompt, n_batch, top_k, top_p, repeat_penalty, temp
                     FROM chats
-                    WHERE username = $1
-                    ORDER BY start_date
-                "#,
+                    WHERE   username = $1
+                        AND ($2 IS NULL OR model = $2)
+                        AND ($3 IS NULL OR start_date >= $3)
+                        AND ($4 IS NULL OR start_date < $4)
+                        {search_filter}
+                    {order_by}
+                    LIMIT $5
+                    OFFSET $6
+                "#
+        );
+
+        let mut statement = sqlx::query_as(&sql)
+            .bind(username)
+            .bind(query.model.as_deref())
+            .bind(from)
+            .bind(to)
+            .bind(limit)
+            .bind(offset);
+        if let Some(search) = search {
+            statement = statement.bind(search);
+        }
+        statement
+            .fetch_all(db)
+            .await
+            .map_err(ChatError::ListChatsError)
+            .map_err(Error::from)
+    }
+
+    pub async fn list_entries(db: &DbPool, id: &Uuid, username: &str) -> Result<Vec<ChatEntry>> {
+        ChatEntry::get_chat_entries(db, id, username).await
+    }
+
+    /// Selects the branch of the conversation ending with the entry, new prompts continue from it.
+    pub async fn update_active_entry(db: &DbPool, id: &Uuid, entry_id: &Uuid) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE chats
+            SET active_entry_id = $1
+            WHERE id = $2
+            "#,
         )
-        .bind(username)
-        .fetch_all(db)
+        .bind(entry_id)
+        .bind(id)
+        .execute(db)
         .await
-        .map_err(ChatError::ListChatsError)
+        .map(|_| ())
+        .map_err(ChatError::UpdateError)
         .map_err(Error::from)
     }
 
-    pub async fn list_entries(db: &DbPool, id: &Uuid, username: &str) -> Result<Vec<ChatEntry>> {
-        ChatEntry::get_chat_entries(db, id, username).await
+    /// Selects the branch ending with `entry_id` only if the active entry is still `from`, so that
+    /// a reply finishing after the user switched to another branch doesn't switch back. Returns
+    /// whether the active entry was updated.
+    pub async fn advance_active_entry(
+        db: &DbPool,
+        id: &Uuid,
+        from: Option<Uuid>,
+        entry_id: &Uuid,
+    ) -> Result<bool> {
+        sqlx::query(&format!(
+            r#"
+            UPDATE chats
+            SET active_entry_id = $1
+            WHERE id = $2 AND active_entry_id {IS_SAME_AS} $3
+            "#
+        ))
+        .bind(entry_id)
+        .bind(id)
+        .bind(from)
+        .execute(db)
+        .await
+        .map(|result| result.rows_affected() > 0)
+        .map_err(ChatError::UpdateError)
+        .map_err(Error::from)
     }
 
     pub async fn update_title(db: &DbPool, id: &Uuid, title: &str) -> Result<()> {
@@ -11,13 +11,13 @@ use thiserror::Error as ErrorType;
 #[derive(Debug, ErrorType)]
 pub enum ChatEntryError {
     #[error("failed to create a chat entry - {0}")]
-    CreateError(sqlx::Error),
+    CreateError(#[source] sqlx::Error),
     #[error("failed to inspect a chat entry - {0}")]
-    InspectError(sqlx::Error),
+    InspectError(#[source] sqlx::Error),
     #[error("failed to delete a chat entry - {0}")]
-    DeleteError(sqlx::Error),
+    DeleteError(#[source] sqlx::Error),
     #[error("failed to list chat entries - {0}")]
-    ListChatsError(sqlx::Error),
+    ListChatsError(#[source] sqlx::Error),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, sqlx::FromRow)]
@@ -28,25 +28,30 @@ pub struct ChatEntry {
     pub entry_type: ChatEntryType,
     pub content: String,
     pub entry_date: chrono::DateTime<chrono::Utc>,
+    /// The entry this one follows, entries with the same parent are alternative branches of the
+    /// conversation
+    pub parent_entry_id: Option<Uuid>,
 }
 
 impl ChatEntry {
-    pub fn new_user(chat_id: Uuid, content: String) -> Self {
+    pub fn new_user(chat_id: Uuid, parent_entry_id: Option<Uuid>, content: String) -> Self {
         Self {
             entry_id: Uuid::new_v4(),
             chat_id,
             entry_type: ChatEntryType::User,
             content,
             entry_date: chrono::Utc::now(),
+            parent_entry_id,
         }
     }
-    pub fn new_bot(chat_id: Uuid, content: String) -> Self {
+    pub fn new_bot(chat_id: Uuid, parent_entry_id: Option<Uuid>, content: String) -> Self {
         Self {
             entry_id: Uuid::new_v4(),
             chat_id,
             entry_type: ChatEntryType::Bot,
             content,
             entry_date: chrono::Utc::now(),
+            parent_entry_id,
         }
     }
 }
@@ -56,8 +61,8 @@ impl ChatEntry {
         sqlx::query(
             r#"
             INSERT INTO chat_entries
-                    (entry_id, chat_id, entry_type, content, entry_date)
-            VALUES  ($1, $2, $3, $4, $5)
+                    (entry_id, chat_id, entry_type, content, entry_date, parent_entry_id)
+            VALUES  ($1, $2, $3, $4, $5, $6)
             "#,
         )
         .bind(self.entry_id)
@@ -65,6 +70,7 @@ impl ChatEntry {
         .bind(self.entry_type)
         .bind(&self.content)
         .bind(self.entry_date)
+        .bind(self.parent_entry_id)
         .execute(db)
         .await
         .map(|_| ())
@@ -92,6 +98,24 @@ impl ChatEntry {
             .map_err(Error::from)
     }
 
+    pub async fn get(db: &DbPool, chat_id: &Uuid, entry_id: &Uuid) -> Result<Self> {
+        sqlx::query_as(
+            r#"
+            SELECT entry_id, chat_id, entry_type, content, entry_date, parent_entry_id
+            FROM chat_entries
+            WHERE entry_id = $1 AND chat_id = $2
+            "#,
+        )
+        .bind(entry_id)
+        .bind(chat_id)
+        .fetch_one(db)
+        .await
+        .map_err(ChatEntryError::InspectError)
+        .map_err(Error::from)
+    }
+
+    /// Returns the active branch of the chat, from the first entry to the active entry of the
+    /// chat.
     pub async fn get_chat_entries(
         db: &DbPool,
         chat_id: &Uuid,
@@ -99,11 +123,20 @@ impl ChatEntry {
     ) -> Result<Vec<Self>> {
         sqlx::query_as(
             r#"
-            SELECT entry_id, chat_id, entry_type, content, entry_date
+            WITH RECURSIVE branch (entry_id, parent_entry_id) AS (
+                SELECT e.entry_id, e.parent_entry_id
+                FROM chat_entries e
+                INNER JOIN chats c ON c.id = e.chat_id
+                WHERE c.id = $1 AND c.username = $2 AND e.entry_id = c.active_entry_id
+                UNION ALL
+                SELECT e.entry_id, e.parent_entry_id
+                FROM chat_entries e
+                INNER JOIN branch b ON e.entry_id = b.parent_entry_id
+            )
+            SELECT entry_id, chat_id, entry_type, content, entry_date, parent_entry_id
             FROM chat_entries
-            INNER JOIN chats c ON c.id = $1
-            WHERE chat_id = $1 AND c.username = $2
-            ORDER BY entry_date
+            WHERE entry_id IN (SELECT entry_id FROM branch)
+            ORDER BY entry_date, entry_type
             "#,
         )
         .bind(chat_id)
@@ -113,4 +146,54 @@ impl ChatEntry {
         .map_err(ChatEntryError::ListChatsError)
         .map_err(Error::from)
     }
+
+    /// Returns the entries of all branches of the chat.
+    pub async fn get_all_chat_entries(db: &DbPool, chat_id: &Uuid) -> Result<Vec<Self>> {
+        sqlx::query_as(
+            r#"
+            SELECT entry_id, chat_id, entry_type, content, entry_date, parent_entry_id
+            FROM chat_entries
+            WHERE chat_id = $1
+            ORDER BY entry_date, entry_type
+            "#,
+        )
+        .bind(chat_id)
+        .fetch_all(db)
+        .await
+        .map_err(ChatEntryError::ListChatsError)
+        .map_err(Error::from)
+    }
+
+    /// Returns the most recent entry following the entry, that is the last entry of the most
+    /// recent branch continuing from it.
+    pub async fn get_latest_descendant(
+        db: &DbPool,
+        chat_id: &Uuid,
+        entry_id: &Uuid,
+    ) -> Result<Self> {
+        sqlx::query_as(
+            r#"
+            WITH RECURSIVE descendants (entry_id) AS (
+                SELECT entry_id
+                FROM chat_entries
+                WHERE entry_id = $1 AND chat_id = $2
+                UNION ALL
+                SELECT e.entry_id
+                FROM chat_entries e
+                INNER JOIN descendants d ON e.parent_entry_id = d.entry_id
+            )
+            SELECT entry_id, chat_id, entry_type, content, entry_date, parent_entry_id
+            FROM chat_entries
+            WHERE entry_id IN (SELECT entry_id FROM descendants)
+            ORDER BY entry_date DESC, entry_type DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(entry_id)
+        .bind(chat_id)
+        .fetch_one(db)
+        .await
+        .map_err(ChatEntryError::InspectError)
+        .map_err(Error::from)
+    }
 }
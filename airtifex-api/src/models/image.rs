@@ -1,24 +1,31 @@
 use crate::{
     id::Uuid,
-    models::{Error, Result},
+    models::{listing, Error, Result},
     DbPool,
 };
 
+use airtifex_core::image::{ImageListQuery, ImageScheduler, ImageStatus, ImageVisibility};
 use serde::{Deserialize, Serialize};
 use thiserror::Error as ErrorType;
 
+/// Full-text search over the prompts, bound after the other parameters.
+#[cfg(feature = "postgres")]
+const SEARCH: &str = "search @@ plainto_tsquery('english', $8)";
+#[cfg(not(feature = "postgres"))]
+const SEARCH: &str = "id IN (SELECT id FROM images_fts WHERE images_fts MATCH $8)";
+
 #[derive(Debug, ErrorType)]
 pub enum ImageError {
     #[error("failed to create a image - {0}")]
-    CreateError(sqlx::Error),
+    CreateError(#[source] sqlx::Error),
     #[error("failed to inspect a image - {0}")]
-    InspectError(sqlx::Error),
+    InspectError(#[source] sqlx::Error),
     #[error("failed to delete a image - {0}")]
-    DeleteError(sqlx::Error),
+    DeleteError(#[source] sqlx::Error),
     #[error("failed to list images - {0}")]
-    ListImagesError(sqlx::Error),
+    ListImagesError(#[source] sqlx::Error),
     #[error("failed to update a image - {0}")]
-    UpdateError(sqlx::Error),
+    UpdateError(#[source] sqlx::Error),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, sqlx::FromRow)]
@@ -29,18 +36,33 @@ pub struct Image {
     pub width: i64,
     pub height: i64,
     pub prompt: String,
-    pub input_image: Option<Vec<u8>>,
-    pub mask: Option<Vec<u8>>,
-    pub thumbnail: Option<Vec<u8>>,
+    pub negative_prompt: Option<String>,
+    /// Storage keys of the uploaded input image, mask and of the thumbnail
+    pub input_image_key: Option<String>,
+    pub mask_key: Option<String>,
+    pub thumbnail_key: Option<String>,
     pub strength: Option<f64>,
     pub n_steps: i64,
     pub seed: i64,
     pub num_samples: i64,
     pub guidance_scale: f64,
+    pub scheduler: String,
     pub processing: bool,
+    pub status: String,
+    pub error: Option<String>,
+    pub visibility: String,
     pub create_date: chrono::DateTime<chrono::Utc>,
 }
 
+/// Data of an image stored in the database before images were moved to the storage.
+#[derive(Debug, sqlx::FromRow)]
+pub struct ImageBlobs {
+    pub id: Uuid,
+    pub input_image: Option<Vec<u8>>,
+    pub mask: Option<Vec<u8>>,
+    pub thumbnail: Option<Vec<u8>>,
+}
+
 impl Image {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
@@ -49,14 +71,13 @@ impl Image {
         width: i64,
         height: i64,
         prompt: String,
-        input_image: Option<Vec<u8>>,
-        mask: Option<Vec<u8>>,
-        thumbnail: Option<Vec<u8>>,
+        negative_prompt: Option<String>,
         strength: Option<f64>,
         n_steps: i64,
         seed: i64,
         num_samples: i64,
         guidance_scale: f64,
+        scheduler: ImageScheduler,
     ) -> Self {
         Self {
             id: Uuid::new_v4(),
@@ -65,15 +86,20 @@ impl Image {
             width,
             height,
             prompt,
-            input_image,
-            mask,
-            thumbnail,
+            negative_prompt,
+            input_image_key: None,
+            mask_key: None,
+            thumbnail_key: None,
             strength,
             n_steps,
             seed,
             num_samples,
             guidance_scale,
+            scheduler: scheduler.to_str().to_string(),
             processing: true,
+            status: ImageStatus::Queued.to_str().to_string(),
+            error: None,
+            visibility: ImageVisibility::Private.to_str().to_string(),
             create_date: chrono::Utc::now(),
         }
     }
@@ -84,8 +110,8 @@ impl Image {
         sqlx::query(
             r#"
             INSERT INTO images
-                    (id, user_id, model, width, height, prompt, input_image, mask, thumbnail, strength, n_steps, seed, num_samples, guidance_scale, processing, create_date)
-            VALUES  ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
+                    (id, user_id, model, width, height, prompt, negative_prompt, input_image_key, mask_key, thumbnail_key, strength, n_steps, seed, num_samples, guidance_scale, scheduler, processing, status, visibility, create_date)
+            VALUES  ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20)
             "#,
         )
         .bind(self.id)
@@ -94,15 +120,19 @@ impl Image {
         .bind(self.width)
         .bind(self.height)
         .bind(&self.prompt)
-        .bind(&self.input_image)
-        .bind(&self.mask)
-        .bind(&self.thumbnail)
+        .bind(&self.negative_prompt)
+        .bind(&self.input_image_key)
+        .bind(&self.mask_key)
+        .bind(&self.thumbnail_key)
         .bind(self.strength)
         .bind(self.n_steps)
         .bind(self.seed)
         .bind(self.num_samples)
         .bind(self.guidance_scale)
+        .bind(&self.scheduler)
         .bind(self.processing)
+        .bind(&self.status)
+        .bind(&self.visibility)
         .bind(self.create_date)
         .execute(db)
         .await
@@ -111,23 +141,61 @@ impl Image {
         .map_err(Error::from)
     }
 
-    pub async fn list(db: &DbPool) -> Result<Vec<Self>> {
-        sqlx::query_as(
+    /// Lists a page of the images matching the query, images of all users are listed if
+    /// `user_id` is not set.
+    pub async fn list(
+        db: &DbPool,
+        user_id: Option<&Uuid>,
+        query: &ImageListQuery,
+    ) -> Result<Vec<Self>> {
+        let (limit, offset) = listing::limit_offset(query.page, query.page_size);
+        let (from, to) = listing::day_range(query.from, query.to);
+        let search = listing::search_query(query.search.as_deref());
+        let order_by = listing::order_by(query.order_by.unwrap_or_default().as_ref(), query.order);
+        let search_filter = if search.is_some() {
+            format!("AND {SEARCH}")
+        } else {
+            String::new()
+        };
+
+        let sql = format!(
             r#"
-            SELECT id, user_id, model, width, height, prompt, input_image, mask, thumbnail, strength, n_steps, seed, num_samples, guidance_scale, processing, create_date
+            SELECT id, user_id, model, width, height, prompt, negative_prompt, input_image_key, mask_key, thumbnail_key, strength, n_steps, seed, num_samples, guidance_scale, scheduler, processing, status, error, visibility, create_date
             FROM images
-            "#,
-        )
-        .fetch_all(db)
-        .await
-        .map_err(ImageError::ListImagesError)
-        .map_err(Error::from)
+            WHERE   ($1 IS NULL OR user_id = $1)
+                AND ($2 IS NULL OR model = $2)
+                AND ($3 IS NULL OR status = $3)
+                AND ($4 IS NULL OR create_date >= $4)
+                AND ($5 IS NULL OR create_date < $5)
+                {search_filter}
+            {order_by}
+            LIMIT $6
+            OFFSET $7
+            "#
+        );
+
+        let mut statement = sqlx::query_as(&sql)
+            .bind(user_id)
+            .bind(query.model.as_deref())
+            .bind(query.status.map(ImageStatus::to_str))
+            .bind(from)
+            .bind(to)
+            .bind(limit)
+            .bind(offset);
+        if let Some(search) = search {
+            statement = statement.bind(search);
+        }
+        statement
+            .fetch_all(db)
+            .await
+            .map_err(ImageError::ListImagesError)
+            .map_err(Error::from)
     }
 
     pub async fn get_by_id(db: &DbPool, id: &Uuid) -> Result<Self> {
         sqlx::query_as(
             r#"
-            SELECT id, user_id, model, width, height, prompt, input_image, mask, thumbnail, strength, n_steps, seed, num_samples, guidance_scale, processing, create_date
+            SELECT id, user_id, model, width, height, prompt, negative_prompt, input_image_key, mask_key, thumbnail_key, strength, n_steps, seed, num_samples, guidance_scale, scheduler, processing, status, error, visibility, create_date
             FROM images
             WHERE id = $1
             "#,
@@ -159,15 +227,63 @@ impl Image {
             .map_err(Error::from)
     }
 
-    pub async fn update_is_processing(db: &DbPool, id: &Uuid, processing: bool) -> Result<()> {
+    /// Updates the status keeping the processing flag in sync. Failed and cancelled images keep
+    /// their status so that a late update from the worker doesn't overwrite it, they can only be
+    /// queued again with [`Image::requeue`].
+    pub async fn update_status(db: &DbPool, id: &Uuid, status: ImageStatus) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE images
+            SET status = $1, processing = $2
+            WHERE id = $3 AND status NOT IN ($4, $5)
+            "#,
+        )
+        .bind(status.to_str())
+        .bind(status.is_processing())
+        .bind(id)
+        .bind(ImageStatus::Failed.to_str())
+        .bind(ImageStatus::Cancelled.to_str())
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(ImageError::UpdateError)
+        .map_err(Error::from)
+    }
+
+    /// Queues a failed or cancelled image again clearing the error of the failure and the
+    /// thumbnail that is generated again. Returns `false` if the image is in any other state.
+    pub async fn requeue(db: &DbPool, id: &Uuid) -> Result<bool> {
+        sqlx::query(
+            r#"
+            UPDATE images
+            SET status = $1, processing = true, error = NULL, thumbnail_key = NULL
+            WHERE id = $2 AND status IN ($3, $4)
+            "#,
+        )
+        .bind(ImageStatus::Queued.to_str())
+        .bind(id)
+        .bind(ImageStatus::Failed.to_str())
+        .bind(ImageStatus::Cancelled.to_str())
+        .execute(db)
+        .await
+        .map(|result| result.rows_affected() > 0)
+        .map_err(ImageError::UpdateError)
+        .map_err(Error::from)
+    }
+
+    pub async fn update_visibility(
+        db: &DbPool,
+        id: &Uuid,
+        visibility: ImageVisibility,
+    ) -> Result<()> {
         sqlx::query(
             r#"
             UPDATE images
-            SET processing = $1
+            SET visibility = $1
             WHERE id = $2
             "#,
         )
-        .bind(processing)
+        .bind(visibility.to_str())
         .bind(id)
         .execute(db)
         .await
@@ -176,15 +292,84 @@ impl Image {
         .map_err(Error::from)
     }
 
-    pub async fn update_thumbnail(db: &DbPool, id: &Uuid, thumbnail: &[u8]) -> Result<()> {
+    /// Marks the image as failed with the reason of the failure. A cancelled image stays
+    /// cancelled when its job fails afterwards.
+    pub async fn update_failed(db: &DbPool, id: &Uuid, error: &str) -> Result<()> {
         sqlx::query(
             r#"
             UPDATE images
-            SET thumbnail = $1
+            SET status = $1, processing = false, error = $2
+            WHERE id = $3 AND status <> $4
+            "#,
+        )
+        .bind(ImageStatus::Failed.to_str())
+        .bind(error)
+        .bind(id)
+        .bind(ImageStatus::Cancelled.to_str())
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(ImageError::UpdateError)
+        .map_err(Error::from)
+    }
+
+    pub async fn update_thumbnail(db: &DbPool, id: &Uuid, thumbnail_key: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE images
+            SET thumbnail_key = $1
             WHERE id = $2
             "#,
         )
-        .bind(thumbnail)
+        .bind(thumbnail_key)
+        .bind(id)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(ImageError::UpdateError)
+        .map_err(Error::from)
+    }
+
+    /// Returns images that still have their data stored in the database.
+    pub async fn list_blobs(db: &DbPool, limit: i64) -> Result<Vec<ImageBlobs>> {
+        sqlx::query_as(
+            r#"
+            SELECT id, input_image, mask, thumbnail
+            FROM images
+            WHERE input_image IS NOT NULL OR mask IS NOT NULL OR thumbnail IS NOT NULL
+            LIMIT $1
+            "#,
+        )
+        .bind(limit)
+        .fetch_all(db)
+        .await
+        .map_err(ImageError::ListImagesError)
+        .map_err(Error::from)
+    }
+
+    /// Replaces the data stored in the database with the keys of the moved objects.
+    pub async fn update_blob_keys(
+        db: &DbPool,
+        id: &Uuid,
+        input_image_key: Option<String>,
+        mask_key: Option<String>,
+        thumbnail_key: Option<String>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE images
+            SET input_image_key = COALESCE($1, input_image_key),
+                mask_key = COALESCE($2, mask_key),
+                thumbnail_key = COALESCE($3, thumbnail_key),
+                input_image = NULL,
+                mask = NULL,
+                thumbnail = NULL
+            WHERE id = $4
+            "#,
+        )
+        .bind(input_image_key)
+        .bind(mask_key)
+        .bind(thumbnail_key)
         .bind(id)
         .execute(db)
         .await
@@ -11,15 +11,15 @@ use thiserror::Error as ErrorType;
 #[derive(Debug, ErrorType)]
 pub enum ImageModelError {
     #[error("failed to create image model- {0}")]
-    CreateError(sqlx::Error),
+    CreateError(#[source] sqlx::Error),
     #[error("failed to inspect image model- {0}")]
-    InspectError(sqlx::Error),
+    InspectError(#[source] sqlx::Error),
     #[error("failed to delete image model- {0}")]
-    DeleteError(sqlx::Error),
+    DeleteError(#[source] sqlx::Error),
     #[error("failed to list image models - {0}")]
-    ListImageModelsError(sqlx::Error),
+    ListImageModelsError(#[source] sqlx::Error),
     #[error("failed to update image model - {0}")]
-    UpdateError(sqlx::Error),
+    UpdateError(#[source] sqlx::Error),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, sqlx::FromRow)]
@@ -10,15 +10,17 @@ use thiserror::Error as ErrorType;
 #[derive(Debug, ErrorType)]
 pub enum ImageSampleError {
     #[error("failed to create a image sample - {0}")]
-    CreateError(sqlx::Error),
+    CreateError(#[source] sqlx::Error),
     #[error("failed to inspect a image sample - {0}")]
-    InspectError(sqlx::Error),
+    InspectError(#[source] sqlx::Error),
     #[error("failed to delete a image sample - {0}")]
-    DeleteError(sqlx::Error),
+    DeleteError(#[source] sqlx::Error),
     #[error("failed to get image sample - {0}")]
-    GetImageError(sqlx::Error),
+    GetImageError(#[source] sqlx::Error),
     #[error("failed to list image samples - {0}")]
-    ListImagesError(sqlx::Error),
+    ListImagesError(#[source] sqlx::Error),
+    #[error("failed to update image sample - {0}")]
+    UpdateError(#[source] sqlx::Error),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, sqlx::FromRow)]
@@ -27,33 +29,43 @@ pub struct ImageSample {
     pub sample_id: Uuid,
     pub image_id: Uuid,
     pub n: i32,
-    pub data: Vec<u8>,
+    /// Storage key of the generated image
+    pub data_key: String,
 }
 
 impl ImageSample {
-    pub fn new(image_id: Uuid, n: i32, data: Vec<u8>) -> Self {
+    pub fn new(image_id: Uuid, n: i32, data_key: String) -> Self {
         Self {
             sample_id: Uuid::new_v4(),
             image_id,
             n,
-            data,
+            data_key,
         }
     }
 }
 
+/// A sample stored in the database before images were moved to the storage.
+#[derive(Debug, sqlx::FromRow)]
+pub struct SampleBlob {
+    pub sample_id: Uuid,
+    pub image_id: Uuid,
+    pub n: i32,
+    pub data: Vec<u8>,
+}
+
 impl ImageSample {
     pub async fn create(&self, db: &DbPool) -> Result<()> {
         sqlx::query(
             r#"
             INSERT INTO image_samples
-                    (sample_id, image_id, n, data)
+                    (sample_id, image_id, n, data_key)
             VALUES  ($1, $2, $3, $4)
             "#,
         )
         .bind(self.sample_id)
         .bind(self.image_id)
         .bind(self.n)
-        .bind(&self.data)
+        .bind(&self.data_key)
         .execute(db)
         .await
         .map(|_| ())
@@ -81,10 +93,26 @@ impl ImageSample {
             .map_err(Error::from)
     }
 
+    /// Deletes all samples of the image.
+    pub async fn delete_by_image(db: &DbPool, image_id: &Uuid) -> Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM image_samples
+            WHERE image_id = $1
+            "#,
+        )
+        .bind(image_id)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(ImageSampleError::DeleteError)
+        .map_err(Error::from)
+    }
+
     pub async fn get_sample(db: &DbPool, image_id: &Uuid, n: i32) -> Result<Self> {
         sqlx::query_as(
             r#"
-            SELECT sample_id, image_id, n, data
+            SELECT sample_id, image_id, n, data_key
             FROM image_samples
             WHERE image_id = $1 AND n = $2
             "#,
@@ -100,7 +128,7 @@ impl ImageSample {
     pub async fn get_image_samples(db: &DbPool, image_id: &Uuid) -> Result<Vec<Self>> {
         sqlx::query_as(
             r#"
-            SELECT sample_id, image_id, n, data
+            SELECT sample_id, image_id, n, data_key
             FROM image_samples
             INNER JOIN images i ON i.id = $1
             WHERE image_id = $1
@@ -113,4 +141,39 @@ impl ImageSample {
         .map_err(ImageSampleError::ListImagesError)
         .map_err(Error::from)
     }
+
+    /// Returns samples that still have their data stored in the database.
+    pub async fn list_blobs(db: &DbPool, limit: i64) -> Result<Vec<SampleBlob>> {
+        sqlx::query_as(
+            r#"
+            SELECT sample_id, image_id, n, data
+            FROM image_samples
+            WHERE data IS NOT NULL
+            LIMIT $1
+            "#,
+        )
+        .bind(limit)
+        .fetch_all(db)
+        .await
+        .map_err(ImageSampleError::ListImagesError)
+        .map_err(Error::from)
+    }
+
+    /// Replaces the data stored in the database with the key of the moved object.
+    pub async fn update_data_key(db: &DbPool, id: &Uuid, data_key: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE image_samples
+            SET data_key = $1, data = NULL
+            WHERE sample_id = $2
+            "#,
+        )
+        .bind(data_key)
+        .bind(id)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(ImageSampleError::UpdateError)
+        .map_err(Error::from)
+    }
 }
@@ -0,0 +1,61 @@
+//! Pagination, date filters and full-text search shared by the listings of chats, prompts and
+//! images.
+
+use airtifex_core::query::SortOrder;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+
+pub const DEFAULT_PAGE_SIZE: u32 = 25;
+pub const MAX_PAGE_SIZE: u32 = 100;
+
+/// Returns the `LIMIT` and `OFFSET` of a page, pages start at 1.
+pub fn limit_offset(page: Option<u32>, page_size: Option<u32>) -> (i64, i64) {
+    let page = page.unwrap_or(1).max(1);
+    let page_size = page_size
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    (page_size as i64, (page - 1) as i64 * page_size as i64)
+}
+
+/// Converts an inclusive range of days to the bounds of a `date >= from AND date < to` filter.
+pub fn day_range(
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+    let start_of = |day: NaiveDate| {
+        day.and_hms_opt(0, 0, 0)
+            .map(|date| DateTime::<Utc>::from_utc(date, Utc))
+    };
+    (
+        from.and_then(start_of),
+        to.and_then(|day| start_of(day).map(|date| date + Duration::days(1))),
+    )
+}
+
+/// Returns the `ORDER BY` clause sorting by the column, ties are broken by the ID so that pages
+/// don't overlap.
+pub fn order_by(column: &str, order: Option<SortOrder>) -> String {
+    let order = order.unwrap_or_default();
+    format!("ORDER BY {column} {0}, id {0}", order.as_ref())
+}
+
+/// Converts the search text of the user to a full-text query, blank searches are ignored.
+#[cfg(feature = "postgres")]
+pub fn search_query(search: Option<&str>) -> Option<String> {
+    // passed to `plainto_tsquery` that doesn't interpret operators
+    search
+        .map(str::trim)
+        .filter(|search| !search.is_empty())
+        .map(str::to_string)
+}
+
+/// Converts the search text of the user to a full-text query, blank searches are ignored.
+#[cfg(not(feature = "postgres"))]
+pub fn search_query(search: Option<&str>) -> Option<String> {
+    // every word is quoted so that FTS5 doesn't interpret operators or fail on the syntax
+    let terms = search?
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect::<Vec<_>>();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
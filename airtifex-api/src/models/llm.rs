@@ -10,15 +10,15 @@ use thiserror::Error as ErrorType;
 #[derive(Debug, ErrorType)]
 pub enum LlmError {
     #[error("failed to create a model- {0}")]
-    CreateError(sqlx::Error),
+    CreateError(#[source] sqlx::Error),
     #[error("failed to inspect a model- {0}")]
-    InspectError(sqlx::Error),
+    InspectError(#[source] sqlx::Error),
     #[error("failed to delete a model- {0}")]
-    DeleteError(sqlx::Error),
+    DeleteError(#[source] sqlx::Error),
     #[error("failed to list models - {0}")]
-    ListLargeLanguageModelsError(sqlx::Error),
+    ListLargeLanguageModelsError(#[source] sqlx::Error),
     #[error("failed to update a model - {0}")]
-    UpdateError(sqlx::Error),
+    UpdateError(#[source] sqlx::Error),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, sqlx::FromRow)]
@@ -3,8 +3,11 @@ pub mod chat_entry;
 pub mod image;
 pub mod image_model;
 pub mod image_sample;
+pub mod listing;
 pub mod llm;
 pub mod prompt;
+pub mod usage;
+pub mod usage_counter;
 pub mod user;
 
 use thiserror::Error;
@@ -16,23 +19,69 @@ pub enum Error {
     #[error(transparent)]
     AuthenticationError(#[from] user::AuthenticationError),
     #[error(transparent)]
-    UserError(#[from] user::UserError),
+    UserError(user::UserError),
     #[error(transparent)]
-    ChatError(#[from] chat::ChatError),
+    ChatError(chat::ChatError),
     #[error(transparent)]
-    ImageError(#[from] image::ImageError),
+    ImageError(image::ImageError),
     #[error(transparent)]
-    ImageModelError(#[from] image_model::ImageModelError),
+    ImageModelError(image_model::ImageModelError),
     #[error(transparent)]
-    LlmError(#[from] llm::LlmError),
+    LlmError(llm::LlmError),
     #[error(transparent)]
-    PromptError(#[from] prompt::PromptError),
+    PromptError(prompt::PromptError),
     #[error(transparent)]
-    ChatEntryError(#[from] chat_entry::ChatEntryError),
+    ChatEntryError(chat_entry::ChatEntryError),
     #[error(transparent)]
-    ImageSampleError(#[from] image_sample::ImageSampleError),
+    ImageSampleError(image_sample::ImageSampleError),
+    #[error(transparent)]
+    UsageError(usage::UsageError),
+    #[error(transparent)]
+    UsageCounterError(usage_counter::UsageCounterError),
 }
 
+/// Returns `true` if the database failed to execute the operation, as opposed to the operation
+/// not finding a row or the error not coming from the database at all.
+fn is_db_failure(error: &dyn std::error::Error) -> bool {
+    match error
+        .source()
+        .and_then(|source| source.downcast_ref::<sqlx::Error>())
+    {
+        Some(sqlx::Error::RowNotFound) | None => false,
+        Some(_) => true,
+    }
+}
+
+/// Implements the conversion from errors of database operations and counts the failures of the
+/// database in the metrics.
+macro_rules! impl_from_db_error {
+    ($($variant:ident($error:ty) => $table:literal),* $(,)?) => {
+        $(
+            impl From<$error> for Error {
+                fn from(error: $error) -> Self {
+                    if is_db_failure(&error) {
+                        crate::metrics::record_db_error($table);
+                    }
+                    Error::$variant(error)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_db_error!(
+    UserError(user::UserError) => "users",
+    ChatError(chat::ChatError) => "chats",
+    ImageError(image::ImageError) => "images",
+    ImageModelError(image_model::ImageModelError) => "image_models",
+    LlmError(llm::LlmError) => "llm_models",
+    PromptError(prompt::PromptError) => "prompts",
+    ChatEntryError(chat_entry::ChatEntryError) => "chat_entries",
+    ImageSampleError(image_sample::ImageSampleError) => "image_samples",
+    UsageError(usage::UsageError) => "usage",
+    UsageCounterError(usage_counter::UsageCounterError) => "usage_counters",
+);
+
 pub async fn run_pragma(db: &crate::DbPool) -> crate::Result<()> {
     sqlx::query(
         r#"
@@ -44,3 +93,12 @@ pub async fn run_pragma(db: &crate::DbPool) -> crate::Result<()> {
     .map(|_| ())
     .map_err(crate::Error::from)
 }
+
+/// Verifies that the database can be reached.
+pub async fn ping(db: &crate::DbPool) -> crate::Result<()> {
+    sqlx::query("SELECT 1")
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(crate::Error::from)
+}
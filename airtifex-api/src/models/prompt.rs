@@ -1,25 +1,31 @@
 use crate::{
     id::Uuid,
-    models::{Error, Result},
+    models::{listing, Error, Result},
     DbPool,
 };
-use airtifex_core::llm::InferenceSettings;
+use airtifex_core::llm::{InferenceSettings, PromptListQuery};
 
 use serde::{Deserialize, Serialize};
 use thiserror::Error as ErrorType;
 
+/// Full-text search over the prompts and responses, bound after the other parameters.
+#[cfg(feature = "postgres")]
+const SEARCH: &str = "search @@ plainto_tsquery('english', $7)";
+#[cfg(not(feature = "postgres"))]
+const SEARCH: &str = "id IN (SELECT id FROM prompts_fts WHERE prompts_fts MATCH $7)";
+
 #[derive(Debug, ErrorType)]
 pub enum PromptError {
     #[error("failed to create a prompt - {0}")]
-    Create(sqlx::Error),
+    Create(#[source] sqlx::Error),
     #[error("failed to inspect a prompt - {0}")]
-    Inspect(sqlx::Error),
+    Inspect(#[source] sqlx::Error),
     #[error("failed to delete a prompt - {0}")]
-    Delete(sqlx::Error),
+    Delete(#[source] sqlx::Error),
     #[error("failed to list prompts - {0}")]
-    List(sqlx::Error),
+    List(#[source] sqlx::Error),
     #[error("failed to update a prompt - {0}")]
-    Update(sqlx::Error),
+    Update(#[source] sqlx::Error),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, sqlx::FromRow)]
@@ -133,19 +139,51 @@ impl Prompt {
         .map_err(Error::from)
     }
 
-    pub async fn list_prompts_of_user(db: &DbPool, username: &str) -> Result<Vec<Self>> {
-        sqlx::query_as(
+    /// Lists a page of the prompts of the user matching the query.
+    pub async fn list_prompts_of_user(
+        db: &DbPool,
+        username: &str,
+        query: &PromptListQuery,
+    ) -> Result<Vec<Self>> {
+        let (limit, offset) = listing::limit_offset(query.page, query.page_size);
+        let (from, to) = listing::day_range(query.from, query.to);
+        let search = listing::search_query(query.search.as_deref());
+        let order_by = listing::order_by(query.order_by.unwrap_or_default().as_ref(), query.order);
+        let search_filter = if search.is_some() {
+            format!("AND {SEARCH}")
+        } else {
+            String::new()
+        };
+
+        let sql = format!(
             r#"
                     SELECT id, username, prompt, response, date, model, num_predict, n_batch, top_k, top_p, repeat_penalty, temp
                     FROM prompts
-                    WHERE username = $1
-                    ORDER BY date
-                "#,
-        )
-        .bind(username)
-        .fetch_all(db)
-        .await
-        .map_err(PromptError::List)
-        .map_err(Error::from)
+                    WHERE   username = $1
+                        AND ($2 IS NULL OR model = $2)
+                        AND ($3 IS NULL OR date >= $3)
+                        AND ($4 IS NULL OR date < $4)
+                        {search_filter}
+                    {order_by}
+                    LIMIT $5
+                    OFFSET $6
+                "#
+        );
+
+        let mut statement = sqlx::query_as(&sql)
+            .bind(username)
+            .bind(query.model.as_deref())
+            .bind(from)
+            .bind(to)
+            .bind(limit)
+            .bind(offset);
+        if let Some(search) = search {
+            statement = statement.bind(search);
+        }
+        statement
+            .fetch_all(db)
+            .await
+            .map_err(PromptError::List)
+            .map_err(Error::from)
     }
 }
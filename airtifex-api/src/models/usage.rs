@@ -0,0 +1,185 @@
+use crate::{
+    id::Uuid,
+    models::{Error, Result},
+    DbPool,
+};
+use airtifex_core::usage::{UsageQuery, UsageReportEntry};
+
+use sqlx::Row;
+use std::time::Duration;
+use thiserror::Error as ErrorType;
+
+#[derive(Debug, ErrorType)]
+pub enum UsageError {
+    #[error("failed to record usage - {0}")]
+    Create(#[source] sqlx::Error),
+    #[error("failed to aggregate usage - {0}")]
+    Report(#[source] sqlx::Error),
+}
+
+/// Day of the `date` column formatted as `YYYY-MM-DD`.
+#[cfg(feature = "postgres")]
+const DAY: &str = "to_char(date, 'YYYY-MM-DD')";
+#[cfg(not(feature = "postgres"))]
+const DAY: &str = "strftime('%Y-%m-%d', date)";
+
+/// Resources used by a single finished inference or image generation job.
+#[derive(Clone, Debug)]
+pub struct Usage {
+    /// ID of the inference or prompt, image jobs get a new ID every time they run
+    pub id: Uuid,
+    pub username: String,
+    pub model: String,
+    pub chat_id: Option<Uuid>,
+    pub image_id: Option<Uuid>,
+    pub date: chrono::DateTime<chrono::Utc>,
+    pub prompt_tokens: i64,
+    pub generated_tokens: i64,
+    pub n_steps: i64,
+    pub num_samples: i64,
+    /// Processing time in seconds
+    pub wall_time: f64,
+    pub tokens_per_second: f64,
+}
+
+impl Usage {
+    pub fn inference(
+        id: Uuid,
+        username: String,
+        model: String,
+        chat_id: Option<Uuid>,
+        prompt_tokens: usize,
+        generated_tokens: usize,
+        wall_time: Duration,
+    ) -> Self {
+        let wall_time = wall_time.as_secs_f64();
+        Self {
+            id,
+            username,
+            model,
+            chat_id,
+            image_id: None,
+            date: chrono::Utc::now(),
+            prompt_tokens: prompt_tokens as i64,
+            generated_tokens: generated_tokens as i64,
+            n_steps: 0,
+            num_samples: 0,
+            wall_time,
+            tokens_per_second: if wall_time > 0. {
+                generated_tokens as f64 / wall_time
+            } else {
+                0.
+            },
+        }
+    }
+
+    pub fn image(
+        image_id: Uuid,
+        username: String,
+        model: String,
+        n_steps: usize,
+        num_samples: i64,
+        wall_time: Duration,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            username,
+            model,
+            chat_id: None,
+            image_id: Some(image_id),
+            date: chrono::Utc::now(),
+            prompt_tokens: 0,
+            generated_tokens: 0,
+            n_steps: n_steps as i64,
+            num_samples,
+            wall_time: wall_time.as_secs_f64(),
+            tokens_per_second: 0.,
+        }
+    }
+}
+
+impl Usage {
+    pub async fn create(&self, db: &DbPool) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO usage
+                    (id, username, model, chat_id, image_id, date, prompt_tokens, generated_tokens, n_steps, num_samples, wall_time, tokens_per_second)
+            VALUES  ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+            "#,
+        )
+        .bind(self.id)
+        .bind(&self.username)
+        .bind(&self.model)
+        .bind(self.chat_id)
+        .bind(self.image_id)
+        .bind(self.date)
+        .bind(self.prompt_tokens)
+        .bind(self.generated_tokens)
+        .bind(self.n_steps)
+        .bind(self.num_samples)
+        .bind(self.wall_time)
+        .bind(self.tokens_per_second)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(UsageError::Create)
+        .map_err(Error::from)
+    }
+
+    /// Aggregates the usage by user, model and day, most recent days first.
+    pub async fn report(db: &DbPool, query: &UsageQuery) -> Result<Vec<UsageReportEntry>> {
+        sqlx::query(&format!(
+            r#"
+            SELECT  username, model, {DAY} as day, COUNT(*) as jobs,
+                    CAST(SUM(prompt_tokens) AS BIGINT) as prompt_tokens,
+                    CAST(SUM(generated_tokens) AS BIGINT) as generated_tokens,
+                    CAST(SUM(n_steps) AS BIGINT) as n_steps,
+                    CAST(SUM(num_samples) AS BIGINT) as num_samples,
+                    SUM(wall_time) as wall_time
+            FROM usage
+            WHERE   ($1 IS NULL OR username = $1)
+                AND ($2 IS NULL OR model = $2)
+                AND ($3 IS NULL OR {DAY} >= $3)
+                AND ($4 IS NULL OR {DAY} <= $4)
+            GROUP BY username, model, {DAY}
+            ORDER BY day DESC, username, model
+            "#
+        ))
+        .bind(query.username.as_deref())
+        .bind(query.model.as_deref())
+        .bind(query.from.map(|day| day.to_string()))
+        .bind(query.to.map(|day| day.to_string()))
+        .fetch_all(db)
+        .await
+        .map(|rows| {
+            rows.into_iter()
+                .map(|row| {
+                    let generated_tokens = row.try_get::<i64, _>("generated_tokens").unwrap_or(0);
+                    let wall_time = row.try_get::<f64, _>("wall_time").unwrap_or(0.);
+                    UsageReportEntry {
+                        username: row.try_get("username").unwrap_or_default(),
+                        model: row.try_get("model").unwrap_or_default(),
+                        day: row
+                            .try_get::<String, _>("day")
+                            .ok()
+                            .and_then(|day| day.parse().ok())
+                            .unwrap_or_default(),
+                        jobs: row.try_get::<i64, _>("jobs").unwrap_or(0) as u64,
+                        prompt_tokens: row.try_get::<i64, _>("prompt_tokens").unwrap_or(0) as u64,
+                        generated_tokens: generated_tokens as u64,
+                        n_steps: row.try_get::<i64, _>("n_steps").unwrap_or(0) as u64,
+                        num_samples: row.try_get::<i64, _>("num_samples").unwrap_or(0) as u64,
+                        wall_time,
+                        tokens_per_second: if wall_time > 0. {
+                            generated_tokens as f64 / wall_time
+                        } else {
+                            0.
+                        },
+                    }
+                })
+                .collect()
+        })
+        .map_err(UsageError::Report)
+        .map_err(Error::from)
+    }
+}
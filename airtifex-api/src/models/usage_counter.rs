@@ -0,0 +1,219 @@
+use crate::{
+    models::{Error, Result},
+    DbPool,
+};
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use sqlx::Row;
+use thiserror::Error as ErrorType;
+
+#[derive(Debug, ErrorType)]
+pub enum UsageCounterError {
+    #[error("failed to inspect usage counter - {0}")]
+    Inspect(#[source] sqlx::Error),
+    #[error("failed to update usage counter - {0}")]
+    Update(#[source] sqlx::Error),
+}
+
+/// A resource counted per user within a fixed time window.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UsageKind {
+    /// Generation requests, counted per minute.
+    Requests,
+    /// Prompt and generated tokens, counted per day.
+    Tokens,
+    /// Generated image samples, counted per day.
+    ImageSamples,
+}
+
+impl AsRef<str> for UsageKind {
+    fn as_ref(&self) -> &str {
+        match self {
+            UsageKind::Requests => "requests",
+            UsageKind::Tokens => "tokens",
+            UsageKind::ImageSamples => "image_samples",
+        }
+    }
+}
+
+impl UsageKind {
+    fn window(self) -> Duration {
+        match self {
+            UsageKind::Requests => Duration::minutes(1),
+            UsageKind::Tokens | UsageKind::ImageSamples => Duration::days(1),
+        }
+    }
+
+    /// Returns the start of the window that `time` falls into. Daily windows start at midnight UTC.
+    pub fn window_start(self, time: DateTime<Utc>) -> DateTime<Utc> {
+        time.duration_trunc(self.window()).unwrap_or(time)
+    }
+}
+
+/// An amount added to a usage counter that must not exceed the limit of the window.
+#[derive(Copy, Clone, Debug)]
+pub struct UsageCharge {
+    pub kind: UsageKind,
+    pub amount: u64,
+    pub limit: Option<u64>,
+}
+
+pub struct UsageCounter;
+
+impl UsageCounter {
+    /// Returns the usage of `kind` by the user in the current window.
+    pub async fn get(db: &DbPool, username: &str, kind: UsageKind) -> Result<u64> {
+        sqlx::query(
+            r#"
+            SELECT value
+            FROM usage_counters
+            WHERE username = $1 AND counter = $2 AND window_start = $3
+            "#,
+        )
+        .bind(username)
+        .bind(kind.as_ref())
+        .bind(kind.window_start(Utc::now()))
+        .fetch_optional(db)
+        .await
+        .map(|row| {
+            row.and_then(|row| row.try_get::<i64, _>("value").ok())
+                .unwrap_or(0) as u64
+        })
+        .map_err(UsageCounterError::Inspect)
+        .map_err(Error::from)
+    }
+
+    /// Adds `amount` to the usage of `kind` by the user in the current window and removes the
+    /// counters of past windows.
+    pub async fn increment(
+        db: &DbPool,
+        username: &str,
+        kind: UsageKind,
+        amount: u64,
+    ) -> Result<()> {
+        let window_start = kind.window_start(Utc::now());
+        let mut tx = db.begin().await.map_err(UsageCounterError::Update)?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO usage_counters (username, counter, window_start, value)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (username, counter, window_start)
+            DO UPDATE SET value = usage_counters.value + excluded.value
+            "#,
+        )
+        .bind(username)
+        .bind(kind.as_ref())
+        .bind(window_start)
+        .bind(amount as i64)
+        .execute(&mut tx)
+        .await
+        .map_err(UsageCounterError::Update)?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM usage_counters
+            WHERE username = $1 AND counter = $2 AND window_start < $3
+            "#,
+        )
+        .bind(username)
+        .bind(kind.as_ref())
+        .bind(window_start)
+        .execute(&mut tx)
+        .await
+        .map_err(UsageCounterError::Update)?;
+
+        tx.commit()
+            .await
+            .map(|_| ())
+            .map_err(UsageCounterError::Update)
+            .map_err(Error::from)
+    }
+
+    /// Adds all charges to the usage of the user in the current window unless any of them would
+    /// exceed its limit, in which case nothing is recorded and the first such charge is returned.
+    /// The limits are checked by the same statement that updates a counter so that concurrent
+    /// requests can't both pass the check.
+    pub async fn charge(
+        db: &DbPool,
+        username: &str,
+        charges: &[UsageCharge],
+    ) -> Result<Option<UsageCharge>> {
+        let now = Utc::now();
+        let mut tx = db.begin().await.map_err(UsageCounterError::Update)?;
+
+        for charge in charges {
+            if charge.limit.map_or(false, |limit| charge.amount > limit) {
+                return Ok(Some(*charge));
+            }
+            let window_start = charge.kind.window_start(now);
+            let charged = sqlx::query(
+                r#"
+                INSERT INTO usage_counters (username, counter, window_start, value)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (username, counter, window_start)
+                DO UPDATE SET value = usage_counters.value + excluded.value
+                WHERE $5 IS NULL OR usage_counters.value + excluded.value <= $5
+                RETURNING value
+                "#,
+            )
+            .bind(username)
+            .bind(charge.kind.as_ref())
+            .bind(window_start)
+            .bind(charge.amount as i64)
+            .bind(charge.limit.map(|limit| limit as i64))
+            .fetch_optional(&mut tx)
+            .await
+            .map_err(UsageCounterError::Update)?;
+            if charged.is_none() {
+                // dropping the transaction rolls back the charges made so far
+                return Ok(Some(*charge));
+            }
+
+            sqlx::query(
+                r#"
+                DELETE FROM usage_counters
+                WHERE username = $1 AND counter = $2 AND window_start < $3
+                "#,
+            )
+            .bind(username)
+            .bind(charge.kind.as_ref())
+            .bind(window_start)
+            .execute(&mut tx)
+            .await
+            .map_err(UsageCounterError::Update)?;
+        }
+
+        tx.commit()
+            .await
+            .map(|_| None)
+            .map_err(UsageCounterError::Update)
+            .map_err(Error::from)
+    }
+
+    /// Takes back `amount` from the usage of `kind` by the user in the current window, used when
+    /// a charged request couldn't be started.
+    pub async fn decrement(
+        db: &DbPool,
+        username: &str,
+        kind: UsageKind,
+        amount: u64,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE usage_counters
+            SET value = CASE WHEN value > $4 THEN value - $4 ELSE 0 END
+            WHERE username = $1 AND counter = $2 AND window_start = $3
+            "#,
+        )
+        .bind(username)
+        .bind(kind.as_ref())
+        .bind(kind.window_start(Utc::now()))
+        .bind(amount as i64)
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(UsageCounterError::Update)
+        .map_err(Error::from)
+    }
+}
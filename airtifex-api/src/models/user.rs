@@ -17,15 +17,15 @@ use sqlx::FromRow;
 #[derive(Debug, ErrorType)]
 pub enum UserError {
     #[error("Failed to update password - {0}")]
-    PasswordUpdateError(sqlx::Error),
+    PasswordUpdateError(#[source] sqlx::Error),
     #[error("Failed to update a user - {0}")]
-    UpdateError(sqlx::Error),
+    UpdateError(#[source] sqlx::Error),
     #[error("Failed to delete a user - {0}")]
-    DeleteError(sqlx::Error),
+    DeleteError(#[source] sqlx::Error),
     #[error("Failed to create a new user - {0}")]
-    CreateError(sqlx::Error),
+    CreateError(#[source] sqlx::Error),
     #[error("Failed to list users - {0}")]
-    ListError(sqlx::Error),
+    ListError(#[source] sqlx::Error),
     #[error("Invalid account type `{0}`")]
     InvalidAccountType(String),
 }
@@ -1,35 +1,625 @@
-use flume::{unbounded, Sender};
+use airtifex_core::queue::{QueuePosition, QueueStatus, QueuedJob};
+
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::VecDeque,
-    sync::{Arc, RwLock},
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, MutexGuard, PoisonError,
+    },
+    time::Duration,
 };
 
-pub type Queue<T> = Arc<RwLock<VecDeque<T>>>;
+/// Scheduling priority of a request. Queued requests with a higher priority are always started
+/// before the ones with a lower priority.
+#[derive(
+    Copy, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+#[derive(Debug)]
+struct QueueState<T> {
+    items: VecDeque<T>,
+    closed: bool,
+    /// Set by [`Queue::notify`] to wake up the worker once
+    notified: bool,
+    /// Tick at which a request of each owner was last scheduled, only kept for owners that
+    /// have something queued
+    last_served: HashMap<String, u64>,
+    tick: u64,
+    /// Positions of the items computed by [`Queue::schedule`], cleared whenever the items or
+    /// the tick change
+    positions: Option<Vec<usize>>,
+}
 
-pub fn empty_queue<T: Send + Sync + 'static>() -> Queue<T> {
-    Default::default()
+#[derive(Debug)]
+struct QueueInner<T> {
+    state: Mutex<QueueState<T>>,
+    condvar: Condvar,
 }
 
-pub fn start_queue_thread<T: Send + Sync + 'static>(queue: Queue<T>) -> Sender<T> {
-    let (tx_request, rx_request) = unbounded();
-    std::thread::spawn(move || {
-        let mut temp_queue = VecDeque::new();
-        loop {
-            if !temp_queue.is_empty() {
-                if let Ok(mut queue) = queue.try_write() {
-                    queue.append(&mut temp_queue);
-                }
+/// A queue shared between the API and a model worker. Workers block on [`Queue::wait`] instead
+/// of polling so that they only wake up when there is something to do.
+///
+/// Items are scheduled by priority first and then round-robin between their owners, so that a
+/// single user submitting many requests can't starve everyone else.
+#[derive(Debug)]
+pub struct Queue<T>(Arc<QueueInner<T>>);
+
+impl<T> Clone for Queue<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Default for Queue<T> {
+    fn default() -> Self {
+        Self(Arc::new(QueueInner {
+            state: Mutex::new(QueueState {
+                items: VecDeque::new(),
+                closed: false,
+                notified: false,
+                last_served: HashMap::new(),
+                tick: 0,
+                positions: None,
+            }),
+            condvar: Condvar::new(),
+        }))
+    }
+}
+
+impl<T> Queue<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState<T>> {
+        self.0.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Adds an item to the back of the queue waking up the worker. Returns the item back if the
+    /// queue was closed.
+    pub fn push(&self, item: T) -> Result<(), T> {
+        let mut state = self.lock();
+        if state.closed {
+            return Err(item);
+        }
+        state.items.push_back(item);
+        state.positions = None;
+        drop(state);
+        self.0.condvar.notify_all();
+        Ok(())
+    }
+
+    /// Blocks until the queue has at least one item, is closed, the worker is woken up with
+    /// [`Queue::notify`] or the `timeout` elapses. Returns `false` if the queue was closed and
+    /// there is nothing left to process.
+    pub fn wait(&self, timeout: Option<Duration>) -> bool {
+        let condition =
+            |state: &mut QueueState<T>| state.items.is_empty() && !state.closed && !state.notified;
+        let mut state = match timeout {
+            Some(timeout) => {
+                self.0
+                    .condvar
+                    .wait_timeout_while(self.lock(), timeout, condition)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0
+            }
+            None => self
+                .0
+                .condvar
+                .wait_while(self.lock(), condition)
+                .unwrap_or_else(PoisonError::into_inner),
+        };
+        state.notified = false;
+        !(state.closed && state.items.is_empty())
+    }
+
+    /// Wakes up the worker waiting on the queue even if there is nothing to process.
+    pub fn notify(&self) {
+        self.lock().notified = true;
+        self.0.condvar.notify_all();
+    }
+
+    /// Removes all items for which `f` returns `true` and returns them.
+    pub fn remove_where(&self, mut f: impl FnMut(&T) -> bool) -> Vec<T> {
+        let mut state = self.lock();
+        let mut removed = vec![];
+        let mut kept = VecDeque::with_capacity(state.items.len());
+        for item in state.items.drain(..) {
+            if f(&item) {
+                removed.push(item);
+            } else {
+                kept.push_back(item);
             }
-            if let Ok(gen_image_request) = rx_request.try_recv() {
-                if let Ok(mut queue) = queue.try_write() {
-                    queue.push_back(gen_image_request);
-                } else {
-                    temp_queue.push_back(gen_image_request);
-                }
+        }
+        state.items = kept;
+        if !removed.is_empty() {
+            state.positions = None;
+        }
+        removed
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().items.is_empty()
+    }
+
+    /// Whether the queue was closed and has nothing left to process.
+    pub fn is_finished(&self) -> bool {
+        let state = self.lock();
+        state.closed && state.items.is_empty()
+    }
+
+    /// Stops accepting new items and wakes up the worker.
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.0.condvar.notify_all();
+    }
+}
+
+impl<T: QueueItem> Queue<T> {
+    /// Removes up to `n` items that should be processed next without blocking. `running` are the
+    /// owners of the sessions that are currently running, used to enforce `max_per_owner`.
+    pub fn pop_batch<'a>(
+        &self,
+        n: usize,
+        running: impl IntoIterator<Item = &'a str>,
+        max_per_owner: Option<usize>,
+    ) -> Vec<T> {
+        let mut state = self.lock();
+        let state = &mut *state;
+
+        let max_per_owner = max_per_owner.unwrap_or(usize::MAX).max(1);
+        let mut sessions: HashMap<String, usize> = HashMap::new();
+        for owner in running {
+            *sessions.entry(owner.to_string()).or_default() += 1;
+        }
+
+        let mut batch = vec![];
+        while batch.len() < n {
+            let candidates = state
+                .items
+                .iter()
+                .enumerate()
+                .filter(|(_, item)| {
+                    sessions.get(item.owner()).copied().unwrap_or_default() < max_per_owner
+                })
+                .map(|(index, item)| (index, item.owner(), item.priority()));
+            let Some(index) = next_index(candidates, &state.last_served) else {
+                break;
+            };
+            let Some(item) = state.items.remove(index) else {
+                break;
+            };
+
+            state.tick += 1;
+            state
+                .last_served
+                .insert(item.owner().to_string(), state.tick);
+            *sessions.entry(item.owner().to_string()).or_default() += 1;
+            batch.push(item);
+        }
+
+        if !batch.is_empty() {
+            let queued = state
+                .items
+                .iter()
+                .map(|item| item.owner())
+                .collect::<HashSet<_>>();
+            state
+                .last_served
+                .retain(|owner, _| queued.contains(owner.as_str()));
+            state.positions = None;
+        }
+        batch
+    }
+
+    /// Returns the queued items in the order in which they will be scheduled, ignoring the limit
+    /// of sessions per owner.
+    pub fn schedule(&self) -> Vec<ScheduledItem> {
+        let mut state = self.lock();
+        let state = &mut *state;
+        let positions = state.positions.get_or_insert_with(|| {
+            let entries = state
+                .items
+                .iter()
+                .map(|item| (item.owner(), item.priority()))
+                .collect::<Vec<_>>();
+            schedule_positions(&entries, &state.last_served, state.tick)
+        });
+
+        let mut schedule = state
+            .items
+            .iter()
+            .zip(positions.iter().copied())
+            .map(|(item, position)| ScheduledItem {
+                id: item.item_id(),
+                owner: item.owner().to_string(),
+                position,
+            })
+            .collect::<Vec<_>>();
+        schedule.sort_by_key(|item| item.position);
+        schedule
+    }
+
+    /// Returns the position at which a new item of `owner` with `priority` would be scheduled.
+    pub fn next_position(&self, owner: &str, priority: Priority) -> usize {
+        let state = self.lock();
+        let mut entries = state
+            .items
+            .iter()
+            .map(|item| (item.owner(), item.priority()))
+            .collect::<Vec<_>>();
+        entries.push((owner, priority));
+        schedule_positions(&entries, &state.last_served, state.tick)
+            .last()
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+pub struct ScheduledItem {
+    pub id: String,
+    pub owner: String,
+    pub position: usize,
+}
+
+/// Picks the index of the next item to schedule out of `(index, owner, priority)` candidates.
+/// The highest priority goes first, ties are broken by picking the owner that was served the
+/// longest time ago and the requests of a single owner are processed in order of arrival.
+fn next_index<'a>(
+    candidates: impl Iterator<Item = (usize, &'a str, Priority)>,
+    last_served: &HashMap<String, u64>,
+) -> Option<usize> {
+    candidates
+        .min_by_key(|(index, owner, priority)| {
+            (
+                Reverse(*priority),
+                last_served.get(*owner).copied().unwrap_or_default(),
+                *index,
+            )
+        })
+        .map(|(index, _, _)| index)
+}
+
+/// Simulates scheduling of `(owner, priority)` entries returning the position at which each of
+/// them would be scheduled.
+///
+/// Within a priority the owners are served in turns, so instead of searching all pending entries
+/// for every position only the next entry of each owner is kept in a heap.
+fn schedule_positions(
+    entries: &[(&str, Priority)],
+    last_served: &HashMap<String, u64>,
+    mut tick: u64,
+) -> Vec<usize> {
+    let mut pending: HashMap<(Priority, &str), VecDeque<usize>> = HashMap::new();
+    for (index, (owner, priority)) in entries.iter().enumerate() {
+        pending
+            .entry((*priority, *owner))
+            .or_default()
+            .push_back(index);
+    }
+    let mut last_served = entries
+        .iter()
+        .map(|(owner, _)| (*owner, last_served.get(*owner).copied().unwrap_or_default()))
+        .collect::<HashMap<_, _>>();
+
+    let mut priorities = entries
+        .iter()
+        .map(|(_, priority)| *priority)
+        .collect::<Vec<_>>();
+    priorities.sort_unstable_by_key(|priority| Reverse(*priority));
+    priorities.dedup();
+
+    let mut positions = vec![0; entries.len()];
+    let mut position = 0;
+    for priority in priorities {
+        // owners are ordered by the tick at which they were served and then by the index of
+        // their next entry, the same as in `next_index`
+        let mut turns = pending
+            .iter()
+            .filter(|((p, _), _)| *p == priority)
+            .map(|((_, owner), indexes)| Reverse((last_served[owner], indexes[0], *owner)))
+            .collect::<BinaryHeap<_>>();
+        while let Some(Reverse((_, index, owner))) = turns.pop() {
+            positions[index] = position;
+            position += 1;
+            tick += 1;
+            last_served.insert(owner, tick);
+
+            let indexes = pending
+                .get_mut(&(priority, owner))
+                .expect("owner has pending entries");
+            indexes.pop_front();
+            if let Some(&next) = indexes.front() {
+                turns.push(Reverse((tick, next, owner)));
             }
-            std::thread::sleep(std::time::Duration::from_millis(5));
         }
-    });
+    }
+    positions
+}
+
+/// Number of most recently finished jobs used to estimate the throughput of a worker.
+const THROUGHPUT_WINDOW: usize = 20;
+
+/// Implemented by requests stored in a [`Queue`] so that they can be reported to their owners.
+pub trait QueueItem {
+    fn item_id(&self) -> String;
+    fn owner(&self) -> &str;
+    fn priority(&self) -> Priority;
+}
+
+/// Statistics of a model worker shared with the API used to report queue positions and ETAs.
+#[derive(Debug)]
+pub struct QueueStats {
+    max_running: usize,
+    running: AtomicUsize,
+    durations: Mutex<VecDeque<Duration>>,
+}
+
+impl QueueStats {
+    pub fn new(max_running: usize) -> Self {
+        Self {
+            max_running: max_running.max(1),
+            running: AtomicUsize::new(0),
+            durations: Mutex::new(VecDeque::with_capacity(THROUGHPUT_WINDOW)),
+        }
+    }
+
+    pub fn max_running(&self) -> usize {
+        self.max_running
+    }
+
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    pub fn set_running(&self, running: usize) {
+        self.running.store(running, Ordering::Relaxed);
+    }
+
+    /// Records the processing time of a finished job.
+    pub fn record_job(&self, duration: Duration) {
+        let mut durations = self
+            .durations
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if durations.len() == THROUGHPUT_WINDOW {
+            durations.pop_front();
+        }
+        durations.push_back(duration);
+    }
+
+    pub fn average_job_duration(&self) -> Option<Duration> {
+        let durations = self
+            .durations
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if durations.is_empty() {
+            return None;
+        }
+        Some(durations.iter().sum::<Duration>() / durations.len() as u32)
+    }
+
+    /// Estimates how long a job with `position` jobs ahead of it in the queue will wait before
+    /// it starts processing. Returns `None` until at least one job has finished.
+    pub fn eta(&self, position: usize) -> Option<Duration> {
+        let free_spots = self.max_running.saturating_sub(self.running());
+        if position < free_spots {
+            return Some(Duration::ZERO);
+        }
+        let average = self.average_job_duration()?;
+        let jobs_to_finish = position + 1 - free_spots;
+        let rounds = (jobs_to_finish + self.max_running - 1) / self.max_running;
+        Some(average * rounds as u32)
+    }
+
+    pub fn position(&self, position: usize) -> QueuePosition {
+        QueuePosition {
+            position,
+            running: self.running(),
+            max_running: self.max_running,
+            eta_seconds: self.eta(position).map(|eta| eta.as_secs_f64()),
+        }
+    }
+
+    pub fn status<T: QueueItem>(&self, model: &str, user: &str, queue: &Queue<T>) -> QueueStatus {
+        let schedule = queue.schedule();
+        let queued = schedule.len();
+        let jobs = schedule
+            .into_iter()
+            .filter(|item| item.owner == user)
+            .map(|item| QueuedJob {
+                id: item.id,
+                position: self.position(item.position),
+            })
+            .collect();
+
+        QueueStatus {
+            model: model.to_string(),
+            queued,
+            running: self.running(),
+            max_running: self.max_running,
+            average_job_seconds: self.average_job_duration().map(|d| d.as_secs_f64()),
+            eta_seconds: self.eta(queued).map(|eta| eta.as_secs_f64()),
+            jobs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Job {
+        id: usize,
+        owner: &'static str,
+        priority: Priority,
+    }
+
+    impl QueueItem for Job {
+        fn item_id(&self) -> String {
+            self.id.to_string()
+        }
+
+        fn owner(&self) -> &str {
+            self.owner
+        }
+
+        fn priority(&self) -> Priority {
+            self.priority
+        }
+    }
+
+    fn queue(jobs: &[(&'static str, Priority)]) -> Queue<Job> {
+        let queue = Queue::new();
+        for (id, (owner, priority)) in jobs.iter().enumerate() {
+            queue
+                .push(Job {
+                    id,
+                    owner,
+                    priority: *priority,
+                })
+                .unwrap();
+        }
+        queue
+    }
+
+    fn ids(jobs: Vec<Job>) -> Vec<usize> {
+        jobs.into_iter().map(|job| job.id).collect()
+    }
 
-    tx_request
+    /// Pops the jobs one by one the way a worker with a single session does.
+    fn pop_all(queue: &Queue<Job>) -> Vec<usize> {
+        std::iter::from_fn(|| queue.pop_batch(1, [], None).pop())
+            .map(|job| job.id)
+            .collect()
+    }
+
+    #[test]
+    fn schedules_higher_priority_first() {
+        use Priority::*;
+        let queue = queue(&[("a", Low), ("b", Normal), ("c", High), ("a", High)]);
+        assert_eq!(pop_all(&queue), [2, 3, 1, 0]);
+    }
+
+    #[test]
+    fn takes_turns_between_owners() {
+        use Priority::Normal;
+        let queue = queue(&[
+            ("a", Normal),
+            ("a", Normal),
+            ("a", Normal),
+            ("b", Normal),
+            ("b", Normal),
+            ("c", Normal),
+        ]);
+        assert_eq!(ids(queue.pop_batch(6, [], None)), [0, 3, 5, 1, 4, 2]);
+    }
+
+    #[test]
+    fn remembers_turns_across_batches() {
+        use Priority::Normal;
+        let queue = queue(&[("a", Normal), ("a", Normal), ("b", Normal)]);
+        assert_eq!(ids(queue.pop_batch(1, [], None)), [0]);
+        // `a` was just served so `b` goes first although `a` queued earlier
+        assert_eq!(ids(queue.pop_batch(1, [], None)), [2]);
+        assert_eq!(ids(queue.pop_batch(1, [], None)), [1]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn forgets_owners_without_queued_jobs() {
+        use Priority::Normal;
+        let queue = queue(&[("a", Normal), ("b", Normal), ("b", Normal)]);
+        queue.pop_batch(2, [], None);
+        let state = queue.lock();
+        assert!(!state.last_served.contains_key("a"));
+        assert!(state.last_served.contains_key("b"));
+    }
+
+    #[test]
+    fn limits_sessions_per_owner() {
+        use Priority::Normal;
+        let queue = queue(&[("a", Normal), ("a", Normal), ("b", Normal)]);
+        assert_eq!(ids(queue.pop_batch(3, ["a"], Some(1))), [2]);
+        assert_eq!(ids(queue.pop_batch(3, [], Some(1))), [0]);
+        assert_eq!(ids(queue.pop_batch(3, [], Some(1))), [1]);
+    }
+
+    #[test]
+    fn predicts_the_scheduling_order() {
+        use Priority::*;
+        // deterministic pseudo random jobs
+        let mut seed = 42u64;
+        let mut next = |n: u64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) % n
+        };
+        let owners = ["a", "b", "c", "d"];
+        let priorities = [Low, Normal, High];
+        let jobs = (0..60)
+            .map(|_| (owners[next(4) as usize], priorities[next(3) as usize]))
+            .collect::<Vec<_>>();
+
+        let queue = queue(&jobs);
+        // serve a few jobs so that the turns of the owners are not all equal
+        queue.pop_batch(5, [], None);
+
+        let schedule = queue.schedule();
+        assert!(schedule
+            .iter()
+            .enumerate()
+            .all(|(position, item)| item.position == position));
+        let predicted = schedule
+            .iter()
+            .map(|item| item.id.parse::<usize>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(pop_all(&queue), predicted);
+    }
+
+    #[test]
+    fn predicts_the_position_of_a_new_job() {
+        use Priority::*;
+        let queue = queue(&[
+            ("a", Normal),
+            ("a", Normal),
+            ("b", High),
+            ("b", Normal),
+            ("c", Low),
+        ]);
+        queue.pop_batch(1, [], None);
+
+        for (owner, priority) in [("a", Normal), ("b", Low), ("d", Normal), ("d", High)] {
+            let position = queue.next_position(owner, priority);
+            queue
+                .push(Job {
+                    id: 100,
+                    owner,
+                    priority,
+                })
+                .unwrap();
+            let scheduled = queue
+                .schedule()
+                .into_iter()
+                .find(|item| item.id == "100")
+                .unwrap();
+            assert_eq!(scheduled.position, position, "{owner} {priority:?}");
+            queue.remove_where(|job| job.id == 100);
+        }
+    }
 }
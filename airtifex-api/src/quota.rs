@@ -0,0 +1,90 @@
+use crate::{
+    config::Quota,
+    models::usage_counter::{UsageCharge, UsageCounter, UsageKind},
+    DbPool,
+};
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum QuotaError {
+    #[error("rate limit exceeded, at most {0} requests per minute are allowed")]
+    RequestsPerMinute(u64),
+    #[error("daily quota of {0} tokens exceeded")]
+    TokensPerDay(u64),
+    #[error("daily quota of {limit} image samples exceeded, {remaining} samples left for today")]
+    ImageSamplesPerDay { limit: u64, remaining: u64 },
+    #[error(transparent)]
+    Database(#[from] crate::models::Error),
+}
+
+/// The kind of generation a request is about to start.
+#[derive(Copy, Clone, Debug)]
+pub enum Generation {
+    Text,
+    Image { num_samples: u64 },
+}
+
+/// Verifies that the user has enough quota left for the request and records it in the usage
+/// counters. Tokens are only known once the inference finishes so they are recorded by the
+/// inference thread and a text request is allowed as long as the daily quota isn't used up.
+///
+/// Should only be called once the request was validated, if it can't be started afterwards the
+/// usage has to be given back with [`release`].
+pub async fn acquire(
+    db: &DbPool,
+    quota: &Quota,
+    username: &str,
+    generation: Generation,
+) -> Result<(), QuotaError> {
+    let mut charges = vec![UsageCharge {
+        kind: UsageKind::Requests,
+        amount: 1,
+        limit: quota.requests_per_minute,
+    }];
+    match generation {
+        Generation::Text => {
+            if let Some(limit) = quota.tokens_per_day {
+                let used = UsageCounter::get(db, username, UsageKind::Tokens).await?;
+                if used >= limit {
+                    return Err(QuotaError::TokensPerDay(limit));
+                }
+            }
+        }
+        Generation::Image { num_samples } => charges.push(UsageCharge {
+            kind: UsageKind::ImageSamples,
+            amount: num_samples,
+            limit: quota.image_samples_per_day,
+        }),
+    }
+
+    let Some(exceeded) = UsageCounter::charge(db, username, &charges).await? else {
+        return Ok(());
+    };
+    let limit = exceeded.limit.unwrap_or_default();
+    Err(match exceeded.kind {
+        UsageKind::Requests => QuotaError::RequestsPerMinute(limit),
+        UsageKind::Tokens => QuotaError::TokensPerDay(limit),
+        UsageKind::ImageSamples => {
+            let used = UsageCounter::get(db, username, UsageKind::ImageSamples).await?;
+            QuotaError::ImageSamplesPerDay {
+                limit,
+                remaining: limit.saturating_sub(used),
+            }
+        }
+    })
+}
+
+/// Gives back the usage recorded by [`acquire`] for a request that couldn't be started.
+pub async fn release(
+    db: &DbPool,
+    username: &str,
+    generation: Generation,
+) -> Result<(), QuotaError> {
+    if let Generation::Image { num_samples } = generation {
+        UsageCounter::decrement(db, username, UsageKind::ImageSamples, num_samples).await?;
+    }
+    UsageCounter::decrement(db, username, UsageKind::Requests, 1)
+        .await
+        .map_err(QuotaError::from)
+}
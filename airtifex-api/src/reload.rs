@@ -0,0 +1,198 @@
+use crate::{
+    config::Config,
+    gen::{image, llm, ModelHandle, ModelName, Models},
+    InnerAppState, Result,
+};
+use airtifex_core::admin::{ConfigReloadResponse, ModelChanges};
+
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, PoisonError},
+};
+
+/// Serializes reloads so that concurrent requests don't start the same model twice.
+static RELOAD_LOCK: Lazy<tokio::sync::Mutex<()>> = Lazy::new(|| tokio::sync::Mutex::new(()));
+
+/// Reads the configuration file again and applies it to the running server. Models that were
+/// added to the configuration are started, models that were removed are stopped and models with a
+/// changed configuration are restarted. Requests already queued on a stopped model are still
+/// processed before its worker exits. Priorities, quotas and the residency settings apply
+/// immediately.
+///
+/// The listen address, database URL, JWT secret and storage only take effect after a restart.
+pub async fn reload(state: &InnerAppState) -> Result<ConfigReloadResponse> {
+    let _guard = RELOAD_LOCK.lock().await;
+
+    let config = Config::read(&state.config_path)?;
+    {
+        let current = state.config();
+        if current.listen_addr != config.listen_addr
+            || current.listen_port != config.listen_port
+            || current.db_url != config.db_url
+            || current.jwt_secret != config.jwt_secret
+            || current.storage != config.storage
+        {
+            log::warn!(
+                "listen address, database URL, JWT secret and storage changes require a restart and were ignored"
+            );
+        }
+    }
+
+    // new workers read the residency settings when they start
+    let previous_residency = state.config().residency.clone();
+    state.residency.set_config(config.residency.clone());
+    let rollback = |e| {
+        state.residency.set_config(previous_residency.clone());
+        e
+    };
+
+    let llms = prepare(
+        &state.llms,
+        config.llms.clone(),
+        |model, llm_config| async move {
+            llm::initialize_model(
+                state.db.clone(),
+                &model,
+                &llm_config,
+                state.runtime.clone(),
+                state.inference_registry.clone(),
+                state.residency.clone(),
+            )
+            .await
+        },
+    )
+    .await
+    .map_err(rollback)?;
+
+    let image_configs = config
+        .stable_diffusion
+        .iter()
+        .map(|model_config| (model_config.name(), model_config.clone()))
+        .collect();
+    let image_models = match prepare(
+        &state.image_models,
+        image_configs,
+        |model, model_config| async move {
+            image::initialize_model(
+                state.db.clone(),
+                &model,
+                &model_config,
+                state.runtime.clone(),
+                state.image_progress.clone(),
+                state.storage.clone(),
+                state.residency.clone(),
+            )
+            .await
+        },
+    )
+    .await
+    {
+        Ok(image_models) => image_models,
+        Err(e) => {
+            llms.abort();
+            return Err(rollback(e));
+        }
+    };
+
+    let llms = llms.commit(&state.llms);
+    let image_models = image_models.commit(&state.image_models);
+
+    let mut current = state.config.write().unwrap_or_else(PoisonError::into_inner);
+    current.llms = config.llms;
+    current.stable_diffusion = config.stable_diffusion;
+    current.priorities = config.priorities;
+    current.quotas = config.quotas;
+    current.residency = config.residency;
+
+    log::info!(
+        "configuration reloaded, llms: {:?}, image models: {:?}",
+        llms,
+        image_models
+    );
+
+    Ok(ConfigReloadResponse { llms, image_models })
+}
+
+/// Handles started for a new configuration that are not serving requests yet.
+struct Pending<C, R> {
+    changes: ModelChanges,
+    started: Vec<(ModelName, Arc<ModelHandle<C, R>>)>,
+}
+
+impl<C, R: crate::queue::QueueItem> Pending<C, R> {
+    /// Stops the started handles leaving the running models untouched.
+    fn abort(self) {
+        for (name, handle) in self.started {
+            log::info!("stopping model {name} after a failed reload");
+            handle.stop();
+        }
+    }
+
+    /// Swaps the started handles in and stops the replaced and removed models. They finish
+    /// their queued requests before their workers exit, the workers of the replacements only
+    /// load their model after that.
+    fn commit(self, models: &Models<ModelHandle<C, R>>) -> ModelChanges {
+        let changes = self.changes;
+        let mut updated = models.all().into_iter().collect::<HashMap<_, _>>();
+        for name in &changes.removed {
+            updated.remove(name);
+        }
+        updated.extend(self.started);
+
+        for (name, previous) in models.replace(updated) {
+            if changes.removed.contains(&name) {
+                log::info!("stopping model {name}");
+                previous.stop();
+            } else if changes.replaced.contains(&name) {
+                log::info!("restarting model {name} with the new configuration");
+                previous.stop();
+            }
+        }
+        changes
+    }
+}
+
+/// Starts new handles with `initialize` for the models of `configs` that were added or changed.
+/// If one of them fails to start the ones started before are stopped again.
+async fn prepare<C, R, F, Fut>(
+    models: &Models<ModelHandle<C, R>>,
+    configs: HashMap<ModelName, C>,
+    initialize: F,
+) -> Result<Pending<C, R>>
+where
+    C: PartialEq,
+    R: crate::queue::QueueItem,
+    F: Fn(ModelName, C) -> Fut,
+    Fut: Future<Output = Result<ModelHandle<C, R>>>,
+{
+    let current = models.all();
+    let mut pending = Pending {
+        changes: ModelChanges::default(),
+        started: vec![],
+    };
+    pending.changes.removed = current
+        .iter()
+        .filter(|(name, _)| !configs.contains_key(name))
+        .map(|(name, _)| name.clone())
+        .collect();
+
+    let mut configs = configs.into_iter().collect::<Vec<_>>();
+    configs.sort_by(|a, b| a.0.cmp(&b.0));
+    for (name, config) in configs {
+        match current.iter().find(|(current, _)| *current == name) {
+            Some((_, handle)) if handle.config == config => continue,
+            Some(_) => pending.changes.replaced.push(name.clone()),
+            None => pending.changes.added.push(name.clone()),
+        }
+        match initialize(name.clone(), config).await {
+            Ok(handle) => pending.started.push((name, Arc::new(handle))),
+            Err(e) => {
+                pending.abort();
+                return Err(e);
+            }
+        }
+    }
+    Ok(pending)
+}
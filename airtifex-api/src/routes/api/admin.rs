@@ -0,0 +1,17 @@
+use crate::{auth::Claims, reload, ApiResponse, SharedAppState};
+
+use axum::{extract::State, response::Response, routing, Router};
+
+pub fn router() -> Router<SharedAppState> {
+    Router::new().route("/reload", routing::post(reload_config))
+}
+
+async fn reload_config(claims: Claims, State(state): State<SharedAppState>) -> Response {
+    let db = &state.db;
+    with_admin_guard!(claims, db);
+
+    match reload::reload(&state).await {
+        Ok(changes) => ApiResponse::success(changes).ok(),
+        Err(e) => ApiResponse::failure(e).internal_server_error(),
+    }
+}
@@ -3,34 +3,49 @@ use crate::{
     gen::llm::{ChatData, InferenceRequest},
     id::Uuid,
     models::{chat::Chat, chat_entry::ChatEntry, llm::LargeLanguageModel},
-    routes::handle_db_result_as_json,
-    Error, SharedAppState, ToAxumResponse,
+    quota::Generation,
+    routes::{
+        enforce_quota, export, handle_db_result_as_json, inference_event_stream, release_quota,
+    },
+    DbPool, Error, SharedAppState, ToAxumResponse,
 };
 use airtifex_core::{
     api_response::ApiResponse,
     llm::{
-        ChatEntryListEntry, ChatListEntry, ChatResponseRequest, ChatStartRequest,
-        ChatStartResponse, ChatStreamResult, InferenceSettings, LlmListEntry,
+        ChatEntryListEntry, ChatEntryType, ChatExport, ChatExportFormat, ChatExportQuery,
+        ChatImportQuery, ChatListEntry, ChatListQuery, ChatResponseRequest, ChatStartRequest,
+        ChatStartResponse, InferenceEvent, LlmListEntry,
     },
+    user::AuthenticatedUser,
 };
 
 use axum::{
-    body::StreamBody,
-    extract::{Json, Path, State},
-    response::{IntoResponse, Response},
+    body::Full,
+    extract::{Json, Path, Query, State},
+    response::Response,
     routing, Router,
 };
 
 pub fn router() -> Router<SharedAppState> {
     Router::new()
         .route("/models", routing::get(list_models))
+        .route("/queue", routing::get(queue_status))
         .route("/chat", routing::post(start_chat).get(list))
         .route("/chat/counters", routing::get(counters))
+        .route("/chat/import", routing::post(import_chat))
         .route(
             "/chat/:id",
             routing::get(get_chat).delete(delete_chat).post(inference),
         )
         .route("/chat/:id/history", routing::get(get_chat_history))
+        .route("/chat/:id/export", routing::get(export_chat))
+        .route("/chat/:id/regenerate", routing::post(regenerate))
+        .route("/chat/:id/entries/:entry_id", routing::post(edit_entry))
+        .route("/chat/:id/entries/:entry_id/fork", routing::post(fork_chat))
+        .route(
+            "/chat/:id/entries/:entry_id/select",
+            routing::post(select_branch),
+        )
 }
 
 async fn inference(
@@ -40,66 +55,290 @@ async fn inference(
     Json(request): Json<ChatResponseRequest>,
 ) -> Response {
     let db = &state.db;
-    with_user_guard!(claims, db);
+    let user = with_user_guard!(claims, db);
 
-    let (tx_tokens, rx_tokens): (
-        flume::Sender<ChatStreamResult>,
-        flume::Receiver<ChatStreamResult>,
-    ) = flume::unbounded();
+    let chat = match authorize_chat(db, &user, &id).await {
+        Ok(chat) => chat,
+        Err(response) => return response,
+    };
 
-    let history = match Chat::list_entries(db, &id, &claims.sub).await {
+    let history = match Chat::list_entries(db, &id, &chat.username).await {
+        Ok(history) => history,
+        Err(e) => {
+            return ApiResponse::failure(e).internal_server_error();
+        }
+    };
+    let active_entry_id = history.last().map(|entry| entry.entry_id);
+
+    submit_chat_inference(
+        &state,
+        &user,
+        chat,
+        history,
+        request.prompt,
+        None,
+        active_entry_id,
+    )
+    .await
+}
+
+/// Generates a new reply to the last prompt of the active branch. The previous reply is kept as
+/// an alternative branch.
+async fn regenerate(
+    claims: Claims,
+    State(state): State<SharedAppState>,
+    Path(id): Path<Uuid>,
+) -> Response {
+    let db = &state.db;
+    let user = with_user_guard!(claims, db);
+
+    let chat = match authorize_chat(db, &user, &id).await {
         Ok(chat) => chat,
+        Err(response) => return response,
+    };
+
+    let mut history = match Chat::list_entries(db, &id, &chat.username).await {
+        Ok(history) => history,
         Err(e) => {
             return ApiResponse::failure(e).internal_server_error();
         }
     };
+    let active_entry_id = history.last().map(|entry| entry.entry_id);
+
+    if matches!(history.last(), Some(entry) if entry.entry_type == ChatEntryType::Bot) {
+        history.pop();
+    }
+    let prompt = match history.pop() {
+        Some(entry) if entry.entry_type == ChatEntryType::User => entry,
+        _ => {
+            return ApiResponse::failure(format!("chat {id} has no prompt to regenerate"))
+                .bad_request()
+        }
+    };
+
+    submit_chat_inference(
+        &state,
+        &user,
+        chat,
+        history,
+        prompt.content,
+        Some(prompt.entry_id),
+        active_entry_id,
+    )
+    .await
+}
 
-    let chat = match Chat::get_chat_for_user(db, &claims.sub, &id).await {
+/// Replaces a prompt of the active branch with the edited one and generates a reply to it. The
+/// original prompt and the entries following it are kept as an alternative branch.
+async fn edit_entry(
+    claims: Claims,
+    State(state): State<SharedAppState>,
+    Path((id, entry_id)): Path<(Uuid, Uuid)>,
+    Json(request): Json<ChatResponseRequest>,
+) -> Response {
+    let db = &state.db;
+    let user = with_user_guard!(claims, db);
+
+    let chat = match authorize_chat(db, &user, &id).await {
         Ok(chat) => chat,
+        Err(response) => return response,
+    };
+
+    let mut history = match Chat::list_entries(db, &id, &chat.username).await {
+        Ok(history) => history,
         Err(e) => {
             return ApiResponse::failure(e).internal_server_error();
         }
     };
+    let active_entry_id = history.last().map(|entry| entry.entry_id);
+
+    match history.iter().position(|entry| entry.entry_id == entry_id) {
+        Some(position) if history[position].entry_type == ChatEntryType::User => {
+            history.truncate(position);
+        }
+        Some(_) => {
+            return ApiResponse::failure("only prompts of the user can be edited").bad_request()
+        }
+        None => {
+            return ApiResponse::failure(format!(
+                "entry {entry_id} is not a part of the active branch of chat {id}"
+            ))
+            .not_found()
+        }
+    }
+
+    submit_chat_inference(
+        &state,
+        &user,
+        chat,
+        history,
+        request.prompt,
+        None,
+        active_entry_id,
+    )
+    .await
+}
+
+/// Continues the conversation from the entry, the next prompt starts a new branch following it.
+async fn fork_chat(
+    claims: Claims,
+    State(state): State<SharedAppState>,
+    Path((id, entry_id)): Path<(Uuid, Uuid)>,
+) -> Response {
+    let db = &state.db;
+    let user = with_user_guard!(claims, db);
+
+    let chat = match authorize_chat(db, &user, &id).await {
+        Ok(chat) => chat,
+        Err(response) => return response,
+    };
+
+    if let Err(e) = ChatEntry::get(db, &id, &entry_id).await {
+        return ApiResponse::failure(e).not_found();
+    }
+
+    handle_db_result_as_json(activate_branch(db, &chat, &entry_id).await)
+}
+
+/// Switches to the most recent branch containing the entry, used to browse the alternatives of
+/// an entry.
+async fn select_branch(
+    claims: Claims,
+    State(state): State<SharedAppState>,
+    Path((id, entry_id)): Path<(Uuid, Uuid)>,
+) -> Response {
+    let db = &state.db;
+    let user = with_user_guard!(claims, db);
+
+    let chat = match authorize_chat(db, &user, &id).await {
+        Ok(chat) => chat,
+        Err(response) => return response,
+    };
+
+    let leaf = match ChatEntry::get_latest_descendant(db, &id, &entry_id).await {
+        Ok(leaf) => leaf,
+        Err(e) => return ApiResponse::failure(e).not_found(),
+    };
+
+    handle_db_result_as_json(activate_branch(db, &chat, &leaf.entry_id).await)
+}
+
+/// Makes the entry the end of the active branch and returns the entries of the branch.
+async fn activate_branch(
+    db: &DbPool,
+    chat: &Chat,
+    entry_id: &Uuid,
+) -> crate::Result<Vec<ChatEntryListEntry>> {
+    Chat::update_active_entry(db, &chat.id, entry_id).await?;
+    branch_entries(db, chat).await
+}
+
+/// Returns the entries of the active branch with the alternatives of every entry.
+async fn branch_entries(db: &DbPool, chat: &Chat) -> crate::Result<Vec<ChatEntryListEntry>> {
+    let branch = ChatEntry::get_chat_entries(db, &chat.id, &chat.username).await?;
+    let all = ChatEntry::get_all_chat_entries(db, &chat.id).await?;
+
+    Ok(branch
+        .into_iter()
+        .map(|e| ChatEntryListEntry {
+            id: e.entry_id.to_string(),
+            chat_id: e.chat_id.to_string(),
+            content: e.content,
+            entry_type: e.entry_type,
+            parent_id: e.parent_entry_id.map(|id| id.to_string()),
+            siblings: all
+                .iter()
+                .filter(|sibling| sibling.parent_entry_id == e.parent_entry_id)
+                .map(|sibling| sibling.entry_id.to_string())
+                .collect(),
+        })
+        .collect())
+}
+
+/// Queues the inference of a reply to the prompt continuing the history. The prompt and the
+/// reply are saved as a new branch of the chat once the inference finishes.
+async fn submit_chat_inference(
+    state: &SharedAppState,
+    user: &AuthenticatedUser,
+    chat: Chat,
+    history: Vec<ChatEntry>,
+    prompt: String,
+    prompt_entry_id: Option<Uuid>,
+    active_entry_id: Option<Uuid>,
+) -> Response {
+    let Some(model) = state.llms.get(&chat.model) else {
+        return ApiResponse::failure(format!("failed to find model {}", &chat.model))
+            .internal_server_error();
+    };
+    if let Err(response) = enforce_quota(state, user, Generation::Text).await {
+        return response;
+    }
 
+    let (tx_tokens, rx_tokens): (
+        flume::Sender<InferenceEvent>,
+        flume::Receiver<InferenceEvent>,
+    ) = flume::unbounded();
+
+    let (inference_id, cancellation) = state
+        .inference_registry
+        .register(&user.username, &chat.model);
     let request = InferenceRequest {
+        id: inference_id,
+        cancellation,
         tx_tokens,
-        user: claims.sub,
+        user: user.username.clone(),
+        priority: state.config().priorities.of(user.account_type),
         save: true,
         chat_data: Some(ChatData {
-            conversation_id: id,
+            conversation_id: chat.id,
             history,
+            prompt_entry_id,
+            active_entry_id,
         }),
-        prompt: request.prompt,
-        settings: InferenceSettings {
-            num_predict: chat.num_predict.map(|k| k as usize),
-            system_prompt: chat.system_prompt,
-            n_batch: chat.n_batch.map(|k| k as usize),
-            top_k: chat.top_k.map(|k| k as usize),
-            top_p: chat.top_p,
-            repeat_penalty: chat.repeat_penalty,
-            temp: chat.temp,
-        },
+        prompt,
+        settings: chat.settings(),
         play_back_tokens: false,
     };
     log::info!("{request:?}");
 
-    if let Some((_, model)) = state.tx_inference_req.get(&chat.model) {
-        if let Err(e) = model.send_async(request).await {
-            return ApiResponse::failure(e).internal_server_error();
-        }
-    } else {
-        return ApiResponse::failure(format!("failed to find model {}", &chat.model))
-            .internal_server_error();
+    let _ = request.tx_tokens.send(InferenceEvent::Queued(
+        model.next_position(&request.user, request.priority),
+    ));
+    if let Err(e) = model.submit(request) {
+        state.inference_registry.remove(&inference_id);
+        release_quota(state, &user.username, Generation::Text).await;
+        return ApiResponse::failure(e).internal_server_error();
     }
 
-    (
-        [
-            (axum::http::header::CONTENT_TYPE, "text/event-stream"),
-            (axum::http::header::TRANSFER_ENCODING, "chunked"),
-        ],
-        StreamBody::new(rx_tokens.into_stream()),
-    )
-        .into_response()
+    inference_event_stream(inference_id, rx_tokens)
+}
+
+/// Returns the chat if it was started by the user or the user is an admin. Chats of other users
+/// are reported as missing.
+async fn authorize_chat(
+    db: &DbPool,
+    user: &AuthenticatedUser,
+    id: &Uuid,
+) -> Result<Chat, Response> {
+    match Chat::get(db, id).await {
+        Ok(chat) if user.is_admin() || chat.username == user.username => Ok(chat),
+        Ok(_) => Err(ApiResponse::failure(format!("chat {id} not found")).not_found()),
+        Err(e) => Err(ApiResponse::failure(e).not_found()),
+    }
+}
+
+async fn queue_status(claims: Claims, State(state): State<SharedAppState>) -> Response {
+    let db = &state.db;
+    with_user_guard!(claims, db);
+
+    let status = state
+        .llms
+        .all()
+        .into_iter()
+        .map(|(name, model)| model.queue_status(&name, &claims.sub))
+        .collect::<Vec<_>>();
+
+    ApiResponse::success(status).ok()
 }
 
 async fn start_chat(
@@ -114,17 +353,13 @@ async fn start_chat(
     let model = if let Some(model) = request.model {
         model
     } else {
-        state
-            .tx_inference_req
-            .keys()
-            .next()
-            .map(|k| k.to_string())
-            .unwrap_or_default()
+        state.llms.names().into_iter().next().unwrap_or_default()
     };
 
     let mut chat = Chat::new(claims.sub, model.clone(), request.title, request.settings);
 
-    if let Some((config, _)) = state.tx_inference_req.get(&model) {
+    if let Some(model) = state.llms.get(&model) {
+        let config = &model.config;
         if chat.n_batch.is_none() {
             chat.n_batch = Some(config.batch_size as i32);
         }
@@ -152,12 +387,16 @@ async fn start_chat(
     )
 }
 
-async fn list(claims: Claims, state: State<SharedAppState>) -> Response {
+async fn list(
+    claims: Claims,
+    state: State<SharedAppState>,
+    Query(query): Query<ChatListQuery>,
+) -> Response {
     let db = &state.db;
     with_user_guard!(claims, db);
 
     handle_db_result_as_json(
-        Chat::list_chats_of_user(db, &claims.sub)
+        Chat::list_chats_of_user(db, &claims.sub, &query)
             .await
             .map(|entries| {
                 entries
@@ -186,29 +425,28 @@ async fn list(claims: Claims, state: State<SharedAppState>) -> Response {
 
 async fn get_chat(claims: Claims, state: State<SharedAppState>, Path(id): Path<Uuid>) -> Response {
     let db = &state.db;
-    with_user_guard!(claims, db);
+    let user = with_user_guard!(claims, db);
 
-    handle_db_result_as_json(
-        Chat::get_chat_for_user(db, &claims.sub, &id)
-            .await
-            .map(|chat| ChatListEntry {
-                id: chat.id.to_string(),
-                title: chat.title,
-                username: chat.username,
-                start_date: chat.start_date,
-                model: chat.model,
-                settings: airtifex_core::llm::InferenceSettings {
-                    num_predict: chat.num_predict.map(|n| n as usize),
-                    system_prompt: chat.system_prompt,
-                    n_batch: chat.n_batch.map(|n| n as usize),
-                    top_k: chat.top_k.map(|n| n as usize),
-                    top_p: chat.top_p,
-                    repeat_penalty: chat.repeat_penalty,
-                    temp: chat.temp,
-                },
-            })
-            .map_err(Error::from),
-    )
+    match authorize_chat(db, &user, &id).await {
+        Ok(chat) => ApiResponse::success(ChatListEntry {
+            id: chat.id.to_string(),
+            title: chat.title,
+            username: chat.username,
+            start_date: chat.start_date,
+            model: chat.model,
+            settings: airtifex_core::llm::InferenceSettings {
+                num_predict: chat.num_predict.map(|n| n as usize),
+                system_prompt: chat.system_prompt,
+                n_batch: chat.n_batch.map(|n| n as usize),
+                top_k: chat.top_k.map(|n| n as usize),
+                top_p: chat.top_p,
+                repeat_penalty: chat.repeat_penalty,
+                temp: chat.temp,
+            },
+        })
+        .ok(),
+        Err(response) => response,
+    }
 }
 
 async fn get_chat_history(
@@ -217,24 +455,14 @@ async fn get_chat_history(
     Path(id): Path<Uuid>,
 ) -> Response {
     let db = &state.db;
-    with_user_guard!(claims, db);
+    let user = with_user_guard!(claims, db);
 
-    handle_db_result_as_json(
-        ChatEntry::get_chat_entries(db, &id, &claims.sub)
-            .await
-            .map(|entries| {
-                entries
-                    .into_iter()
-                    .map(|e| ChatEntryListEntry {
-                        id: e.entry_id.to_string(),
-                        chat_id: e.chat_id.to_string(),
-                        content: e.content,
-                        entry_type: e.entry_type,
-                    })
-                    .collect::<Vec<_>>()
-            })
-            .map_err(Error::from),
-    )
+    let chat = match authorize_chat(db, &user, &id).await {
+        Ok(chat) => chat,
+        Err(response) => return response,
+    };
+
+    handle_db_result_as_json(branch_entries(db, &chat).await)
 }
 
 async fn list_models(claims: Claims, state: State<SharedAppState>) -> Response {
@@ -249,6 +477,10 @@ async fn list_models(claims: Claims, state: State<SharedAppState>) -> Response {
                     .into_iter()
                     .map(|model| LlmListEntry {
                         model_id: model.model_id.to_string(),
+                        residency: state
+                            .llms
+                            .get(&model.name)
+                            .map(|handle| handle.residency(&model.name)),
                         name: model.name,
                         description: model.description,
                     })
@@ -264,7 +496,11 @@ async fn delete_chat(
     Path(id): Path<Uuid>,
 ) -> Response {
     let db = &state.db;
-    with_user_guard!(claims, db);
+    let user = with_user_guard!(claims, db);
+
+    if let Err(response) = authorize_chat(db, &user, &id).await {
+        return response;
+    }
 
     handle_db_result_as_json(Chat::delete(db, &id).await.map_err(Error::from))
 }
@@ -275,3 +511,94 @@ async fn counters(claims: Claims, State(state): State<SharedAppState>) -> Respon
 
     handle_db_result_as_json(Chat::counters(db, &claims.sub).await.map_err(Error::from))
 }
+
+async fn export_chat(
+    claims: Claims,
+    State(state): State<SharedAppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<ChatExportQuery>,
+) -> Response {
+    let db = &state.db;
+    let user = with_user_guard!(claims, db);
+
+    let chat = match authorize_chat(db, &user, &id).await {
+        Ok(chat) => chat,
+        Err(response) => return response,
+    };
+
+    let chat = match export::chat_export(db, chat).await {
+        Ok(chat) => chat,
+        Err(e) => return ApiResponse::failure(e).internal_server_error(),
+    };
+
+    match query.format.unwrap_or_default() {
+        ChatExportFormat::Json => match serde_json::to_vec_pretty(&chat) {
+            Ok(json) => export::attachment(
+                "application/json",
+                &format!("chat-{id}.json"),
+                Full::from(json),
+            ),
+            Err(e) => ApiResponse::failure(e).internal_server_error(),
+        },
+        ChatExportFormat::Markdown => export::attachment(
+            "text/markdown; charset=utf-8",
+            &format!("chat-{id}.md"),
+            Full::from(export::chat_markdown(&chat)),
+        ),
+    }
+}
+
+async fn import_chat(
+    claims: Claims,
+    State(state): State<SharedAppState>,
+    Query(query): Query<ChatImportQuery>,
+    Json(chat): Json<ChatExport>,
+) -> Response {
+    let db = &state.db;
+    with_user_guard!(claims, db);
+
+    let model = query.model.unwrap_or(chat.model);
+    if state.llms.get(&model).is_none() {
+        return ApiResponse::failure(format!("unknown model {model}")).bad_request();
+    }
+
+    let mut imported = Chat::new(
+        claims.sub,
+        model,
+        Some(chat.title.clone()),
+        chat.settings.clone(),
+    );
+    imported.start_date = chat.start_date;
+    let (entries, active_entry_id) = match export::import_entries(imported.id, chat) {
+        Ok(entries) => entries,
+        Err(e) => return ApiResponse::failure(e).bad_request(),
+    };
+    if let Err(e) = imported.create(db).await {
+        return ApiResponse::failure(e).internal_server_error();
+    }
+
+    let entries = async {
+        for entry in entries {
+            entry.create(db).await?;
+        }
+        if let Some(entry_id) = &active_entry_id {
+            Chat::update_active_entry(db, &imported.id, entry_id).await?;
+        }
+        Ok::<_, crate::Error>(())
+    };
+    if let Err(e) = entries.await {
+        // don't leave a partially imported chat behind
+        if let Err(e) = Chat::delete(db, &imported.id).await {
+            log::error!(
+                "failed to remove partially imported chat {} - {e}",
+                imported.id
+            );
+        }
+        return ApiResponse::failure(e).internal_server_error();
+    }
+
+    ApiResponse::success(ChatStartResponse {
+        chat_id: imported.id.to_string(),
+    })
+    .ok()
+}
@@ -1,37 +1,61 @@
 use crate::{
     auth::Claims,
-    gen::image::{BaseImageData, GenerateImageRequest, ImageToImageData, InpaintData},
+    config::StableDiffusionConfig,
+    gen::image::{sd::prompt, BaseImageData, GenerateImageRequest, ImageToImageData, InpaintData},
     id::Uuid,
     models::{image::Image, image_model::ImageModel, image_sample::ImageSample, user::User},
-    routes::handle_db_result_as_json,
-    Error, SharedAppState, ToAxumResponse,
+    queue::{Priority, QueueItem, QueuePosition},
+    quota::Generation,
+    routes::{
+        enforce_quota, handle_db_result_as_json,
+        object::{self, VersionQuery},
+        release_quota,
+    },
+    storage::{self, StorageError},
+    ApiVersion, DbPool, Error, SharedAppState, ToAxumResponse,
 };
 use airtifex_core::{
     api_response::ApiResponse,
     image::{
-        ImageGenerateRequest, ImageInspect, ImageModelFeatures, ImageModelListEntry,
-        ImageSampleInspect, TextToImageResponse,
+        ImageGenerateRequest, ImageInspect, ImageListQuery, ImageModelFeatures,
+        ImageModelListEntry, ImageProgress, ImageSampleInspect, ImageScheduler, ImageStatus,
+        ImageVisibility, ImageVisibilityRequest, TextToImageResponse,
     },
+    user::AuthenticatedUser,
 };
 
 use axum::{
-    extract::{Json, Path, State},
-    response::Response,
+    extract::{Json, Path, Query, State},
+    http::HeaderMap,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     routing, Router,
 };
 use rand::Rng;
+use tokio::sync::watch;
 
 pub fn router() -> Router<SharedAppState> {
     Router::new()
         .route("/generate", routing::post(generate_image))
         .route("/", routing::get(list_images))
         .route("/models", routing::get(list_models))
+        .route("/queue", routing::get(queue_status))
         .route(
             "/:id",
             routing::get(get_image_metadata).delete(delete_image),
         )
         .route("/:id/samples", routing::get(list_image_entries))
+        .route("/:id/progress", routing::get(image_progress))
+        .route("/:id/cancel", routing::post(cancel_image))
+        .route("/:id/retry", routing::post(retry_image))
+        .route("/:id/visibility", routing::post(update_visibility))
         .route("/:id/samples/:n", routing::get(get_image_entry))
+        .route("/:id/samples/:n/raw", routing::get(get_image_entry_raw))
+        .route("/:id/thumbnail", routing::get(get_image_thumbnail))
+        .route("/:id/input", routing::get(get_image_input))
+        .route("/:id/mask", routing::get(get_image_mask))
 }
 
 async fn generate_image(
@@ -40,7 +64,7 @@ async fn generate_image(
     Json(request): Json<ImageGenerateRequest>,
 ) -> Response {
     let db = &state.db;
-    with_user_guard!(claims, db);
+    let user = with_user_guard!(claims, db);
 
     log::info!("{request:?}");
 
@@ -49,48 +73,186 @@ async fn generate_image(
         Err(e) => return ApiResponse::failure(e).internal_server_error(),
     };
 
+    let model = match ImageModel::get_by_name(db, &request.model).await {
+        Ok(model) => model,
+        Err(e) => return ApiResponse::failure(e).not_found(),
+    };
+    let (feature, enabled) = match &request.input_image {
+        Some(input) if input.mask.is_some() => ("inpaint", model.feature_inpaint),
+        Some(_) => ("image-to-image", model.feature_image_to_image),
+        None => ("text-to-image", model.feature_text_to_image),
+    };
+    if !enabled {
+        return ApiResponse::failure(format!(
+            "feature {feature} is disabled for model {}",
+            model.name
+        ))
+        .bad_request();
+    }
+
     let guidance_scale = request.guidance_scale.unwrap_or(7.5).min(20.0);
     let num_samples = request.num_samples.unwrap_or(1).min(16);
     let n_steps = request.n_steps.unwrap_or(25).min(420) as i64;
+    let scheduler = request
+        .scheduler
+        .or_else(|| {
+            state
+                .image_models
+                .get(&request.model)
+                .map(|model| model.config.scheduler)
+        })
+        .unwrap_or_default();
+    let negative_prompt = request
+        .negative_prompt
+        .filter(|prompt| !prompt.trim().is_empty());
+
+    let Some(handle) = state.image_models.get(&request.model) else {
+        return ApiResponse::failure(format!("model {} is not loaded", request.model)).not_found();
+    };
+    let warnings = prompt_warnings(
+        handle.config.clone(),
+        request.prompt.clone(),
+        negative_prompt.clone(),
+    )
+    .await;
+
+    let generation = Generation::Image {
+        num_samples: num_samples.max(0) as u64,
+    };
+    if let Err(response) = enforce_quota(&state, &user, generation).await {
+        return response;
+    }
 
     let (data, mask, strength) = request
         .input_image
         .map(|i| (Some(i.data), i.mask, i.strength))
         .unwrap_or_default();
 
-    let image = Image::new(
+    let mut image = Image::new(
         user_id,
         request.model,
         request.width.unwrap_or(512),
         request.height.unwrap_or(512),
         request.prompt,
-        data,
-        mask,
-        None,
+        negative_prompt,
         strength,
         n_steps,
         request.seed.unwrap_or_else(|| rand::thread_rng().gen()),
         num_samples,
         guidance_scale,
+        scheduler,
     );
 
-    if let Err(e) = image.create(db).await {
-        return ApiResponse::failure(e).internal_server_error();
+    let image_id = image.id.to_string();
+    let priority = state.config().priorities.of(user.account_type);
+    let submitted = async {
+        for (data, key, image_key) in [
+            (
+                &data,
+                storage::input_image_key(&image.id),
+                &mut image.input_image_key,
+            ),
+            (&mask, storage::mask_key(&image.id), &mut image.mask_key),
+        ] {
+            let Some(data) = data else {
+                continue;
+            };
+            if let Err(e) = state.storage.put(&key, data.clone()).await {
+                return Err(ApiResponse::failure(e).internal_server_error());
+            }
+            *image_key = Some(key);
+        }
+
+        if let Err(e) = image.create(db).await {
+            return Err(ApiResponse::failure(e).internal_server_error());
+        }
+
+        let id = image.id;
+        match submit_image(&state, image, [data, mask], claims.sub, priority).await {
+            Ok(queue) => Ok(queue),
+            Err(e) => {
+                // fail the image so that it can be retried instead of staying queued
+                let error = format!("failed to queue the image - {e}");
+                if let Err(e) = Image::update_failed(db, &id, &error).await {
+                    log::error!("[{id}] failed to update image status - {e}");
+                }
+                Err(ApiResponse::failure(error).internal_server_error())
+            }
+        }
+    };
+    let queue = match submitted.await {
+        Ok(queue) => queue,
+        Err(response) => {
+            release_quota(&state, &user.username, generation).await;
+            return response;
+        }
+    };
+
+    ApiResponse::success(TextToImageResponse {
+        image_id,
+        queue: Some(queue),
+        warnings,
+    })
+    .ok()
+}
+
+/// Reads the input image and mask of the image back from the storage.
+async fn stored_inputs(
+    state: &SharedAppState,
+    image: &Image,
+) -> Result<[Option<Vec<u8>>; 2], String> {
+    let mut inputs = [None, None];
+    for (input, key) in inputs
+        .iter_mut()
+        .zip([&image.input_image_key, &image.mask_key])
+    {
+        if let Some(key) = key {
+            match state.storage.get(key).await {
+                Ok(object) => *input = Some(object.data),
+                Err(e) => return Err(e.to_string()),
+            }
+        }
     }
+    Ok(inputs)
+}
+
+/// Queues the generation of the image on its model with the data of its input image and mask.
+async fn submit_image(
+    state: &SharedAppState,
+    image: Image,
+    [input_image, mask]: [Option<Vec<u8>>; 2],
+    user: String,
+    priority: Priority,
+) -> Result<QueuePosition, String> {
+    let Some(model) = state.image_models.get(&image.model) else {
+        return Err(format!("model {} is not loaded", image.model));
+    };
 
     let data = BaseImageData {
         id: image.id.to_string(),
+        user,
+        priority,
         prompt: image.prompt,
-        // input_image: image.input_image,
-        // mask: image.mask,
+        negative_prompt: image.negative_prompt,
         width: image.width,
         height: image.height,
         n_steps: image.n_steps as usize,
         seed: image.seed,
         num_samples: image.num_samples,
         guidance_scale: image.guidance_scale,
+        scheduler: ImageScheduler::parse_str(&image.scheduler).unwrap_or_default(),
     };
-    let request = match (image.input_image, image.mask) {
+    state.image_progress.register(ImageProgress {
+        image_id: data.id.clone(),
+        queued: true,
+        sample: 1,
+        num_samples: data.num_samples,
+        timestep: 0,
+        n_steps: data.n_steps,
+        finished: false,
+        preview: None,
+    });
+    let request = match (input_image, mask) {
         (Some(input_image), Some(mask)) => GenerateImageRequest::Inpaint(InpaintData {
             data,
             input_image,
@@ -104,134 +266,532 @@ async fn generate_image(
         (None, None) | (None, Some(_)) => GenerateImageRequest::TextToImage(data),
     };
 
-    if let Some(tx_gen_req) = state.tx_image_gen_req.get(&image.model) {
-        if let Err(e) = tx_gen_req.send_async(request).await {
-            return ApiResponse::failure(e).internal_server_error();
+    let position = model.next_position(request.owner(), request.priority());
+    if let Err(e) = model.submit(request) {
+        state.image_progress.finish(&image.id.to_string());
+        return Err(e.to_string());
+    }
+    Ok(position)
+}
+
+/// Stops the generation of the image. Queued jobs are removed from the queue while running ones
+/// stop before their next timestep, samples that were already generated are kept until the image
+/// is retried.
+async fn cancel_image(
+    claims: Claims,
+    State(state): State<SharedAppState>,
+    Path(id): Path<Uuid>,
+) -> Response {
+    let db = &state.db;
+    let user = with_user_guard!(claims, db);
+
+    let image = match authorize_image(db, Some(&user), &id, Access::Modify).await {
+        Ok(image) => image,
+        Err(response) => return response,
+    };
+    let status = ImageStatus::parse_str(&image.status).unwrap_or_default();
+    let image_id = image.id.to_string();
+
+    let dequeued = state
+        .image_models
+        .get(&image.model)
+        .map(|model| !model.queue.remove_where(|r| r.id() == image_id).is_empty())
+        .unwrap_or_default();
+    if dequeued {
+        state.image_progress.finish(&image_id);
+    } else if state.image_progress.cancel(&image_id) {
+        // the worker updates the status once the job stopped
+        return ApiResponse::success(ImageStatus::Cancelled).ok();
+    } else if !status.is_processing() {
+        return ApiResponse::failure(format!("image {id} is not being generated")).conflict();
+    }
+
+    // Also reached by jobs lost on a restart that would otherwise stay processing forever
+    match Image::update_status(db, &id, ImageStatus::Cancelled).await {
+        Ok(()) => ApiResponse::success(ImageStatus::Cancelled).ok(),
+        Err(e) => ApiResponse::failure(e).internal_server_error(),
+    }
+}
+
+/// Queues a cancelled or failed image again with the same parameters. The job generates all
+/// samples from the start so the samples kept from the previous run are deleted.
+async fn retry_image(
+    claims: Claims,
+    State(state): State<SharedAppState>,
+    Path(id): Path<Uuid>,
+) -> Response {
+    let db = &state.db;
+    let user = with_user_guard!(claims, db);
+
+    let image = match authorize_image(db, Some(&user), &id, Access::Modify).await {
+        Ok(image) => image,
+        Err(response) => return response,
+    };
+    if !matches!(
+        ImageStatus::parse_str(&image.status),
+        Some(ImageStatus::Cancelled | ImageStatus::Failed)
+    ) {
+        return ApiResponse::failure("only cancelled or failed images can be retried").conflict();
+    }
+    if state.image_models.get(&image.model).is_none() {
+        return ApiResponse::failure(format!("model {} is not loaded", image.model)).not_found();
+    }
+
+    let generation = Generation::Image {
+        num_samples: image.num_samples.max(0) as u64,
+    };
+    if let Err(response) = enforce_quota(&state, &user, generation).await {
+        return response;
+    }
+
+    // only one of concurrent retries gets to queue the image again
+    let requeued = match Image::requeue(db, &id).await {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            Err(ApiResponse::failure("only cancelled or failed images can be retried").conflict())
         }
-    } else {
-        return ApiResponse::failure("Image generation from text is disabled")
-            .internal_server_error();
+        Err(e) => Err(ApiResponse::failure(e).internal_server_error()),
+    };
+    if let Err(response) = requeued {
+        release_quota(&state, &user.username, generation).await;
+        return response;
     }
 
+    let image_id = image.id.to_string();
+    let priority = state.config().priorities.of(user.account_type);
+    let submitted = async {
+        let inputs = stored_inputs(&state, &image).await?;
+        let samples = ImageSample::get_image_samples(db, &id)
+            .await
+            .map_err(|e| e.to_string())?;
+        ImageSample::delete_by_image(db, &id)
+            .await
+            .map_err(|e| e.to_string())?;
+        // the new samples and thumbnail are saved under new keys
+        let keys = samples
+            .into_iter()
+            .map(|sample| sample.data_key)
+            .chain(image.thumbnail_key.clone());
+        for key in keys {
+            if let Err(e) = state.storage.delete(&key).await {
+                log::error!("[{id}] failed to delete image data - {e}");
+            }
+        }
+        submit_image(&state, image, inputs, claims.sub, priority).await
+    };
+    let queue = match submitted.await {
+        Ok(queue) => queue,
+        Err(e) => {
+            // fail the image so that it can be retried again instead of staying queued
+            let error = format!("failed to queue the image - {e}");
+            if let Err(e) = Image::update_failed(db, &id, &error).await {
+                log::error!("[{id}] failed to update image status - {e}");
+            }
+            release_quota(&state, &user.username, generation).await;
+            return ApiResponse::failure(error).internal_server_error();
+        }
+    };
+
     ApiResponse::success(TextToImageResponse {
-        image_id: image.id.to_string(),
+        image_id,
+        queue: Some(queue),
+        warnings: vec![],
     })
     .ok()
 }
 
-async fn list_images(claims: Claims, state: State<SharedAppState>) -> Response {
+/// Checks the prompts off the async runtime as the tokenizer may have to be loaded first.
+async fn prompt_warnings(
+    config: StableDiffusionConfig,
+    prompt: String,
+    negative_prompt: Option<String>,
+) -> Vec<String> {
+    let result = tokio::task::spawn_blocking(move || {
+        prompt::truncation_warnings(&config, &prompt, negative_prompt.as_deref())
+    })
+    .await;
+    match result {
+        Ok(Ok(warnings)) => warnings,
+        Ok(Err(e)) => {
+            log::warn!("failed to check the prompt length - {e}");
+            vec![]
+        }
+        Err(e) => {
+            log::warn!("failed to check the prompt length - {e}");
+            vec![]
+        }
+    }
+}
+
+async fn queue_status(claims: Claims, State(state): State<SharedAppState>) -> Response {
     let db = &state.db;
     with_user_guard!(claims, db);
 
+    let status = state
+        .image_models
+        .all()
+        .into_iter()
+        .map(|(name, model)| model.queue_status(&name, &claims.sub))
+        .collect::<Vec<_>>();
+
+    ApiResponse::success(status).ok()
+}
+
+/// Lists the images of the user, admins get the images of all users.
+async fn list_images(
+    claims: Claims,
+    state: State<SharedAppState>,
+    Query(query): Query<ImageListQuery>,
+) -> Response {
+    let db = &state.db;
+    let user = with_user_guard!(claims, db);
+
+    let user_id = if user.is_admin() {
+        None
+    } else {
+        match user.id.parse::<Uuid>() {
+            Ok(user_id) => Some(user_id),
+            Err(e) => return ApiResponse::failure(e).internal_server_error(),
+        }
+    };
+
     handle_db_result_as_json(
-        Image::list(db)
+        Image::list(db, user_id.as_ref(), &query)
             .await
-            .map(|e| {
-                e.into_iter()
-                    .map(|e| ImageInspect {
-                        id: e.id.to_string(),
-                        user_id: e.user_id.to_string(),
-                        model: e.model,
-                        width: e.width,
-                        height: e.height,
-                        prompt: e.prompt,
-                        input_image: e.input_image,
-                        mask: e.mask,
-                        thumbnail: e.thumbnail,
-                        n_steps: e.n_steps,
-                        seed: e.seed,
-                        num_samples: e.num_samples,
-                        processing: e.processing,
-                        create_date: e.create_date,
-                        guidance_scale: e.guidance_scale,
-                    })
-                    .collect::<Vec<_>>()
-            })
+            .map(|images| images.into_iter().map(image_inspect).collect::<Vec<_>>())
             .map_err(Error::from),
     )
 }
 
+/// URL of an object of the image that carries the version of its key so that it can be cached
+/// forever.
+fn image_url(id: &Uuid, path: &str, key: &str) -> String {
+    format!(
+        "/api/{}/image/{id}/{path}?v={}",
+        ApiVersion::V1.as_ref(),
+        object::version(key)
+    )
+}
+
+pub(crate) fn image_inspect(image: Image) -> ImageInspect {
+    ImageInspect {
+        id: image.id.to_string(),
+        user_id: image.user_id.to_string(),
+        model: image.model,
+        width: image.width,
+        height: image.height,
+        prompt: image.prompt,
+        negative_prompt: image.negative_prompt,
+        input_image_url: image
+            .input_image_key
+            .map(|key| image_url(&image.id, "input", &key)),
+        mask_url: image.mask_key.map(|key| image_url(&image.id, "mask", &key)),
+        thumbnail_url: image
+            .thumbnail_key
+            .map(|key| image_url(&image.id, "thumbnail", &key)),
+        n_steps: image.n_steps,
+        seed: image.seed,
+        num_samples: image.num_samples,
+        processing: image.processing,
+        create_date: image.create_date,
+        guidance_scale: image.guidance_scale,
+        scheduler: ImageScheduler::parse_str(&image.scheduler).unwrap_or_default(),
+        status: ImageStatus::parse_str(&image.status).unwrap_or_default(),
+        error: image.error,
+        visibility: ImageVisibility::parse_str(&image.visibility).unwrap_or_default(),
+    }
+}
+
+/// What the caller is about to do with an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Access {
+    View,
+    Modify,
+}
+
+/// Returns the image if the user may access it. Owners and admins may do anything, other users
+/// may only view shared or public images and anonymous clients public ones. Images the user
+/// can't view are reported as missing so that their IDs can't be probed.
+async fn authorize_image(
+    db: &DbPool,
+    user: Option<&AuthenticatedUser>,
+    id: &Uuid,
+    access: Access,
+) -> Result<Image, Response> {
+    let image = match Image::get_by_id(db, id).await {
+        Ok(image) => image,
+        Err(e) => return Err(ApiResponse::failure(e).not_found()),
+    };
+    if user.map_or(false, |user| {
+        user.is_admin() || user.id == image.user_id.to_string()
+    }) {
+        return Ok(image);
+    }
+
+    let visible = match ImageVisibility::parse_str(&image.visibility).unwrap_or_default() {
+        ImageVisibility::Private => false,
+        ImageVisibility::Shared => user.is_some(),
+        ImageVisibility::Public => true,
+    };
+    match (visible, access) {
+        (true, Access::View) => Ok(image),
+        (true, Access::Modify) => {
+            Err(ApiResponse::failure(format!("image {id} belongs to another user")).forbidden())
+        }
+        (false, _) => Err(ApiResponse::failure(format!("image {id} not found")).not_found()),
+    }
+}
+
+/// Authenticates the caller if a token was sent, anonymous callers can only view public images.
+async fn view_image(claims: Option<Claims>, db: &DbPool, id: &Uuid) -> Result<Image, Response> {
+    let user = match &claims {
+        Some(claims) => with_optional_guard!(claims, db),
+        None => None,
+    };
+    authorize_image(db, user.as_ref(), id, Access::View).await
+}
+
 async fn list_image_entries(
-    claims: Claims,
+    claims: Option<Claims>,
     state: State<SharedAppState>,
     id: Path<Uuid>,
 ) -> Response {
     let db = &state.db;
-    with_user_guard!(claims, db);
+    if let Err(response) = view_image(claims, db, &id).await {
+        return response;
+    }
 
     handle_db_result_as_json(
         ImageSample::get_image_samples(db, &id)
             .await
-            .map(|e| {
-                e.into_iter()
-                    .map(|e| ImageSampleInspect {
-                        sample_id: e.sample_id.to_string(),
-                        image_id: e.image_id.to_string(),
-                        n_sample: e.n,
-                        data: e.data,
-                    })
-                    .collect::<Vec<_>>()
-            })
+            .map(|samples| samples.into_iter().map(sample_inspect).collect::<Vec<_>>())
             .map_err(Error::from),
     )
 }
 
 async fn get_image_entry(
-    claims: Claims,
+    claims: Option<Claims>,
     state: State<SharedAppState>,
     Path((id, n)): Path<(Uuid, i32)>,
 ) -> Response {
     let db = &state.db;
-    with_user_guard!(claims, db);
+    if let Err(response) = view_image(claims, db, &id).await {
+        return response;
+    }
 
-    handle_db_result_as_json(
-        ImageSample::get_sample(db, &id, n)
-            .await
-            .map(|e| ImageSampleInspect {
-                sample_id: e.sample_id.to_string(),
-                image_id: e.image_id.to_string(),
-                n_sample: e.n,
-                data: e.data,
-            })
-            .map_err(Error::from),
-    )
+    match ImageSample::get_sample(db, &id, n).await {
+        Ok(sample) => ApiResponse::success(sample_inspect(sample)).ok(),
+        Err(e) => ApiResponse::failure(e).not_found(),
+    }
 }
 
-async fn get_image_metadata(
-    claims: Claims,
+fn sample_inspect(sample: ImageSample) -> ImageSampleInspect {
+    ImageSampleInspect {
+        url: image_url(
+            &sample.image_id,
+            &format!("samples/{}/raw", sample.n),
+            &sample.data_key,
+        ),
+        sample_id: sample.sample_id.to_string(),
+        image_id: sample.image_id.to_string(),
+        n_sample: sample.n,
+    }
+}
+
+/// Returns the PNG of the sample as is.
+async fn get_image_entry_raw(
+    claims: Option<Claims>,
+    state: State<SharedAppState>,
+    Path((id, n)): Path<(Uuid, i32)>,
+    Query(version): Query<VersionQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let db = &state.db;
+    let image = match view_image(claims, db, &id).await {
+        Ok(image) => image,
+        Err(response) => return response,
+    };
+
+    match ImageSample::get_sample(db, &id, n).await {
+        Ok(sample) => {
+            stored_image_response(&state, &headers, &image, &sample.data_key, version).await
+        }
+        Err(e) => ApiResponse::failure(e).not_found(),
+    }
+}
+
+async fn get_image_thumbnail(
+    claims: Option<Claims>,
+    state: State<SharedAppState>,
+    Path(id): Path<Uuid>,
+    Query(version): Query<VersionQuery>,
+    headers: HeaderMap,
+) -> Response {
+    get_image_object(claims, state, id, version, headers, |image| {
+        image.thumbnail_key.clone()
+    })
+    .await
+}
+
+async fn get_image_input(
+    claims: Option<Claims>,
     state: State<SharedAppState>,
     Path(id): Path<Uuid>,
+    Query(version): Query<VersionQuery>,
+    headers: HeaderMap,
+) -> Response {
+    get_image_object(claims, state, id, version, headers, |image| {
+        image.input_image_key.clone()
+    })
+    .await
+}
+
+async fn get_image_mask(
+    claims: Option<Claims>,
+    state: State<SharedAppState>,
+    Path(id): Path<Uuid>,
+    Query(version): Query<VersionQuery>,
+    headers: HeaderMap,
+) -> Response {
+    get_image_object(claims, state, id, version, headers, |image| {
+        image.mask_key.clone()
+    })
+    .await
+}
+
+/// Returns the object of the image selected by `key` as is.
+async fn get_image_object(
+    claims: Option<Claims>,
+    state: State<SharedAppState>,
+    id: Uuid,
+    version: VersionQuery,
+    headers: HeaderMap,
+    key: impl FnOnce(&Image) -> Option<String>,
 ) -> Response {
     let db = &state.db;
-    with_user_guard!(claims, db);
 
-    let user_id = match User::get(db, &claims.sub).await.map(|u| u.id) {
-        Ok(id) => id.to_string(),
-        Err(e) => return ApiResponse::failure(e).internal_server_error(),
+    let image = match view_image(claims, db, &id).await {
+        Ok(image) => image,
+        Err(response) => return response,
     };
+    match key(&image) {
+        Some(key) => stored_image_response(&state, &headers, &image, &key, version).await,
+        None => ApiResponse::failure(format!("image {id} has no such data")).not_found(),
+    }
+}
 
-    handle_db_result_as_json(
-        Image::get_by_id(db, &id)
-            .await
-            .map(|image| ImageInspect {
-                id: image.id.to_string(),
-                user_id,
-                model: image.model,
-                width: image.width,
-                height: image.height,
-                prompt: image.prompt,
-                input_image: image.input_image,
-                mask: image.mask,
-                thumbnail: image.thumbnail,
-                n_steps: image.n_steps,
-                seed: image.seed,
+/// Responds with the stored object of the image. Requests for the current version of the object
+/// can be cached forever, requests for a replaced version are answered with `404 Not Found`.
+async fn stored_image_response(
+    state: &SharedAppState,
+    headers: &HeaderMap,
+    image: &Image,
+    key: &str,
+    version: VersionQuery,
+) -> Response {
+    let cache_control = match version.v {
+        None => object::REVALIDATE,
+        Some(v) if v != object::version(key) => {
+            return ApiResponse::failure(format!("version {v} of the image data was replaced"))
+                .not_found()
+        }
+        Some(_) => match ImageVisibility::parse_str(&image.visibility).unwrap_or_default() {
+            ImageVisibility::Public => object::IMMUTABLE,
+            ImageVisibility::Private | ImageVisibility::Shared => object::IMMUTABLE_PRIVATE,
+        },
+    };
+    let etag = object::etag(key);
+    if object::is_cached(headers, &etag) {
+        return object::not_modified(&etag, cache_control);
+    }
+
+    match state.storage.get(key).await {
+        Ok(object) => {
+            let content_type = object::image_content_type(&object.data);
+            object::object_response(headers, object, &etag, content_type, cache_control)
+        }
+        Err(e @ StorageError::NotFound(_)) => ApiResponse::failure(e).not_found(),
+        Err(e) => ApiResponse::failure(e).internal_server_error(),
+    }
+}
+
+/// Streams the progress of the image as `progress` events until it is finished.
+async fn image_progress(
+    claims: Option<Claims>,
+    State(state): State<SharedAppState>,
+    Path(id): Path<Uuid>,
+) -> Response {
+    let db = &state.db;
+    let image = match view_image(claims, db, &id).await {
+        Ok(image) => image,
+        Err(response) => return response,
+    };
+
+    let rx_progress = match state.image_progress.subscribe(&id.to_string()) {
+        Some(rx_progress) => rx_progress,
+        // The image isn't queued or generated right now, send its state from the database
+        // once. Dropping the sender ends the stream after the first event.
+        None => {
+            watch::channel(ImageProgress {
+                image_id: image.id.to_string(),
+                queued: false,
+                sample: image.num_samples,
                 num_samples: image.num_samples,
-                processing: image.processing,
-                create_date: image.create_date,
-                guidance_scale: image.guidance_scale,
+                timestep: image.n_steps as usize,
+                n_steps: image.n_steps as usize,
+                finished: !image.processing,
+                preview: None,
             })
-            .map_err(Error::from),
-    )
+            .1
+        }
+    };
+
+    let stream = futures_util::stream::unfold(Some((rx_progress, true)), |state| async move {
+        let (mut rx_progress, first) = state?;
+        if !first && rx_progress.changed().await.is_err() {
+            return None;
+        }
+        let progress = rx_progress.borrow_and_update().clone();
+        let next = (!progress.finished).then_some((rx_progress, false));
+        Some((Event::default().event("progress").json_data(progress), next))
+    });
+
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+async fn get_image_metadata(
+    claims: Option<Claims>,
+    state: State<SharedAppState>,
+    Path(id): Path<Uuid>,
+) -> Response {
+    let db = &state.db;
+
+    match view_image(claims, db, &id).await {
+        Ok(image) => ApiResponse::success(image_inspect(image)).ok(),
+        Err(response) => response,
+    }
+}
+
+/// Changes who besides the owner can view the image.
+async fn update_visibility(
+    claims: Claims,
+    state: State<SharedAppState>,
+    Path(id): Path<Uuid>,
+    Json(request): Json<ImageVisibilityRequest>,
+) -> Response {
+    let db = &state.db;
+    let user = with_user_guard!(claims, db);
+
+    if let Err(response) = authorize_image(db, Some(&user), &id, Access::Modify).await {
+        return response;
+    }
+
+    match Image::update_visibility(db, &id, request.visibility).await {
+        Ok(()) => ApiResponse::success(request.visibility).ok(),
+        Err(e) => ApiResponse::failure(e).internal_server_error(),
+    }
 }
 
 async fn delete_image(
@@ -240,9 +800,34 @@ async fn delete_image(
     Path(id): Path<Uuid>,
 ) -> Response {
     let db = &state.db;
-    with_user_guard!(claims, db);
+    let user = with_user_guard!(claims, db);
+
+    let image = match authorize_image(db, Some(&user), &id, Access::Modify).await {
+        Ok(image) => image,
+        Err(response) => return response,
+    };
+    let samples = match ImageSample::get_image_samples(db, &id).await {
+        Ok(samples) => samples,
+        Err(e) => return ApiResponse::failure(e).internal_server_error(),
+    };
+    if let Err(e) = Image::delete(db, &id).await {
+        return ApiResponse::failure(e).internal_server_error();
+    }
+
+    // Objects that fail to be deleted are only orphaned, the image itself is gone
+    let keys = samples
+        .into_iter()
+        .map(|sample| sample.data_key)
+        .chain(image.input_image_key)
+        .chain(image.mask_key)
+        .chain(image.thumbnail_key);
+    for key in keys {
+        if let Err(e) = state.storage.delete(&key).await {
+            log::error!("[{id}] failed to delete image data - {e}");
+        }
+    }
 
-    handle_db_result_as_json(Image::delete(db, &id).await.map_err(Error::from))
+    ApiResponse::success(()).ok()
 }
 
 async fn list_models(claims: Claims, state: State<SharedAppState>) -> Response {
@@ -257,6 +842,10 @@ async fn list_models(claims: Claims, state: State<SharedAppState>) -> Response {
                     .into_iter()
                     .map(|model| ImageModelListEntry {
                         model_id: model.model_id.to_string(),
+                        residency: state
+                            .image_models
+                            .get(&model.name)
+                            .map(|handle| handle.residency(&model.name)),
                         name: model.name,
                         description: model.description,
                         features: ImageModelFeatures {
@@ -1,6 +1,8 @@
+pub mod admin;
 pub mod chat;
 pub mod image;
 pub mod prompt;
+pub mod usage;
 pub mod users;
 
 use crate::ApiVersion;
@@ -11,7 +13,9 @@ pub fn router() -> Router<crate::SharedAppState> {
     let base = Router::new()
         .nest("/users", users::router())
         .nest("/llm", chat::router().merge(prompt::router()))
-        .nest("/image", image::router());
+        .nest("/image", image::router())
+        .nest("/usage", usage::router())
+        .nest("/admin", admin::router());
 
     Router::new().nest(&format!("/api/{}", ApiVersion::V1.as_ref()), base)
 }
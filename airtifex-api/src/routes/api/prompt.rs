@@ -1,22 +1,29 @@
 use crate::{
-    auth::Claims, gen::llm::InferenceRequest, id::Uuid, models::prompt::Prompt,
-    routes::handle_db_result_as_json, Error, SharedAppState, ToAxumResponse,
+    auth::Claims,
+    gen::llm::InferenceRequest,
+    id::Uuid,
+    models::prompt::Prompt,
+    quota::Generation,
+    routes::{enforce_quota, handle_db_result_as_json, inference_event_stream, release_quota},
+    Error, SharedAppState, ToAxumResponse,
 };
 use airtifex_core::{
     api_response::ApiResponse,
-    llm::{ChatStreamResult, InferenceSettings, OneshotInferenceRequest, PromptInspect},
+    llm::{
+        InferenceEvent, InferenceSettings, OneshotInferenceRequest, PromptInspect, PromptListQuery,
+    },
 };
 
 use axum::{
-    body::StreamBody,
-    extract::{Json, Path, State},
-    response::{IntoResponse, Response},
+    extract::{Json, Path, Query, State},
+    response::Response,
     routing, Router,
 };
 
 pub fn router() -> Router<SharedAppState> {
     Router::new()
         .route("/inference", routing::post(oneshot_inference))
+        .route("/inference/:id", routing::delete(cancel_inference))
         .route("/prompt", routing::get(list))
         .route(
             "/prompt/:id",
@@ -30,17 +37,31 @@ async fn oneshot_inference(
     Json(request): Json<OneshotInferenceRequest>,
 ) -> Response {
     let db = &state.db;
-    with_user_guard!(claims, db);
+    let user = with_user_guard!(claims, db);
+
+    let Some(model) = state.llms.get(&request.model) else {
+        return ApiResponse::failure(format!("failed to find model {}", &request.model))
+            .internal_server_error();
+    };
+    if let Err(response) = enforce_quota(&state, &user, Generation::Text).await {
+        return response;
+    }
 
     let (tx_tokens, rx_tokens): (
-        flume::Sender<ChatStreamResult>,
-        flume::Receiver<ChatStreamResult>,
+        flume::Sender<InferenceEvent>,
+        flume::Receiver<InferenceEvent>,
     ) = flume::unbounded();
 
+    let (inference_id, cancellation) = state
+        .inference_registry
+        .register(&claims.sub, &request.model);
     let inference_request = InferenceRequest {
+        id: inference_id,
+        cancellation,
         tx_tokens,
         save: request.save,
         user: claims.sub,
+        priority: state.config().priorities.of(user.account_type),
         chat_data: None,
         prompt: request.prompt,
         settings: InferenceSettings {
@@ -56,50 +77,77 @@ async fn oneshot_inference(
     };
     log::info!("{inference_request:?}");
 
-    if let Some((_, model)) = state.tx_inference_req.get(&request.model) {
-        if let Err(e) = model.send_async(inference_request).await {
-            return ApiResponse::failure(e).internal_server_error();
+    let _ = inference_request.tx_tokens.send(InferenceEvent::Queued(
+        model.next_position(&inference_request.user, inference_request.priority),
+    ));
+    if let Err(e) = model.submit(inference_request) {
+        state.inference_registry.remove(&inference_id);
+        release_quota(&state, &user.username, Generation::Text).await;
+        return ApiResponse::failure(e).internal_server_error();
+    }
+
+    inference_event_stream(inference_id, rx_tokens)
+}
+
+async fn cancel_inference(
+    claims: Claims,
+    State(state): State<SharedAppState>,
+    Path(id): Path<Uuid>,
+) -> Response {
+    let db = &state.db;
+    let user = with_user_guard!(claims, db);
+
+    let Some(inference) = state.inference_registry.get(&id) else {
+        return ApiResponse::failure(format!("inference {id} not found")).not_found();
+    };
+    if inference.user != user.username && !user.is_admin() {
+        return ApiResponse::failure("not allowed to cancel this inference").unauthorized();
+    }
+
+    log::info!("cancelling inference {id} of user {}", inference.user);
+    inference.cancellation.cancel();
+
+    // Requests that haven't started yet are removed right away, running ones are stopped by
+    // the inference thread
+    if let Some(model) = state.llms.get(&inference.model) {
+        for request in model.queue.remove_where(|request| request.id == id) {
+            request.cancel_queued();
+            state.inference_registry.remove(&id);
         }
-    } else {
-        return ApiResponse::failure(format!("failed to find model {}", &request.model))
-            .internal_server_error();
     }
 
-    (
-        [
-            (axum::http::header::CONTENT_TYPE, "text/event-stream"),
-            (axum::http::header::TRANSFER_ENCODING, "chunked"),
-        ],
-        StreamBody::new(rx_tokens.into_stream()),
-    )
-        .into_response()
+    ApiResponse::success(()).ok()
+}
+
+pub(crate) fn prompt_inspect(p: Prompt) -> PromptInspect {
+    PromptInspect {
+        id: p.id.to_string(),
+        prompt: p.prompt,
+        date: p.date,
+        username: p.username,
+        response: p.response,
+        model: p.model,
+        n_batch: p.n_batch.map(|v| v as usize),
+        num_predict: p.num_predict.map(|v| v as usize),
+        top_k: p.top_k.map(|v| v as usize),
+        top_p: p.top_p,
+        repeat_penalty: p.repeat_penalty,
+        temp: p.temp,
+    }
 }
 
-async fn list(claims: Claims, State(state): State<SharedAppState>) -> Response {
+async fn list(
+    claims: Claims,
+    State(state): State<SharedAppState>,
+    Query(query): Query<PromptListQuery>,
+) -> Response {
     let db = &state.db;
     with_user_guard!(claims, db);
 
     handle_db_result_as_json(
-        Prompt::list_prompts_of_user(db, &claims.sub)
+        Prompt::list_prompts_of_user(db, &claims.sub, &query)
             .await
-            .map(|p| {
-                p.into_iter()
-                    .map(|p| PromptInspect {
-                        id: p.id.to_string(),
-                        prompt: p.prompt,
-                        date: p.date,
-                        username: p.username,
-                        response: p.response,
-                        model: p.model,
-                        n_batch: p.n_batch.map(|v| v as usize),
-                        num_predict: p.num_predict.map(|v| v as usize),
-                        top_k: p.top_k.map(|v| v as usize),
-                        top_p: p.top_p,
-                        repeat_penalty: p.repeat_penalty,
-                        temp: p.temp,
-                    })
-                    .collect::<Vec<_>>()
-            })
+            .map(|p| p.into_iter().map(prompt_inspect).collect::<Vec<_>>())
             .map_err(Error::from),
     )
 }
@@ -115,20 +163,7 @@ async fn get_prompt(
     handle_db_result_as_json(
         Prompt::get_prompt_for_user(db, &claims.sub, &id)
             .await
-            .map(|p| PromptInspect {
-                id: p.id.to_string(),
-                prompt: p.prompt,
-                date: p.date,
-                username: p.username,
-                response: p.response,
-                model: p.model,
-                n_batch: p.n_batch.map(|v| v as usize),
-                num_predict: p.num_predict.map(|v| v as usize),
-                top_k: p.top_k.map(|v| v as usize),
-                top_p: p.top_p,
-                repeat_penalty: p.repeat_penalty,
-                temp: p.temp,
-            })
+            .map(prompt_inspect)
             .map_err(Error::from),
     )
 }
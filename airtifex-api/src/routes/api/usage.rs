@@ -0,0 +1,25 @@
+use crate::{
+    auth::Claims, models::usage::Usage, routes::handle_db_result_as_json, Error, SharedAppState,
+};
+use airtifex_core::usage::UsageQuery;
+
+use axum::{
+    extract::{Query, State},
+    response::Response,
+    routing, Router,
+};
+
+pub fn router() -> Router<SharedAppState> {
+    Router::new().route("/", routing::get(report))
+}
+
+async fn report(
+    claims: Claims,
+    State(state): State<SharedAppState>,
+    Query(query): Query<UsageQuery>,
+) -> Response {
+    let db = &state.db;
+    with_admin_guard!(claims, db);
+
+    handle_db_result_as_json(Usage::report(db, &query).await.map_err(Error::from))
+}
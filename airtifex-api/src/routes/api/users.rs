@@ -1,13 +1,14 @@
 use crate::{
     auth::{generate_jwt, Claims, JsonWebToken},
     errors::Error,
-    models::user::User,
-    routes::handle_db_result_as_json,
+    models::{usage::Usage, user::User},
+    routes::{export, handle_db_result_as_json},
     SharedAppState, ToAxumResponse,
 };
 use airtifex_core::{
     api_response::ApiResponse,
     auth::Credentials,
+    usage::UsageQuery,
     user::{
         GetUserEntry, ListQuery, ListUserEntry, PasswordChangeRequest, UserEditRequest,
         UserRegisterRequest,
@@ -15,15 +16,19 @@ use airtifex_core::{
 };
 
 use axum::{
+    body::StreamBody,
     extract::{Path, Query, State},
     response::Response,
     routing, Json, Router,
 };
+use tokio_util::io::ReaderStream;
 
 pub fn router() -> Router<SharedAppState> {
     Router::new()
         .route("/", routing::get(list).post(register))
         .route("/me", routing::get(me))
+        .route("/me/usage", routing::get(my_usage))
+        .route("/me/export", routing::get(my_export))
         .route("/login", routing::post(auth))
         .route("/:user", routing::get(info).post(update).delete(remove))
         .route("/:user/password", routing::post(change_password))
@@ -35,6 +40,40 @@ async fn me(claims: Claims, state: State<SharedAppState>) -> Response {
     ApiResponse::success(user).ok()
 }
 
+async fn my_usage(
+    claims: Claims,
+    state: State<SharedAppState>,
+    Query(query): Query<UsageQuery>,
+) -> Response {
+    let db = &state.db;
+    with_user_guard!(claims, db);
+
+    let query = UsageQuery {
+        username: Some(claims.sub),
+        ..query
+    };
+    handle_db_result_as_json(Usage::report(db, &query).await.map_err(Error::from))
+}
+
+async fn my_export(claims: Claims, state: State<SharedAppState>) -> Response {
+    let db = &state.db;
+    with_user_guard!(claims, db);
+
+    let user = match User::get(db, &claims.sub).await {
+        Ok(user) => user,
+        Err(e) => return ApiResponse::failure(e).internal_server_error(),
+    };
+
+    match export::user_archive(db, state.storage.as_ref(), &user).await {
+        Ok(archive) => export::attachment(
+            "application/zip",
+            "airtifex-export.zip",
+            StreamBody::new(ReaderStream::new(archive)),
+        ),
+        Err(e) => ApiResponse::failure(e).internal_server_error(),
+    }
+}
+
 async fn info(
     claims: Claims,
     state: State<SharedAppState>,
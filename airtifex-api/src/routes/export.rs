@@ -0,0 +1,322 @@
+//! Exports of chats and archives of everything a user created, to keep them or to move them to
+//! another instance.
+
+use crate::{
+    id::Uuid,
+    models::{
+        chat::Chat, chat_entry::ChatEntry, image::Image, image_sample::ImageSample, listing,
+        prompt::Prompt, user::User,
+    },
+    routes::{api, object},
+    storage::{Storage, StorageError},
+    DbPool,
+};
+use airtifex_core::{
+    image::ImageListQuery,
+    llm::{ChatEntryType, ChatExport, ChatExportEntry, ChatListQuery, PromptListQuery},
+    query::SortOrder,
+};
+
+use axum::{
+    body::{self, Bytes, HttpBody},
+    http::header,
+    response::Response,
+    BoxError,
+};
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs::File,
+    io::{self, Seek, Write},
+};
+use zip::{result::ZipError, write::FileOptions, CompressionMethod, ZipWriter};
+
+/// Responds with a file that the browser saves instead of displaying it.
+pub fn attachment<B>(content_type: &str, filename: &str, body: B) -> Response
+where
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{filename}\""),
+        )
+        .body(body::boxed(body))
+        .unwrap()
+}
+
+/// Returns the chat with its settings and the entries of all branches.
+pub async fn chat_export(db: &DbPool, chat: Chat) -> crate::Result<ChatExport> {
+    let active_id = ChatEntry::get_chat_entries(db, &chat.id, &chat.username)
+        .await?
+        .last()
+        .map(|entry| entry.entry_id.to_string());
+    let entries = ChatEntry::get_all_chat_entries(db, &chat.id).await?;
+    Ok(ChatExport {
+        settings: chat.settings(),
+        title: chat.title,
+        model: chat.model,
+        start_date: chat.start_date,
+        entries: entries
+            .into_iter()
+            .map(|entry| ChatExportEntry {
+                id: Some(entry.entry_id.to_string()),
+                parent_id: entry.parent_entry_id.map(|id| id.to_string()),
+                entry_type: entry.entry_type,
+                content: entry.content,
+                entry_date: entry.entry_date,
+            })
+            .collect(),
+        active_id,
+    })
+}
+
+/// Recreates the entries of an exported chat under new IDs for the chat `chat_id`. Returns them
+/// in an order in which they can be saved, parents first, with the new ID of the active entry.
+///
+/// Entries of exports without IDs follow the previous entry, the last one is active.
+pub fn import_entries(
+    chat_id: Uuid,
+    chat: ChatExport,
+) -> Result<(Vec<ChatEntry>, Option<Uuid>), String> {
+    let mut ids = HashMap::new();
+    let mut entries: Vec<ChatEntry> = vec![];
+    for entry in chat.entries {
+        let parent_entry_id = match (&entry.id, &entry.parent_id) {
+            (None, _) => entries.last().map(|entry| entry.entry_id),
+            (Some(_), None) => None,
+            (Some(id), Some(parent)) => match ids.get(parent) {
+                Some(parent) => Some(*parent),
+                None => {
+                    return Err(format!(
+                        "entry {id} follows an unknown or later entry {parent}"
+                    ))
+                }
+            },
+        };
+        let imported = ChatEntry {
+            entry_type: entry.entry_type,
+            entry_date: entry.entry_date,
+            ..ChatEntry::new_user(chat_id, parent_entry_id, entry.content)
+        };
+        if let Some(id) = entry.id {
+            if ids.insert(id.clone(), imported.entry_id).is_some() {
+                return Err(format!("entry {id} is exported more than once"));
+            }
+        }
+        entries.push(imported);
+    }
+
+    let active_entry_id = match chat.active_id {
+        Some(id) => match ids.get(&id) {
+            Some(active) => Some(*active),
+            None => return Err(format!("active entry {id} is not a part of the chat")),
+        },
+        None if ids.is_empty() => entries.last().map(|entry| entry.entry_id),
+        None => None,
+    };
+    Ok((entries, active_entry_id))
+}
+
+/// Renders the active branch of the chat as a Markdown document with a section for every
+/// message.
+pub fn chat_markdown(chat: &ChatExport) -> String {
+    let mut markdown = format!("# {}\n\n", chat.title);
+    let _ = writeln!(markdown, "- Model: `{}`", chat.model);
+    let _ = writeln!(
+        markdown,
+        "- Started: {}",
+        chat.start_date.format("%a, %d %b %Y %H:%M:%S UTC")
+    );
+    if let Some(system_prompt) = &chat.settings.system_prompt {
+        let _ = writeln!(markdown, "- System prompt: {system_prompt}");
+    }
+    for entry in chat.active_branch() {
+        let author = match entry.entry_type {
+            ChatEntryType::User => "User",
+            ChatEntryType::Bot => "Assistant",
+        };
+        let _ = write!(markdown, "\n## {author}\n\n{}\n", entry.content.trim_end());
+    }
+    markdown
+}
+
+/// A file of an archive sent to the thread writing the archive.
+struct ArchiveFile {
+    path: String,
+    options: FileOptions,
+    data: Vec<u8>,
+}
+
+type ArchiveFiles = flume::Sender<ArchiveFile>;
+
+async fn add_file(
+    files: &ArchiveFiles,
+    path: String,
+    options: FileOptions,
+    data: Vec<u8>,
+) -> crate::Result<()> {
+    files
+        .send_async(ArchiveFile {
+            path,
+            options,
+            data,
+        })
+        .await
+        .map_err(|_| {
+            ZipError::from(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the archive writer stopped",
+            ))
+        })?;
+    Ok(())
+}
+
+/// Writes the received files into a zip archive in an anonymous temporary file that is removed
+/// once it is closed. Returns the file positioned at its start.
+fn write_archive(files: flume::Receiver<ArchiveFile>) -> Result<File, ZipError> {
+    let mut archive = ZipWriter::new(tempfile::tempfile()?);
+    for file in files.iter() {
+        archive.start_file(file.path, file.options)?;
+        archive.write_all(&file.data)?;
+    }
+    let mut file = archive.finish()?;
+    file.rewind()?;
+    Ok(file)
+}
+
+/// Returns a zip archive with all chats, prompts and images of the user. Chats are included both
+/// as JSON that can be imported again and as Markdown.
+///
+/// The archive is compressed and written on a blocking thread while its files are collected, the
+/// returned file can be streamed to the client.
+pub async fn user_archive(
+    db: &DbPool,
+    storage: &dyn Storage,
+    user: &User,
+) -> crate::Result<tokio::fs::File> {
+    let (tx_files, rx_files) = flume::bounded(4);
+    let writer = tokio::task::spawn_blocking(move || write_archive(rx_files));
+    let collected = archive_files(db, storage, user, &tx_files).await;
+    drop(tx_files);
+
+    // an error of the writer is the reason the files couldn't be sent
+    let file = writer
+        .await
+        .map_err(|e| ZipError::from(io::Error::new(io::ErrorKind::Other, e)))??;
+    collected?;
+    Ok(tokio::fs::File::from_std(file))
+}
+
+/// Sends all files of the archive of the user to `files`.
+async fn archive_files(
+    db: &DbPool,
+    storage: &dyn Storage,
+    user: &User,
+    files: &ArchiveFiles,
+) -> crate::Result<()> {
+    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    // images are compressed already
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+
+    for page in 1.. {
+        let query = ChatListQuery {
+            page: Some(page),
+            page_size: Some(listing::MAX_PAGE_SIZE),
+            order: Some(SortOrder::Asc),
+            ..Default::default()
+        };
+        let chats = Chat::list_chats_of_user(db, &user.username, &query).await?;
+        if chats.is_empty() {
+            break;
+        }
+        for chat in chats {
+            let id = chat.id;
+            let chat = chat_export(db, chat).await?;
+            let json = serde_json::to_vec_pretty(&chat)?;
+            add_file(files, format!("chats/{id}.json"), deflated, json).await?;
+            let markdown = chat_markdown(&chat);
+            add_file(
+                files,
+                format!("chats/{id}.md"),
+                deflated,
+                markdown.into_bytes(),
+            )
+            .await?;
+        }
+    }
+
+    let mut prompts = vec![];
+    for page in 1.. {
+        let query = PromptListQuery {
+            page: Some(page),
+            page_size: Some(listing::MAX_PAGE_SIZE),
+            order: Some(SortOrder::Asc),
+            ..Default::default()
+        };
+        let page = Prompt::list_prompts_of_user(db, &user.username, &query).await?;
+        if page.is_empty() {
+            break;
+        }
+        prompts.extend(page.into_iter().map(api::prompt::prompt_inspect));
+    }
+    let json = serde_json::to_vec_pretty(&prompts)?;
+    add_file(files, "prompts.json".into(), deflated, json).await?;
+
+    for page in 1.. {
+        let query = ImageListQuery {
+            page: Some(page),
+            page_size: Some(listing::MAX_PAGE_SIZE),
+            order: Some(SortOrder::Asc),
+            ..Default::default()
+        };
+        let images = Image::list(db, Some(&user.id), &query).await?;
+        if images.is_empty() {
+            break;
+        }
+        for image in images {
+            let id = image.id;
+            let samples = ImageSample::get_image_samples(db, &id).await?;
+            let objects = samples
+                .into_iter()
+                .map(|sample| (format!("samples/{}", sample.n), sample.data_key))
+                .chain(
+                    image
+                        .input_image_key
+                        .clone()
+                        .map(|key| ("input".into(), key)),
+                )
+                .chain(image.mask_key.clone().map(|key| ("mask".into(), key)))
+                .collect::<Vec<_>>();
+
+            let json = serde_json::to_vec_pretty(&api::image::image_inspect(image))?;
+            add_file(files, format!("images/{id}/image.json"), deflated, json).await?;
+
+            for (name, key) in objects {
+                let data = match storage.get(&key).await {
+                    Ok(object) => object.data,
+                    // an archive without a lost object is more useful than no archive
+                    Err(e @ StorageError::NotFound(_)) => {
+                        log::warn!("[{id}] skipping image data in export - {e}");
+                        continue;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                let extension = object::image_content_type(&data)
+                    .strip_prefix("image/")
+                    .unwrap_or("bin");
+                add_file(
+                    files,
+                    format!("images/{id}/{name}.{extension}"),
+                    stored,
+                    data,
+                )
+                .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
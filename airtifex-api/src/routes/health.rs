@@ -0,0 +1,77 @@
+use crate::{models, SharedAppState};
+use airtifex_core::health::{DatabaseHealth, HealthReport};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing, Json, Router,
+};
+
+pub fn router() -> Router<SharedAppState> {
+    Router::new()
+        .route("/healthz", routing::get(healthz))
+        .route("/readyz", routing::get(readyz))
+}
+
+/// Reports whether the server can serve requests, models that are still loading or failed to
+/// load don't affect the status.
+async fn healthz(State(state): State<SharedAppState>) -> Response {
+    let report = health_report(&state).await;
+    let code = if report.database.connected {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(report)).into_response()
+}
+
+/// Reports whether the server is ready, that is the database is reachable and all models loaded.
+async fn readyz(State(state): State<SharedAppState>) -> Response {
+    let report = health_report(&state).await;
+    let code = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(report)).into_response()
+}
+
+async fn health_report(state: &SharedAppState) -> HealthReport {
+    let database = match models::ping(&state.db).await {
+        Ok(()) => DatabaseHealth {
+            connected: true,
+            error: None,
+        },
+        Err(e) => DatabaseHealth {
+            connected: false,
+            error: Some(e.to_string()),
+        },
+    };
+
+    let llms = state
+        .llms
+        .all()
+        .into_iter()
+        .map(|(name, model)| model.health(&name))
+        .collect::<Vec<_>>();
+    let image_models = state
+        .image_models
+        .all()
+        .into_iter()
+        .map(|(name, model)| model.health(&name))
+        .collect::<Vec<_>>();
+
+    let ready = database.connected
+        && llms
+            .iter()
+            .chain(image_models.iter())
+            .all(|model| model.state.is_available());
+
+    HealthReport {
+        ready,
+        database,
+        llms,
+        image_models,
+    }
+}
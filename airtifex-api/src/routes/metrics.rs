@@ -0,0 +1,18 @@
+use crate::SharedAppState;
+
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing, Router,
+};
+
+pub fn router() -> Router<SharedAppState> {
+    Router::new().route("/metrics", routing::get(metrics))
+}
+
+async fn metrics() -> Response {
+    match crate::metrics::gather() {
+        Ok(metrics) => ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], metrics).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
@@ -1,15 +1,72 @@
 pub mod api;
+pub mod export;
+pub mod health;
+pub mod metrics;
+pub mod object;
+pub mod openai;
 pub mod r#static;
 
-use crate::ToAxumResponse;
-use airtifex_core::api_response::ApiResponse;
+use crate::{
+    id::Uuid,
+    quota::{self, Generation, QuotaError},
+    SharedAppState, ToAxumResponse,
+};
+use airtifex_core::{api_response::ApiResponse, llm::InferenceEvent, user::AuthenticatedUser};
 
-use axum::response::Response;
+use axum::response::{
+    sse::{Event, KeepAlive, Sse},
+    IntoResponse, Response,
+};
+use futures_util::StreamExt;
 use serde::Serialize;
 
+/// Header carrying the ID of an inference request that can be used to cancel it.
+pub const INFERENCE_ID_HEADER: &str = "x-inference-id";
+
 fn handle_db_result_as_json<T: Serialize>(result: crate::Result<T>) -> Response {
     match result {
         Ok(data) => ApiResponse::success(&data).ok(),
         Err(e) => ApiResponse::failure(e).internal_server_error(),
     }
 }
+
+/// Checks the quotas of the user and records the request. On failure returns a response that
+/// should be sent back to the client.
+async fn enforce_quota(
+    state: &SharedAppState,
+    user: &AuthenticatedUser,
+    generation: Generation,
+) -> Result<(), Response> {
+    let quota = state.config().quotas.of(&user.username, user.account_type);
+    match quota::acquire(&state.db, &quota, &user.username, generation).await {
+        Ok(()) => Ok(()),
+        Err(e @ QuotaError::Database(_)) => Err(ApiResponse::failure(e).internal_server_error()),
+        Err(e) => Err(ApiResponse::failure(e).too_many_requests()),
+    }
+}
+
+/// Gives back the quota charged by [`enforce_quota`] for a request that failed to be queued.
+async fn release_quota(state: &SharedAppState, username: &str, generation: Generation) {
+    if let Err(e) = quota::release(&state.db, username, generation).await {
+        log::error!("failed to release the quota of user {username} - {e}");
+    }
+}
+
+/// Streams inference events to the client as named Server-Sent Events with JSON data.
+fn inference_event_stream(id: Uuid, rx_events: flume::Receiver<InferenceEvent>) -> Response {
+    let stream = rx_events.into_stream().map(|event| {
+        let sse = Event::default().event(event.name());
+        match &event {
+            InferenceEvent::Queued(position) => sse.json_data(position),
+            InferenceEvent::Token(token) => sse.json_data(token),
+            InferenceEvent::Error(error) => sse.json_data(error),
+            InferenceEvent::Done(done) => sse.json_data(done),
+        }
+    });
+
+    (
+        [(INFERENCE_ID_HEADER, id.to_string())],
+        Sse::new(stream).keep_alive(KeepAlive::default()),
+    )
+        .into_response()
+}
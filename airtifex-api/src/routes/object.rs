@@ -0,0 +1,322 @@
+//! Responses serving stored objects as is with support for conditional and range requests.
+
+use crate::storage::StoredObject;
+
+use axum::{
+    body::{self, Empty, Full},
+    http::{
+        header::{self, HeaderMap, HeaderValue},
+        StatusCode,
+    },
+    response::Response,
+};
+use chrono::{DateTime, Timelike, Utc};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// Stored objects are never modified, an object that changes is stored under a new key. URLs that
+/// carry the version of the key can be cached forever.
+pub const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+/// Same as [`IMMUTABLE`] for objects that must not be stored by shared caches.
+pub const IMMUTABLE_PRIVATE: &str = "private, max-age=31536000, immutable";
+/// URLs without a version may point to another object later so cached objects have to be
+/// revalidated, unchanged ones are answered with `304 Not Modified`.
+pub const REVALIDATE: &str = "private, no-cache";
+
+#[derive(Debug, Default, Deserialize)]
+pub struct VersionQuery {
+    /// Version of the requested object as returned by [`version`]
+    pub v: Option<String>,
+}
+
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Returns the MIME type of an image from its signature.
+pub fn image_content_type(data: &[u8]) -> &'static str {
+    match data {
+        [0x89, b'P', b'N', b'G', ..] => "image/png",
+        [0xFF, 0xD8, 0xFF, ..] => "image/jpeg",
+        [b'G', b'I', b'F', b'8', ..] => "image/gif",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+        [b'B', b'M', ..] => "image/bmp",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Version of the object stored under the key. As objects are never modified the key identifies
+/// the content, the version only keeps the storage layout out of URLs and entity tags.
+pub fn version(key: &str) -> String {
+    Sha256::digest(key.as_bytes())[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Entity tag of the object stored under the key, known without reading the object.
+pub fn etag(key: &str) -> String {
+    format!("\"{}\"", version(key))
+}
+
+/// Whether the client already has the object with the entity tag, it can be answered with
+/// [`not_modified`] without reading the object.
+pub fn is_cached(request_headers: &HeaderMap, etag: &str) -> bool {
+    if_none_match(request_headers, etag) == Some(true)
+}
+
+/// Responds with `304 Not Modified`.
+pub fn not_modified(etag: &str, cache_control: &'static str) -> Response {
+    Response::builder()
+        .status(StatusCode::NOT_MODIFIED)
+        .header(header::ETAG, etag)
+        .header(header::CACHE_CONTROL, cache_control)
+        .body(body::boxed(Empty::new()))
+        .unwrap()
+}
+
+/// Responds with the object answering conditional requests with `304 Not Modified` and
+/// single byte ranges with `206 Partial Content`.
+pub fn object_response(
+    request_headers: &HeaderMap,
+    object: StoredObject,
+    etag: &str,
+    content_type: &str,
+    cache_control: &'static str,
+) -> Response {
+    // HTTP dates have a resolution of seconds
+    let last_modified = object
+        .last_modified
+        .and_then(|date| date.with_nanosecond(0));
+
+    let mut builder = Response::builder()
+        .header(header::ETAG, etag)
+        .header(header::CACHE_CONTROL, cache_control)
+        .header(header::ACCEPT_RANGES, "bytes");
+    if let Some(last_modified) = last_modified {
+        builder = builder.header(
+            header::LAST_MODIFIED,
+            last_modified.format(HTTP_DATE).to_string(),
+        );
+    }
+
+    if is_not_modified(request_headers, etag, last_modified) {
+        return builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(body::boxed(Empty::new()))
+            .unwrap();
+    }
+
+    let len = object.data.len();
+    let range = request_headers
+        .get(header::RANGE)
+        .and_then(|range| range.to_str().ok())
+        // a range of a different version of the object is ignored
+        .filter(|_| {
+            request_headers
+                .get(header::IF_RANGE)
+                .map_or(true, |if_range| if_range.as_bytes() == etag.as_bytes())
+        })
+        .and_then(|range| parse_range(range, len));
+
+    let builder = builder.header(header::CONTENT_TYPE, content_type);
+    match range {
+        None => builder
+            .status(StatusCode::OK)
+            .body(body::boxed(Full::from(object.data)))
+            .unwrap(),
+        Some(Ok((start, end))) => builder
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_RANGE, format!("bytes {start}-{end}/{len}"))
+            .body(body::boxed(Full::from(object.data[start..=end].to_vec())))
+            .unwrap(),
+        Some(Err(())) => builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{len}"))
+            .body(body::boxed(Empty::new()))
+            .unwrap(),
+    }
+}
+
+/// Whether `If-None-Match` matches the entity tag, `None` if the header wasn't sent.
+fn if_none_match(request_headers: &HeaderMap, etag: &str) -> Option<bool> {
+    let if_none_match = request_headers.get(header::IF_NONE_MATCH)?;
+    Some(if_none_match.to_str().map_or(false, |tags| {
+        tags.split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+    }))
+}
+
+fn is_not_modified(
+    request_headers: &HeaderMap,
+    etag: &str,
+    last_modified: Option<DateTime<Utc>>,
+) -> bool {
+    // If-Modified-Since is ignored when the client sent an entity tag
+    if let Some(matches) = if_none_match(request_headers, etag) {
+        return matches;
+    }
+    match (
+        request_headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(parse_http_date),
+        last_modified,
+    ) {
+        (Some(since), Some(last_modified)) => last_modified <= since,
+        _ => false,
+    }
+}
+
+fn parse_http_date(date: &HeaderValue) -> Option<DateTime<Utc>> {
+    let date = date.to_str().ok()?;
+    DateTime::parse_from_rfc2822(date)
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+/// Parses a `Range` header with a single range of bytes into inclusive bounds. Returns `None` if
+/// the header isn't supported and should be ignored and `Some(Err(()))` if no byte of the range
+/// is part of the object.
+fn parse_range(range: &str, len: usize) -> Option<Result<(usize, usize), ()>> {
+    let range = range.trim().strip_prefix("bytes=")?;
+    if range.contains(',') {
+        return None;
+    }
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    let bounds = if start.is_empty() {
+        // suffix range with the number of bytes at the end of the object
+        let suffix = end.parse::<usize>().ok()?;
+        if suffix == 0 {
+            return Some(Err(()));
+        }
+        (len.saturating_sub(suffix), len.checked_sub(1))
+    } else {
+        let start = start.parse::<usize>().ok()?;
+        let end = if end.is_empty() {
+            len.checked_sub(1)
+        } else {
+            let end = end.parse::<usize>().ok()?;
+            if end < start {
+                return None;
+            }
+            len.checked_sub(1).map(|last| end.min(last))
+        };
+        (start, end)
+    };
+
+    match bounds {
+        (start, Some(end)) if start <= end => Some(Ok((start, end))),
+        _ => Some(Err(())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_str(value).unwrap()))
+            .collect()
+    }
+
+    fn date(date: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc2822(date)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn parses_byte_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Ok((0, 99))));
+        assert_eq!(parse_range("bytes=100-", 1000), Some(Ok((100, 999))));
+        // the end is clamped to the object
+        assert_eq!(parse_range("bytes=900-1999", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range(" bytes= 5 - 9 ", 1000), Some(Ok((5, 9))));
+    }
+
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(parse_range("bytes=-100", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=-2000", 1000), Some(Ok((0, 999))));
+        assert_eq!(parse_range("bytes=-0", 1000), Some(Err(())));
+    }
+
+    #[test]
+    fn rejects_unsatisfiable_ranges() {
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=1000-1999", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=0-", 0), Some(Err(())));
+        assert_eq!(parse_range("bytes=-10", 0), Some(Err(())));
+    }
+
+    #[test]
+    fn ignores_unsupported_ranges() {
+        assert_eq!(parse_range("bytes=0-9,20-29", 1000), None);
+        assert_eq!(parse_range("bytes=9-0", 1000), None);
+        assert_eq!(parse_range("items=0-9", 1000), None);
+        assert_eq!(parse_range("bytes=a-9", 1000), None);
+        assert_eq!(parse_range("bytes=5", 1000), None);
+    }
+
+    #[test]
+    fn matches_entity_tags() {
+        let etag = "\"0123456789abcdef\"";
+        for if_none_match in [
+            etag,
+            "W/\"0123456789abcdef\"",
+            "*",
+            "\"other\", \"0123456789abcdef\"",
+        ] {
+            let headers = headers(&[(header::IF_NONE_MATCH, if_none_match)]);
+            assert!(is_cached(&headers, etag), "{if_none_match}");
+            assert!(is_not_modified(&headers, etag, None), "{if_none_match}");
+        }
+
+        let headers = headers(&[(header::IF_NONE_MATCH, "\"other\"")]);
+        assert!(!is_cached(&headers, etag));
+        assert!(!is_cached(&HeaderMap::new(), etag));
+    }
+
+    #[test]
+    fn compares_modification_dates() {
+        let etag = "\"0123456789abcdef\"";
+        let modified = date("Tue, 15 Nov 1994 08:12:31 GMT");
+        let since = |date| headers(&[(header::IF_MODIFIED_SINCE, date)]);
+
+        assert!(is_not_modified(
+            &since("Tue, 15 Nov 1994 08:12:31 GMT"),
+            etag,
+            Some(modified)
+        ));
+        assert!(!is_not_modified(
+            &since("Tue, 15 Nov 1994 08:12:30 GMT"),
+            etag,
+            Some(modified)
+        ));
+        assert!(!is_not_modified(
+            &since("Tue, 15 Nov 1994 08:12:31 GMT"),
+            etag,
+            None
+        ));
+        assert!(!is_not_modified(&since("yesterday"), etag, Some(modified)));
+
+        // If-Modified-Since is ignored when an entity tag was sent
+        let headers = headers(&[
+            (header::IF_NONE_MATCH, "\"other\""),
+            (header::IF_MODIFIED_SINCE, "Tue, 15 Nov 1994 08:12:31 GMT"),
+        ]);
+        assert!(!is_not_modified(&headers, etag, Some(modified)));
+    }
+
+    #[test]
+    fn versions_keys() {
+        let key = "images/1/samples/1-2.png";
+        assert_eq!(version(key).len(), 16);
+        assert_eq!(version(key), version(key));
+        assert_ne!(version(key), version("images/1/samples/1-3.png"));
+        assert_eq!(etag(key), format!("\"{}\"", version(key)));
+    }
+}
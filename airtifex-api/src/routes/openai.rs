@@ -0,0 +1,430 @@
+use crate::{
+    auth::Claims,
+    gen::llm::{conversation_prompt_with_system, ChatData, InferenceRequest},
+    id::Uuid,
+    models::chat_entry::ChatEntry,
+    permissions::Acl,
+    quota::{self, Generation, QuotaError},
+    routes::INFERENCE_ID_HEADER,
+    SharedAppState,
+};
+use airtifex_core::{
+    llm::{DoneEvent, InferenceEvent, InferenceSettings},
+    openai::{
+        ChatCompletionChoice, ChatCompletionChunk, ChatCompletionChunkChoice,
+        ChatCompletionRequest, ChatCompletionResponse, ChatMessage, ChatMessageDelta,
+        CompletionChoice, CompletionPrompt, CompletionRequest, CompletionResponse, ErrorResponse,
+        FinishReason, ModelList, ModelObject, Role,
+    },
+    user::AuthenticatedUser,
+};
+
+use axum::{
+    extract::{Json, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing, Router,
+};
+use serde::Serialize;
+use std::sync::Arc;
+
+pub fn router() -> Router<SharedAppState> {
+    let base = Router::new()
+        .route("/models", routing::get(list_models))
+        .route("/chat/completions", routing::post(chat_completions))
+        .route("/completions", routing::post(completions));
+
+    Router::new().nest("/v1", base)
+}
+
+async fn list_models(claims: Claims, State(state): State<SharedAppState>) -> Response {
+    let db = &state.db;
+    with_guard!(claims, db, Acl::builder().all().build());
+
+    let created = chrono::Utc::now().timestamp();
+    let data = state
+        .llms
+        .names()
+        .into_iter()
+        .map(|name| ModelObject {
+            id: name,
+            object: "model".into(),
+            created,
+            owned_by: "airtifex".into(),
+        })
+        .collect::<Vec<_>>();
+
+    Json(ModelList {
+        object: "list".into(),
+        data,
+    })
+    .into_response()
+}
+
+async fn chat_completions(
+    claims: Claims,
+    State(state): State<SharedAppState>,
+    Json(request): Json<ChatCompletionRequest>,
+) -> Response {
+    let db = &state.db;
+    let user = with_guard!(claims, db, Acl::builder().all().build());
+
+    let mut messages = request.messages;
+    let prompt = match messages.pop() {
+        Some(ChatMessage {
+            role: Role::User,
+            content,
+        }) => content,
+        _ => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                "the last message must have the `user` role",
+            )
+        }
+    };
+
+    let conversation_id = Uuid::new_v4();
+    let mut system = vec![];
+    let mut history = vec![];
+    for message in messages {
+        let parent = history.last().map(|entry: &ChatEntry| entry.entry_id);
+        match message.role {
+            Role::System => system.push(message.content),
+            Role::User => history.push(ChatEntry::new_user(
+                conversation_id,
+                parent,
+                message.content,
+            )),
+            Role::Assistant => {
+                history.push(ChatEntry::new_bot(conversation_id, parent, message.content))
+            }
+        }
+    }
+    let system_prompt = if system.is_empty() {
+        None
+    } else {
+        Some(conversation_prompt_with_system(&system.join("\n")))
+    };
+
+    let (tx_tokens, rx_tokens) = flume::unbounded();
+    let (inference_id, cancellation) = state
+        .inference_registry
+        .register(&claims.sub, &request.model);
+    let inference_request = InferenceRequest {
+        id: inference_id,
+        cancellation,
+        tx_tokens,
+        user: claims.sub,
+        priority: state.config().priorities.of(user.account_type),
+        save: false,
+        chat_data: Some(ChatData {
+            conversation_id,
+            history,
+            prompt_entry_id: None,
+            active_entry_id: None,
+        }),
+        prompt,
+        settings: InferenceSettings {
+            num_predict: request.max_tokens,
+            system_prompt,
+            n_batch: None,
+            top_k: None,
+            top_p: request.top_p,
+            repeat_penalty: None,
+            temp: request.temperature,
+        },
+        play_back_tokens: false,
+    };
+    log::info!("{inference_request:?}");
+
+    if let Err(response) = send_request(&state, &user, &request.model, inference_request).await {
+        return response;
+    }
+
+    let id = format!("chatcmpl-{inference_id}");
+    let created = chrono::Utc::now().timestamp();
+    let model = request.model;
+
+    if request.stream {
+        return completion_stream(
+            inference_id,
+            rx_tokens,
+            move |index, content, finish_reason| ChatCompletionChunk {
+                id: id.clone(),
+                object: "chat.completion.chunk".into(),
+                created,
+                model: model.clone(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    delta: ChatMessageDelta {
+                        role: (index == 0 && content.is_some()).then_some(Role::Assistant),
+                        content,
+                    },
+                    finish_reason,
+                }],
+            },
+        );
+    }
+
+    let (content, done) = match collect_tokens(rx_tokens).await {
+        Ok(output) => output,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "server_error", e),
+    };
+
+    Json(ChatCompletionResponse {
+        id,
+        object: "chat.completion".into(),
+        created,
+        model,
+        choices: vec![ChatCompletionChoice {
+            index: 0,
+            message: ChatMessage {
+                role: Role::Assistant,
+                content,
+            },
+            finish_reason: Some(done.finish_reason.into()),
+        }],
+        usage: done.usage.into(),
+    })
+    .into_response()
+}
+
+async fn completions(
+    claims: Claims,
+    State(state): State<SharedAppState>,
+    Json(request): Json<CompletionRequest>,
+) -> Response {
+    let db = &state.db;
+    let user = with_guard!(claims, db, Acl::builder().all().build());
+
+    let prompt = match request.prompt {
+        CompletionPrompt::Single(prompt) => prompt,
+        CompletionPrompt::Multiple(mut prompts) if prompts.len() == 1 => prompts.remove(0),
+        CompletionPrompt::Multiple(_) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                "only a single prompt per request is supported",
+            )
+        }
+    };
+
+    let (tx_tokens, rx_tokens) = flume::unbounded();
+    let (inference_id, cancellation) = state
+        .inference_registry
+        .register(&claims.sub, &request.model);
+    let inference_request = InferenceRequest {
+        id: inference_id,
+        cancellation,
+        tx_tokens,
+        user: claims.sub,
+        priority: state.config().priorities.of(user.account_type),
+        save: false,
+        chat_data: None,
+        prompt,
+        settings: InferenceSettings {
+            num_predict: request.max_tokens,
+            system_prompt: None,
+            n_batch: None,
+            top_k: None,
+            top_p: request.top_p,
+            repeat_penalty: None,
+            temp: request.temperature,
+        },
+        play_back_tokens: false,
+    };
+    log::info!("{inference_request:?}");
+
+    if let Err(response) = send_request(&state, &user, &request.model, inference_request).await {
+        return response;
+    }
+
+    let id = format!("cmpl-{inference_id}");
+    let created = chrono::Utc::now().timestamp();
+    let model = request.model;
+
+    if request.stream {
+        return completion_stream(inference_id, rx_tokens, move |_, text, finish_reason| {
+            CompletionResponse {
+                id: id.clone(),
+                object: "text_completion".into(),
+                created,
+                model: model.clone(),
+                choices: vec![CompletionChoice {
+                    index: 0,
+                    text: text.unwrap_or_default(),
+                    logprobs: None,
+                    finish_reason,
+                }],
+                usage: None,
+            }
+        });
+    }
+
+    let (text, done) = match collect_tokens(rx_tokens).await {
+        Ok(output) => output,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "server_error", e),
+    };
+
+    Json(CompletionResponse {
+        id,
+        object: "text_completion".into(),
+        created,
+        model,
+        choices: vec![CompletionChoice {
+            index: 0,
+            text,
+            logprobs: None,
+            finish_reason: Some(done.finish_reason.into()),
+        }],
+        usage: Some(done.usage.into()),
+    })
+    .into_response()
+}
+
+async fn enforce_quota(state: &SharedAppState, user: &AuthenticatedUser) -> Result<(), Response> {
+    let quota = state.config().quotas.of(&user.username, user.account_type);
+    quota::acquire(&state.db, &quota, &user.username, Generation::Text)
+        .await
+        .map_err(|e| match e {
+            QuotaError::Database(_) => {
+                error_response(StatusCode::INTERNAL_SERVER_ERROR, "server_error", e)
+            }
+            e => error_response(StatusCode::TOO_MANY_REQUESTS, "rate_limit_exceeded", e),
+        })
+}
+
+/// Charges the quota of the user and queues the request on the model.
+async fn send_request(
+    state: &SharedAppState,
+    user: &AuthenticatedUser,
+    model: &str,
+    request: InferenceRequest,
+) -> Result<(), Response> {
+    let Some(llm) = state.llms.get(model) else {
+        state.inference_registry.remove(&request.id);
+        return Err(error_response(
+            StatusCode::NOT_FOUND,
+            "invalid_request_error",
+            format!("model `{model}` does not exist"),
+        ));
+    };
+    let inference_id = request.id;
+    if let Err(response) = enforce_quota(state, user).await {
+        state.inference_registry.remove(&inference_id);
+        return Err(response);
+    }
+    if let Err(e) = llm.submit(request) {
+        state.inference_registry.remove(&inference_id);
+        if let Err(e) = quota::release(&state.db, &user.username, Generation::Text).await {
+            log::error!(
+                "failed to release the quota of user {} - {e}",
+                user.username
+            );
+        }
+        return Err(error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "server_error",
+            e,
+        ));
+    }
+    Ok(())
+}
+
+async fn collect_tokens(
+    rx_tokens: flume::Receiver<InferenceEvent>,
+) -> Result<(String, DoneEvent), String> {
+    let mut output = String::new();
+    while let Ok(event) = rx_tokens.recv_async().await {
+        match event {
+            InferenceEvent::Queued(_) => {}
+            InferenceEvent::Token(token) => output.push_str(&token.token),
+            InferenceEvent::Error(error) => return Err(error.message),
+            InferenceEvent::Done(done) => return Ok((output, done)),
+        }
+    }
+    Err("inference stream ended unexpectedly".into())
+}
+
+enum CompletionStreamState {
+    Tokens(flume::Receiver<InferenceEvent>),
+    Finished,
+}
+
+/// Streams the tokens as `data:` events in the OpenAI chunk format followed by a final chunk
+/// carrying the finish reason and the `[DONE]` sentinel.
+fn completion_stream<C, F>(
+    inference_id: Uuid,
+    rx_tokens: flume::Receiver<InferenceEvent>,
+    chunk: F,
+) -> Response
+where
+    C: Serialize,
+    F: Fn(usize, Option<String>, Option<FinishReason>) -> C + Send + Sync + 'static,
+{
+    let chunk = Arc::new(chunk);
+    let stream = futures_util::stream::unfold(
+        Some(CompletionStreamState::Tokens(rx_tokens)),
+        move |state| {
+            let chunk = chunk.clone();
+            async move {
+                match state? {
+                    CompletionStreamState::Tokens(rx_tokens) => {
+                        match rx_tokens.recv_async().await {
+                            // queue updates have no OpenAI equivalent, send them as comments
+                            Ok(InferenceEvent::Queued(_)) => Some((
+                                Ok(Event::default().comment("queued")),
+                                Some(CompletionStreamState::Tokens(rx_tokens)),
+                            )),
+                            Ok(InferenceEvent::Token(token)) => Some((
+                                Event::default().json_data(chunk(
+                                    token.index,
+                                    Some(token.token),
+                                    None,
+                                )),
+                                Some(CompletionStreamState::Tokens(rx_tokens)),
+                            )),
+                            Ok(InferenceEvent::Error(error)) => Some((
+                                Event::default()
+                                    .json_data(ErrorResponse::new("server_error", error.message)),
+                                Some(CompletionStreamState::Finished),
+                            )),
+                            Ok(InferenceEvent::Done(done)) => Some((
+                                Event::default().json_data(chunk(
+                                    done.usage.generated_tokens,
+                                    None,
+                                    Some(done.finish_reason.into()),
+                                )),
+                                Some(CompletionStreamState::Finished),
+                            )),
+                            Err(_) => Some((
+                                Event::default().json_data(ErrorResponse::new(
+                                    "server_error",
+                                    "inference stream ended unexpectedly",
+                                )),
+                                Some(CompletionStreamState::Finished),
+                            )),
+                        }
+                    }
+                    CompletionStreamState::Finished => {
+                        Some((Ok(Event::default().data("[DONE]")), None))
+                    }
+                }
+            }
+        },
+    );
+
+    (
+        [(INFERENCE_ID_HEADER, inference_id.to_string())],
+        Sse::new(stream).keep_alive(KeepAlive::default()),
+    )
+        .into_response()
+}
+
+fn error_response(code: StatusCode, type_: &str, message: impl std::fmt::Display) -> Response {
+    (code, Json(ErrorResponse::new(type_, message))).into_response()
+}
@@ -0,0 +1,352 @@
+//! Storage of generated images and uploaded inputs. The database only keeps the keys of the
+//! stored objects.
+
+use crate::{
+    config::StorageConfig,
+    id::Uuid,
+    models::{image::Image, image_sample::ImageSample},
+    DbPool,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use s3::{bucket::Bucket, creds::Credentials, error::S3Error, region::Region};
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+use thiserror::Error as ErrorType;
+
+pub type SharedStorage = Arc<dyn Storage>;
+
+#[derive(Debug, ErrorType)]
+pub enum StorageError {
+    #[error("object `{0}` does not exist")]
+    NotFound(String),
+    #[error("invalid object key `{0}`")]
+    InvalidKey(String),
+    #[error("failed to access object `{0}` - {1}")]
+    Io(String, std::io::Error),
+    #[error("failed to access object `{0}` - {1}")]
+    S3(String, S3Error),
+    #[error("failed to access object `{0}` - object store responded with status {1}")]
+    S3Status(String, u16),
+    #[error("failed to initialize S3 storage - {0}")]
+    S3Init(S3Error),
+}
+
+/// An object read from the storage.
+pub struct StoredObject {
+    pub data: Vec<u8>,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+/// Backend storing objects by key.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Stores the object replacing any previous object with the same key.
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), StorageError>;
+
+    async fn get(&self, key: &str) -> Result<StoredObject, StorageError>;
+
+    /// Deletes the object, deleting an object that doesn't exist is not an error.
+    async fn delete(&self, key: &str) -> Result<(), StorageError>;
+}
+
+pub fn initialize(config: &StorageConfig) -> Result<SharedStorage, StorageError> {
+    Ok(match config {
+        StorageConfig::Local { path } => Arc::new(LocalStorage::new(path.clone())),
+        StorageConfig::S3 {
+            bucket,
+            region,
+            endpoint,
+            access_key,
+            secret_key,
+            path_style,
+        } => Arc::new(S3Storage::new(
+            bucket,
+            region,
+            endpoint.as_deref(),
+            access_key.as_deref(),
+            secret_key.as_deref(),
+            *path_style,
+        )?),
+    })
+}
+
+/// Returns a new key for a generated sample. Objects are never overwritten, the samples and the
+/// thumbnail of a retried image are stored under new keys so that URLs of the replaced ones can be
+/// cached forever.
+pub fn sample_key(image_id: &Uuid, n: i32) -> String {
+    format!("images/{image_id}/samples/{n}-{}.png", Uuid::new_v4())
+}
+
+/// Returns a new key for the thumbnail of an image, see [`sample_key`].
+pub fn thumbnail_key(image_id: &Uuid) -> String {
+    format!("images/{image_id}/thumbnail-{}.png", Uuid::new_v4())
+}
+
+pub fn input_image_key(image_id: &Uuid) -> String {
+    format!("images/{image_id}/input")
+}
+
+pub fn mask_key(image_id: &Uuid) -> String {
+    format!("images/{image_id}/mask")
+}
+
+/// Stores objects as files in a directory.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    /// Returns the path of the object making sure it stays inside of the root directory.
+    fn path(&self, key: &str) -> Result<PathBuf, StorageError> {
+        let relative = Path::new(key);
+        if key.is_empty()
+            || !relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(StorageError::InvalidKey(key.to_string()));
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), StorageError> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| StorageError::Io(key.to_string(), e))?;
+        }
+        // write to a temporary file first so that readers never see a partial object
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, data)
+            .await
+            .map_err(|e| StorageError::Io(key.to_string(), e))?;
+        tokio::fs::rename(&tmp, &path)
+            .await
+            .map_err(|e| StorageError::Io(key.to_string(), e))
+    }
+
+    async fn get(&self, key: &str) -> Result<StoredObject, StorageError> {
+        let path = self.path(key)?;
+        let data = match tokio::fs::read(&path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(StorageError::NotFound(key.to_string()))
+            }
+            Err(e) => return Err(StorageError::Io(key.to_string(), e)),
+        };
+        let last_modified = tokio::fs::metadata(&path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .map(DateTime::<Utc>::from);
+        Ok(StoredObject {
+            data,
+            last_modified,
+        })
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(StorageError::Io(key.to_string(), e))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Stores objects in a bucket of Amazon S3 or any S3 compatible object store like MinIO.
+pub struct S3Storage {
+    bucket: Bucket,
+}
+
+impl S3Storage {
+    pub fn new(
+        bucket: &str,
+        region: &str,
+        endpoint: Option<&str>,
+        access_key: Option<&str>,
+        secret_key: Option<&str>,
+        path_style: bool,
+    ) -> Result<Self, StorageError> {
+        let region = Region::Custom {
+            region: region.to_string(),
+            endpoint: endpoint
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("https://s3.{region}.amazonaws.com")),
+        };
+        // without explicit keys the credentials are read from the environment or the AWS profile
+        let credentials = Credentials::new(access_key, secret_key, None, None, None)
+            .map_err(|e| StorageError::S3Init(e.into()))?;
+        let mut bucket = Bucket::new(bucket, region, credentials).map_err(StorageError::S3Init)?;
+        if path_style {
+            bucket = bucket.with_path_style();
+        }
+        Ok(Self { bucket })
+    }
+
+    fn check_status(key: &str, status: u16) -> Result<(), StorageError> {
+        match status {
+            200..=299 => Ok(()),
+            404 => Err(StorageError::NotFound(key.to_string())),
+            status => Err(StorageError::S3Status(key.to_string(), status)),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), StorageError> {
+        let response = self
+            .bucket
+            .put_object(key, &data)
+            .await
+            .map_err(|e| StorageError::S3(key.to_string(), e))?;
+        Self::check_status(key, response.status_code())
+    }
+
+    async fn get(&self, key: &str) -> Result<StoredObject, StorageError> {
+        let response = self
+            .bucket
+            .get_object(key)
+            .await
+            .map_err(|e| StorageError::S3(key.to_string(), e))?;
+        Self::check_status(key, response.status_code())?;
+        let last_modified = response
+            .headers()
+            .get("last-modified")
+            .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+            .map(|date| date.with_timezone(&Utc));
+        Ok(StoredObject {
+            data: response.bytes().to_vec(),
+            last_modified,
+        })
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        let response = self
+            .bucket
+            .delete_object(key)
+            .await
+            .map_err(|e| StorageError::S3(key.to_string(), e))?;
+        match Self::check_status(key, response.status_code()) {
+            Err(StorageError::NotFound(_)) => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// Number of rows moved at once, every row can hold multiple megabytes of data
+const MOVE_BATCH_SIZE: i64 = 16;
+
+/// Moves images stored in the database by earlier versions to the storage.
+pub async fn move_blobs(db: &DbPool, storage: &dyn Storage) -> crate::Result<()> {
+    let mut moved = 0;
+    loop {
+        let samples = ImageSample::list_blobs(db, MOVE_BATCH_SIZE).await?;
+        if samples.is_empty() {
+            break;
+        }
+        for sample in samples {
+            let key = sample_key(&sample.image_id, sample.n);
+            storage.put(&key, sample.data).await?;
+            ImageSample::update_data_key(db, &sample.sample_id, &key).await?;
+            moved += 1;
+        }
+    }
+
+    loop {
+        let images = Image::list_blobs(db, MOVE_BATCH_SIZE).await?;
+        if images.is_empty() {
+            break;
+        }
+        for image in images {
+            let mut keys = [None, None, None];
+            for (key, (data, object_key)) in keys.iter_mut().zip([
+                (image.input_image, input_image_key(&image.id)),
+                (image.mask, mask_key(&image.id)),
+                (image.thumbnail, thumbnail_key(&image.id)),
+            ]) {
+                if let Some(data) = data {
+                    storage.put(&object_key, data).await?;
+                    *key = Some(object_key);
+                }
+            }
+            let [input_image_key, mask_key, thumbnail_key] = keys;
+            Image::update_blob_keys(db, &image.id, input_image_key, mask_key, thumbnail_key)
+                .await?;
+            moved += 1;
+        }
+    }
+
+    if moved > 0 {
+        log::info!("moved images of {moved} rows from the database to the storage");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage() -> LocalStorage {
+        LocalStorage::new(PathBuf::from("/var/lib/airtifex"))
+    }
+
+    #[test]
+    fn resolves_keys_inside_of_the_root() {
+        let storage = storage();
+        assert_eq!(
+            storage.path("images/1/samples/1.png").unwrap(),
+            Path::new("/var/lib/airtifex/images/1/samples/1.png")
+        );
+        let image_id = Uuid::new_v4();
+        for key in [
+            sample_key(&image_id, 1),
+            thumbnail_key(&image_id),
+            input_image_key(&image_id),
+            mask_key(&image_id),
+        ] {
+            let path = storage.path(&key).unwrap();
+            assert!(path.starts_with("/var/lib/airtifex/images"), "{key}");
+        }
+    }
+
+    #[test]
+    fn rejects_keys_escaping_the_root() {
+        let storage = storage();
+        for key in [
+            "",
+            "..",
+            "../secret",
+            "images/../../secret",
+            "images/..",
+            "./images",
+            "/etc/passwd",
+        ] {
+            assert!(
+                matches!(storage.path(key), Err(StorageError::InvalidKey(_))),
+                "{key}"
+            );
+        }
+    }
+
+    #[test]
+    fn generates_new_keys_for_generated_images() {
+        let image_id = Uuid::new_v4();
+        assert_ne!(sample_key(&image_id, 1), sample_key(&image_id, 1));
+        assert_ne!(thumbnail_key(&image_id), thumbnail_key(&image_id));
+    }
+}
@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+/// Models started or stopped after reloading the configuration.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ModelChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Models with a changed configuration that were restarted
+    pub replaced: Vec<String>,
+}
+
+impl ModelChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.replaced.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ConfigReloadResponse {
+    pub llms: ModelChanges,
+    pub image_models: ModelChanges,
+}
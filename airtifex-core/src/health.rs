@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelLoadState {
+    /// The model is loaded on its next request
+    Unloaded,
+    Loading,
+    Ready,
+    Failed,
+}
+
+impl ModelLoadState {
+    /// Whether the model can process requests, unloaded models are loaded on demand.
+    pub fn is_available(&self) -> bool {
+        matches!(self, ModelLoadState::Unloaded | ModelLoadState::Ready)
+    }
+}
+
+/// Memory residency of a model worker.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ModelResidency {
+    pub state: ModelLoadState,
+    /// Estimated memory used by the weights when loaded
+    pub size_bytes: u64,
+    /// Seconds since the model last processed a request
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_seconds: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ModelHealth {
+    pub name: String,
+    pub state: ModelLoadState,
+    /// Reason of the failure if the model failed to load
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DatabaseHealth {
+    pub connected: bool,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HealthReport {
+    /// Whether the database is reachable and all models are loaded
+    pub ready: bool,
+    pub database: DatabaseHealth,
+    pub llms: Vec<ModelHealth>,
+    pub image_models: Vec<ModelHealth>,
+}
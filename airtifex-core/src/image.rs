@@ -1,9 +1,12 @@
+use crate::query::{append_optional_pair, SortOrder, UrlQuery};
 use debug_stub_derive::DebugStub;
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Default, Deserialize, Serialize, Debug)]
 pub struct ImageGenerateRequest {
     pub prompt: String,
+    /// Describes what the image should not contain
+    pub negative_prompt: Option<String>,
     pub model: String,
     pub input_image: Option<InputImage>,
     pub width: Option<i64>,
@@ -12,6 +15,181 @@ pub struct ImageGenerateRequest {
     pub seed: Option<i64>,
     pub num_samples: Option<i64>,
     pub guidance_scale: Option<f64>,
+    /// Defaults to the scheduler configured for the model
+    pub scheduler: Option<ImageScheduler>,
+}
+
+/// Diffusion scheduler used to denoise the latents.
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageScheduler {
+    #[default]
+    Ddim,
+    Euler,
+    EulerAncestral,
+    /// DPM-Solver++
+    DpmSolverPp,
+    Pndm,
+}
+
+impl ImageScheduler {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            ImageScheduler::Ddim => "ddim",
+            ImageScheduler::Euler => "euler",
+            ImageScheduler::EulerAncestral => "euler_ancestral",
+            ImageScheduler::DpmSolverPp => "dpm_solver_pp",
+            ImageScheduler::Pndm => "pndm",
+        }
+    }
+    pub fn parse_str(s: impl AsRef<str>) -> Option<Self> {
+        match s.as_ref() {
+            "ddim" => Some(ImageScheduler::Ddim),
+            "euler" => Some(ImageScheduler::Euler),
+            "euler_ancestral" => Some(ImageScheduler::EulerAncestral),
+            "dpm_solver_pp" => Some(ImageScheduler::DpmSolverPp),
+            "pndm" => Some(ImageScheduler::Pndm),
+            _ => None,
+        }
+    }
+}
+
+/// State of the generation of an image.
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageStatus {
+    #[default]
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl ImageStatus {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            ImageStatus::Queued => "queued",
+            ImageStatus::Running => "running",
+            ImageStatus::Completed => "completed",
+            ImageStatus::Failed => "failed",
+            ImageStatus::Cancelled => "cancelled",
+        }
+    }
+    pub fn parse_str(s: impl AsRef<str>) -> Option<Self> {
+        match s.as_ref() {
+            "queued" => Some(ImageStatus::Queued),
+            "running" => Some(ImageStatus::Running),
+            "completed" => Some(ImageStatus::Completed),
+            "failed" => Some(ImageStatus::Failed),
+            "cancelled" => Some(ImageStatus::Cancelled),
+            _ => None,
+        }
+    }
+
+    /// Whether the image is waiting for or being generated.
+    pub fn is_processing(self) -> bool {
+        matches!(self, ImageStatus::Queued | ImageStatus::Running)
+    }
+}
+
+/// Who can view an image besides its owner and admins.
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageVisibility {
+    /// Only the owner
+    #[default]
+    Private,
+    /// Every signed in user that knows the ID of the image
+    Shared,
+    /// Everyone including anonymous clients
+    Public,
+}
+
+impl ImageVisibility {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            ImageVisibility::Private => "private",
+            ImageVisibility::Shared => "shared",
+            ImageVisibility::Public => "public",
+        }
+    }
+    pub fn parse_str(s: impl AsRef<str>) -> Option<Self> {
+        match s.as_ref() {
+            "private" => Some(ImageVisibility::Private),
+            "shared" => Some(ImageVisibility::Shared),
+            "public" => Some(ImageVisibility::Public),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ImageVisibilityRequest {
+    pub visibility: ImageVisibility,
+}
+
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
+pub enum ImageListOrder {
+    #[default]
+    #[serde(rename = "create_date")]
+    CreateDate,
+    #[serde(rename = "model")]
+    Model,
+}
+
+impl AsRef<str> for ImageListOrder {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::CreateDate => "create_date",
+            Self::Model => "model",
+        }
+    }
+}
+
+/// Page and filters of the images of a user.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ImageListQuery {
+    pub page: Option<u32>,
+    pub page_size: Option<u32>,
+    pub order_by: Option<ImageListOrder>,
+    pub order: Option<SortOrder>,
+    pub model: Option<String>,
+    pub status: Option<ImageStatus>,
+    /// First day on which the image was requested
+    pub from: Option<chrono::NaiveDate>,
+    /// Last day on which the image was requested
+    pub to: Option<chrono::NaiveDate>,
+    /// Full-text search over the prompt and the negative prompt
+    pub search: Option<String>,
+}
+
+impl UrlQuery for ImageListQuery {
+    fn as_query(&self) -> String {
+        let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+        append_optional_pair(&mut serializer, "page", self.page);
+        append_optional_pair(&mut serializer, "page_size", self.page_size);
+        append_optional_pair(
+            &mut serializer,
+            "order_by",
+            self.order_by.as_ref().map(AsRef::<str>::as_ref),
+        );
+        append_optional_pair(
+            &mut serializer,
+            "order",
+            self.order.as_ref().map(AsRef::<str>::as_ref),
+        );
+        append_optional_pair(&mut serializer, "model", self.model.as_deref());
+        append_optional_pair(
+            &mut serializer,
+            "status",
+            self.status.map(ImageStatus::to_str),
+        );
+        append_optional_pair(&mut serializer, "from", self.from);
+        append_optional_pair(&mut serializer, "to", self.to);
+        append_optional_pair(&mut serializer, "search", self.search.as_deref());
+        serializer.finish()
+    }
 }
 
 #[derive(Clone, Default, Deserialize, Serialize, DebugStub)]
@@ -26,6 +204,11 @@ pub struct InputImage {
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct TextToImageResponse {
     pub image_id: String,
+    #[serde(default)]
+    pub queue: Option<crate::queue::QueuePosition>,
+    /// Parts of the request that were adjusted, e.g. prompts that are too long
+    #[serde(default)]
+    pub warnings: Vec<String>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -36,23 +219,55 @@ pub struct ImageInspect {
     pub width: i64,
     pub height: i64,
     pub prompt: String,
-    pub input_image: Option<Vec<u8>>,
-    pub mask: Option<Vec<u8>>,
-    pub thumbnail: Option<Vec<u8>>,
+    #[serde(default)]
+    pub negative_prompt: Option<String>,
+    /// URLs of the uploaded input image and mask and of the thumbnail
+    pub input_image_url: Option<String>,
+    pub mask_url: Option<String>,
+    pub thumbnail_url: Option<String>,
     pub n_steps: i64,
     pub seed: i64,
     pub num_samples: i64,
     pub guidance_scale: f64,
+    #[serde(default)]
+    pub scheduler: ImageScheduler,
     pub processing: bool,
+    #[serde(default)]
+    pub status: ImageStatus,
+    /// Reason the generation failed
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub visibility: ImageVisibility,
     pub create_date: chrono::DateTime<chrono::Utc>,
 }
 
+/// Progress of an image generation job sent by the progress stream.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ImageProgress {
+    pub image_id: String,
+    /// Whether the job is still waiting in the queue
+    pub queued: bool,
+    /// Sample currently being generated, starting from 1
+    pub sample: i64,
+    pub num_samples: i64,
+    /// Denoising steps finished for the current sample
+    pub timestep: usize,
+    pub n_steps: usize,
+    pub finished: bool,
+    /// Low resolution PNG approximated from the latents as a `data:` URL that can be used as
+    /// the source of an image
+    #[serde(default)]
+    pub preview: Option<String>,
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct ImageSampleInspect {
     pub sample_id: String,
     pub image_id: String,
     pub n_sample: i32,
-    pub data: Vec<u8>,
+    /// URL of the PNG of the sample
+    pub url: String,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -61,6 +276,10 @@ pub struct ImageModelListEntry {
     pub name: String,
     pub description: Option<String>,
     pub features: ImageModelFeatures,
+    /// Memory residency of the model, not set if the model isn't running
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub residency: Option<crate::health::ModelResidency>,
 }
 
 fn on() -> bool {
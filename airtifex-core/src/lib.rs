@@ -1,10 +1,15 @@
 use serde::{Deserialize, Serialize};
 
+pub mod admin;
 pub mod api_response;
 pub mod auth;
+pub mod health;
 pub mod image;
 pub mod llm;
+pub mod openai;
 pub mod query;
+pub mod queue;
+pub mod usage;
 pub mod user;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
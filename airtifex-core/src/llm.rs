@@ -1,3 +1,4 @@
+use crate::query::{append_optional_pair, SortOrder, UrlQuery};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Default, Deserialize, Serialize)]
@@ -61,6 +62,116 @@ pub struct ChatEntryListEntry {
     pub chat_id: String,
     pub entry_type: ChatEntryType,
     pub content: String,
+    /// The entry this one follows, not set for the first entry of a chat
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    /// IDs of the alternatives of this entry following the same parent, including this entry,
+    /// oldest first
+    #[serde(default)]
+    pub siblings: Vec<String>,
+}
+
+/// Format of an exported chat.
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ChatExportFormat {
+    #[default]
+    #[serde(rename = "json")]
+    Json,
+    #[serde(rename = "markdown")]
+    Markdown,
+}
+
+impl AsRef<str> for ChatExportFormat {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::Json => "json",
+            Self::Markdown => "markdown",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ChatExportQuery {
+    pub format: Option<ChatExportFormat>,
+}
+
+impl UrlQuery for ChatExportQuery {
+    fn as_query(&self) -> String {
+        let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+        append_optional_pair(
+            &mut serializer,
+            "format",
+            self.format.as_ref().map(AsRef::<str>::as_ref),
+        );
+        serializer.finish()
+    }
+}
+
+/// A chat with its settings and history as exported in JSON. Importing it recreates the chat.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChatExport {
+    pub title: String,
+    pub model: String,
+    pub start_date: chrono::DateTime<chrono::Utc>,
+    pub settings: InferenceSettings,
+    /// Entries of all branches, every entry follows its parent
+    pub entries: Vec<ChatExportEntry>,
+    /// Last entry of the active branch
+    #[serde(default)]
+    pub active_id: Option<String>,
+}
+
+impl ChatExport {
+    /// Returns the entries of the active branch, oldest first. Exports without entry IDs only
+    /// contain a single branch.
+    pub fn active_branch(&self) -> Vec<&ChatExportEntry> {
+        if self.entries.iter().all(|entry| entry.id.is_none()) {
+            return self.entries.iter().collect();
+        }
+        let mut branch = vec![];
+        let mut next = self.active_id.as_ref();
+        while let Some(entry) = next.and_then(|id| {
+            self.entries
+                .iter()
+                .find(|entry| entry.id.as_ref() == Some(id))
+        }) {
+            // guards against cycles in hand edited exports
+            if branch.len() == self.entries.len() {
+                break;
+            }
+            branch.push(entry);
+            next = entry.parent_id.as_ref();
+        }
+        branch.reverse();
+        branch
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChatExportEntry {
+    /// ID of the entry in the exported chat, missing in exports of older versions
+    #[serde(default)]
+    pub id: Option<String>,
+    /// ID of the entry this one follows, `None` for the first entry of the chat
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    pub entry_type: ChatEntryType,
+    pub content: String,
+    pub entry_date: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ChatImportQuery {
+    /// Model of the imported chat, defaults to the model of the exported chat
+    pub model: Option<String>,
+}
+
+impl UrlQuery for ChatImportQuery {
+    fn as_query(&self) -> String {
+        let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+        append_optional_pair(&mut serializer, "model", self.model.as_deref());
+        serializer.finish()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -76,6 +187,10 @@ pub struct LlmListEntry {
     pub model_id: String,
     pub name: String,
     pub description: Option<String>,
+    /// Memory residency of the model, not set if the model isn't running
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub residency: Option<crate::health::ModelResidency>,
 }
 
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
@@ -113,13 +228,206 @@ fn default_save_inference_request() -> bool {
     false
 }
 
-pub type ChatStreamResult = Result<String, String>;
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FinishReason {
+    /// The model produced an end of text token
+    Stop,
+    /// The maximum number of tokens was generated
+    Length,
+    /// Inference was interrupted by an error
+    Error,
+    /// The request was cancelled by the user
+    Cancelled,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct InferenceUsage {
+    pub prompt_tokens: usize,
+    pub generated_tokens: usize,
+    pub total_tokens: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TokenEvent {
+    pub index: usize,
+    pub token: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ErrorEvent {
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DoneEvent {
+    pub finish_reason: FinishReason,
+    pub usage: InferenceUsage,
+}
+
+pub use crate::queue::QueuePosition;
+
+/// Event emitted on the inference stream, sent to the clients as a Server-Sent Event with
+/// the name returned by [`InferenceEvent::name`] and the inner value as JSON data.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum InferenceEvent {
+    Queued(QueuePosition),
+    Token(TokenEvent),
+    Error(ErrorEvent),
+    Done(DoneEvent),
+}
+
+impl InferenceEvent {
+    pub fn error(message: impl std::fmt::Display) -> Self {
+        Self::Error(ErrorEvent {
+            message: message.to_string(),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Queued(_) => "queued",
+            Self::Token(_) => "token",
+            Self::Error(_) => "error",
+            Self::Done(_) => "done",
+        }
+    }
+
+    /// Parses an event from the name and data fields of a Server-Sent Event. Returns `None` if
+    /// the event name is unknown.
+    pub fn from_sse(event: &str, data: &str) -> Option<Result<Self, serde_json::Error>> {
+        match event {
+            "queued" => Some(serde_json::from_str(data).map(Self::Queued)),
+            "token" => Some(serde_json::from_str(data).map(Self::Token)),
+            "error" => Some(serde_json::from_str(data).map(Self::Error)),
+            "done" => Some(serde_json::from_str(data).map(Self::Done)),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct UserChatCounters {
     pub chat_count: usize,
 }
 
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
+pub enum ChatListOrder {
+    #[default]
+    #[serde(rename = "start_date")]
+    StartDate,
+    #[serde(rename = "title")]
+    Title,
+    #[serde(rename = "model")]
+    Model,
+}
+
+impl AsRef<str> for ChatListOrder {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::StartDate => "start_date",
+            Self::Title => "title",
+            Self::Model => "model",
+        }
+    }
+}
+
+/// Page and filters of the chats of a user.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ChatListQuery {
+    pub page: Option<u32>,
+    pub page_size: Option<u32>,
+    pub order_by: Option<ChatListOrder>,
+    pub order: Option<SortOrder>,
+    pub model: Option<String>,
+    /// First day on which the chat was started
+    pub from: Option<chrono::NaiveDate>,
+    /// Last day on which the chat was started
+    pub to: Option<chrono::NaiveDate>,
+    /// Full-text search over the title and the messages of the chat
+    pub search: Option<String>,
+}
+
+impl UrlQuery for ChatListQuery {
+    fn as_query(&self) -> String {
+        let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+        append_optional_pair(&mut serializer, "page", self.page);
+        append_optional_pair(&mut serializer, "page_size", self.page_size);
+        append_optional_pair(
+            &mut serializer,
+            "order_by",
+            self.order_by.as_ref().map(AsRef::<str>::as_ref),
+        );
+        append_optional_pair(
+            &mut serializer,
+            "order",
+            self.order.as_ref().map(AsRef::<str>::as_ref),
+        );
+        append_optional_pair(&mut serializer, "model", self.model.as_deref());
+        append_optional_pair(&mut serializer, "from", self.from);
+        append_optional_pair(&mut serializer, "to", self.to);
+        append_optional_pair(&mut serializer, "search", self.search.as_deref());
+        serializer.finish()
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
+pub enum PromptListOrder {
+    #[default]
+    #[serde(rename = "date")]
+    Date,
+    #[serde(rename = "model")]
+    Model,
+}
+
+impl AsRef<str> for PromptListOrder {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::Date => "date",
+            Self::Model => "model",
+        }
+    }
+}
+
+/// Page and filters of the one-shot prompts of a user.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PromptListQuery {
+    pub page: Option<u32>,
+    pub page_size: Option<u32>,
+    pub order_by: Option<PromptListOrder>,
+    pub order: Option<SortOrder>,
+    pub model: Option<String>,
+    /// First day on which the prompt was sent
+    pub from: Option<chrono::NaiveDate>,
+    /// Last day on which the prompt was sent
+    pub to: Option<chrono::NaiveDate>,
+    /// Full-text search over the prompt and the response
+    pub search: Option<String>,
+}
+
+impl UrlQuery for PromptListQuery {
+    fn as_query(&self) -> String {
+        let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+        append_optional_pair(&mut serializer, "page", self.page);
+        append_optional_pair(&mut serializer, "page_size", self.page_size);
+        append_optional_pair(
+            &mut serializer,
+            "order_by",
+            self.order_by.as_ref().map(AsRef::<str>::as_ref),
+        );
+        append_optional_pair(
+            &mut serializer,
+            "order",
+            self.order.as_ref().map(AsRef::<str>::as_ref),
+        );
+        append_optional_pair(&mut serializer, "model", self.model.as_deref());
+        append_optional_pair(&mut serializer, "from", self.from);
+        append_optional_pair(&mut serializer, "to", self.to);
+        append_optional_pair(&mut serializer, "search", self.search.as_deref());
+        serializer.finish()
+    }
+}
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct PromptInspect {
     pub id: String,
@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use std::string::ToString;
 use url::form_urlencoded;
 
@@ -5,6 +6,38 @@ pub trait UrlQuery {
     fn as_query(&self) -> String;
 }
 
+/// Direction in which a listing is sorted.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortOrder {
+    #[serde(rename = "asc")]
+    Asc,
+    #[default]
+    #[serde(rename = "desc")]
+    Desc,
+}
+
+impl AsRef<str> for SortOrder {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::Asc => "asc",
+            Self::Desc => "desc",
+        }
+    }
+}
+
+/// Appends the pair to the query if the value is set.
+pub(crate) fn append_optional_pair<V>(
+    serializer: &mut form_urlencoded::Serializer<String>,
+    key: &str,
+    val: Option<V>,
+) where
+    V: ToString,
+{
+    if let Some(val) = val {
+        serializer.append_pair(key, &val.to_string());
+    }
+}
+
 /// Creates an endpoint with a query
 pub fn construct_ep<E, Q>(ep: E, query: Option<Q>) -> String
 where
@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueuePosition {
+    /// Number of jobs waiting in the queue ahead of this one
+    pub position: usize,
+    pub running: usize,
+    pub max_running: usize,
+    /// Estimated number of seconds until the job starts processing
+    pub eta_seconds: Option<f64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueuedJob {
+    pub id: String,
+    #[serde(flatten)]
+    pub position: QueuePosition,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueueStatus {
+    pub model: String,
+    pub queued: usize,
+    pub running: usize,
+    pub max_running: usize,
+    /// Average processing time of recently finished jobs in seconds
+    pub average_job_seconds: Option<f64>,
+    /// Estimated number of seconds until a newly submitted job starts processing
+    pub eta_seconds: Option<f64>,
+    /// Jobs of the requesting user that are still waiting in the queue
+    pub jobs: Vec<QueuedJob>,
+}
@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct UsageQuery {
+    pub username: Option<String>,
+    pub model: Option<String>,
+    /// First day to include in the report
+    pub from: Option<chrono::NaiveDate>,
+    /// Last day to include in the report
+    pub to: Option<chrono::NaiveDate>,
+}
+
+/// Resources used by a single user with a single model during one day (UTC).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UsageReportEntry {
+    pub username: String,
+    pub model: String,
+    pub day: chrono::NaiveDate,
+    pub jobs: u64,
+    pub prompt_tokens: u64,
+    pub generated_tokens: u64,
+    pub n_steps: u64,
+    pub num_samples: u64,
+    /// Total processing time of the jobs in seconds
+    pub wall_time: f64,
+    /// Average number of generated tokens per second of processing time
+    pub tokens_per_second: f64,
+}
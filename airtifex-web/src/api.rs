@@ -1,13 +1,14 @@
 use airtifex_core::{
-    api_response::ApiResponse,
+    api_response::{ApiResponse, ApiVersion},
     auth::Credentials,
     image::{
-        ImageGenerateRequest, ImageInspect, ImageModelListEntry, ImageSampleInspect,
-        TextToImageResponse,
+        ImageGenerateRequest, ImageInspect, ImageListQuery, ImageModelListEntry,
+        ImageSampleInspect, ImageVisibility, ImageVisibilityRequest, TextToImageResponse,
     },
     llm::{
-        ChatEntryListEntry, ChatListEntry, ChatResponseRequest, ChatStartRequest,
-        ChatStartResponse, LlmListEntry, OneshotInferenceRequest, PromptInspect, UserChatCounters,
+        ChatEntryListEntry, ChatExport, ChatExportQuery, ChatImportQuery, ChatListEntry,
+        ChatListQuery, ChatResponseRequest, ChatStartRequest, ChatStartResponse, LlmListEntry,
+        OneshotInferenceRequest, PromptInspect, PromptListQuery, UserChatCounters,
     },
     query::{append_query, UrlQuery},
     user::{
@@ -66,10 +67,26 @@ impl AuthorizedApi {
             .map_err(Error::from)
         // log::info!("got response {response:?}");
     }
+    /// Downloads a file returned as is rather than wrapped in an API response.
+    async fn download(&self, req: Request, what: &str) -> Result<Vec<u8>> {
+        let response = self.send(req).await?;
+        if !response.ok() {
+            return Err(Error::ApiError(format!(
+                "failed to download {what} - {}",
+                response.status_text()
+            )));
+        }
+        Ok(response.binary().await?)
+    }
     pub async fn me(&self) -> Result<AuthenticatedUser> {
         let url = format!("{}/users/me", self.url);
         self.send_json(Request::get(&url)).await
     }
+    /// Downloads a zip archive with all chats, prompts and images of the user.
+    pub async fn user_export(&self) -> Result<Vec<u8>> {
+        let url = format!("{}/users/me/export", self.url);
+        self.download(Request::get(&url), "export").await
+    }
     pub async fn user_info(&self, username: &str) -> Result<GetUserEntry> {
         let url = format!("{}/users/{}", self.url, username);
         self.send_json(Request::get(&url)).await
@@ -106,12 +123,43 @@ impl AuthorizedApi {
         let url = format!("{}/llm/chat/{id}", self.url);
         self.send(Request::post(&url).json(&request)?).await
     }
+    /// Generates a new reply to the last prompt of the chat.
+    pub async fn chat_regenerate(&self, id: &str) -> Result<Response> {
+        let url = format!("{}/llm/chat/{id}/regenerate", self.url);
+        self.send(Request::post(&url)).await
+    }
+    /// Replaces a previous prompt of the chat and generates a reply to it.
+    pub async fn chat_edit_entry(
+        &self,
+        request: ChatResponseRequest,
+        id: &str,
+        entry_id: &str,
+    ) -> Result<Response> {
+        let url = format!("{}/llm/chat/{id}/entries/{entry_id}", self.url);
+        self.send(Request::post(&url).json(&request)?).await
+    }
+    pub async fn chat_fork(&self, id: &str, entry_id: &str) -> Result<Vec<ChatEntryListEntry>> {
+        let url = format!("{}/llm/chat/{id}/entries/{entry_id}/fork", self.url);
+        self.send_json(Request::post(&url)).await
+    }
+    pub async fn chat_select_branch(
+        &self,
+        id: &str,
+        entry_id: &str,
+    ) -> Result<Vec<ChatEntryListEntry>> {
+        let url = format!("{}/llm/chat/{id}/entries/{entry_id}/select", self.url);
+        self.send_json(Request::post(&url)).await
+    }
     pub async fn oneshot_inference(&self, request: OneshotInferenceRequest) -> Result<Response> {
         let url = format!("{}/llm/inference", self.url);
         self.send(Request::post(&url).json(&request)?).await
     }
-    pub async fn prompt_list(&self) -> Result<Vec<PromptInspect>> {
-        let url = format!("{}/llm/prompt", self.url);
+    pub async fn inference_cancel(&self, id: &str) -> Result<()> {
+        let url = format!("{}/llm/inference/{id}", self.url);
+        self.send_json(Request::delete(&url)).await
+    }
+    pub async fn prompt_list(&self, query: PromptListQuery) -> Result<Vec<PromptInspect>> {
+        let url = append_query(format!("{}/llm/prompt", self.url), query.as_query());
         self.send_json(Request::get(&url)).await
     }
     pub async fn prompt_inspect(&self, id: &str) -> Result<PromptInspect> {
@@ -134,16 +182,31 @@ impl AuthorizedApi {
         let url = format!("{}/llm/chat/{id}", self.url);
         self.send_json(Request::delete(&url)).await
     }
-    pub async fn chat_list(&self) -> Result<Vec<ChatListEntry>> {
-        let url = format!("{}/llm/chat", self.url);
+    pub async fn chat_export(&self, id: &str, query: ChatExportQuery) -> Result<Vec<u8>> {
+        let url = append_query(
+            format!("{}/llm/chat/{id}/export", self.url),
+            query.as_query(),
+        );
+        self.download(Request::get(&url), "chat").await
+    }
+    pub async fn chat_import(
+        &self,
+        chat: ChatExport,
+        query: ChatImportQuery,
+    ) -> Result<ChatStartResponse> {
+        let url = append_query(format!("{}/llm/chat/import", self.url), query.as_query());
+        self.send_json(Request::post(&url).json(&chat)?).await
+    }
+    pub async fn chat_list(&self, query: ChatListQuery) -> Result<Vec<ChatListEntry>> {
+        let url = append_query(format!("{}/llm/chat", self.url), query.as_query());
         self.send_json(Request::get(&url)).await
     }
     pub async fn user_chat_counters(&self) -> Result<UserChatCounters> {
         let url = format!("{}/llm/chat/counters", self.url);
         self.send_json(Request::get(&url)).await
     }
-    pub async fn image_list(&self) -> Result<Vec<ImageInspect>> {
-        let url = format!("{}/image", self.url);
+    pub async fn image_list(&self, query: ImageListQuery) -> Result<Vec<ImageInspect>> {
+        let url = append_query(format!("{}/image", self.url), query.as_query());
         self.send_json(Request::get(&url)).await
     }
     pub async fn image_delete(&self, id: &str) -> Result<()> {
@@ -158,6 +221,22 @@ impl AuthorizedApi {
         let url = format!("{}/image/{id}/samples", self.url);
         self.send_json(Request::get(&url)).await
     }
+    pub async fn image_visibility(
+        &self,
+        id: &str,
+        visibility: ImageVisibility,
+    ) -> Result<ImageVisibility> {
+        let url = format!("{}/image/{id}/visibility", self.url);
+        self.send_json(Request::post(&url).json(&ImageVisibilityRequest { visibility })?)
+            .await
+    }
+    /// Downloads an image from one of the URLs included in the image metadata.
+    pub async fn image_data(&self, url: &str) -> Result<Vec<u8>> {
+        // the URLs point to the versioned API that `self.url` is proxied to
+        let path = url.trim_start_matches(&format!("/api/{}", ApiVersion::V1.as_ref()));
+        self.download(Request::get(&format!("{}{path}", self.url)), "image")
+            .await
+    }
     pub async fn image_generate(
         &self,
         request: ImageGenerateRequest,
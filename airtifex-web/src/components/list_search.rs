@@ -0,0 +1,26 @@
+use leptos::*;
+
+/// Full-text search over a listing, a new search starts again at the first page.
+#[component]
+pub fn ListSearch(
+    cx: Scope,
+    search: RwSignal<String>,
+    current_list_page: RwSignal<u32>,
+) -> impl IntoView {
+    view! { cx,
+        <div class="input-group px-5 pt-3">
+            <label class="input-group-text">"Search"</label>
+            <input
+              class="form-control"
+              placeholder="..."
+              prop:value=move || search.get()
+              on:change=move |ev| {
+                  let val = event_target_value(&ev);
+                  current_list_page.set(1);
+                  search.set(val);
+              }
+            />
+        </div>
+    }
+    .into_view(cx)
+}
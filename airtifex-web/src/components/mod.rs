@@ -2,15 +2,18 @@ pub mod credentials;
 pub mod email_validation;
 pub mod go_back_button;
 pub mod list_page_control;
+pub mod list_search;
 pub mod loading;
 pub mod modal;
 pub mod navbar;
 pub mod password_validation;
 pub mod status_message;
+pub mod stored_image;
 pub mod titled_child_page;
 pub mod users;
 
 pub use self::{
-    credentials::*, email_validation::*, go_back_button::*, list_page_control::*, loading::*,
-    modal::*, navbar::*, password_validation::*, status_message::*, titled_child_page::*, users::*,
+    credentials::*, email_validation::*, go_back_button::*, list_page_control::*, list_search::*,
+    loading::*, modal::*, navbar::*, password_validation::*, status_message::*, stored_image::*,
+    titled_child_page::*, users::*,
 };
@@ -0,0 +1,40 @@
+use crate::{api, web_util};
+
+use leptos::*;
+
+/// An image served by the API. The browser can't authorize the request of an `img` element so
+/// the image is downloaded first and embedded as a data URL.
+#[component]
+pub fn StoredImage(
+    cx: Scope,
+    authorized_api: RwSignal<Option<api::AuthorizedApi>>,
+    url: String,
+    #[prop(optional)] class: &'static str,
+    #[prop(optional)] size: Option<(i64, i64)>,
+) -> impl IntoView {
+    let src = create_resource(
+        cx,
+        move || url.clone(),
+        move |url| async move {
+            let api = authorized_api.get()?;
+            match api.image_data(&url).await {
+                Ok(data) => Some(web_util::encode_image_base64(&data)),
+                Err(e) => {
+                    log::error!("Unable to fetch image {url}: {e}");
+                    None
+                }
+            }
+        },
+    );
+
+    view! { cx, {move || {
+        match (src.read(cx).flatten(), size) {
+            (Some(src), Some((width, height))) => {
+                view! { cx, <img class=class src=src width=width height=height></img> }
+                    .into_view(cx)
+            }
+            (Some(src), None) => view! { cx, <img class=class src=src /> }.into_view(cx),
+            (None, _) => view! { cx, <></> }.into_view(cx),
+        }
+    }}}
+}
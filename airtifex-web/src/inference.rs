@@ -1,4 +1,5 @@
 use crate::{api, components::status_message::Message, pages};
+use airtifex_core::{llm::InferenceEvent, queue::QueuePosition};
 
 use futures::StreamExt;
 use leptos::*;
@@ -21,21 +22,60 @@ pub async fn read_inference_stream(
 
                 response_view.update(|rsp| *rsp = "".into());
 
-                loop {
+                let inference_id = response.headers().get("x-inference-id");
+                let mut buffer = vec![];
+                'outer: loop {
                     if should_cancel.get() {
                         should_cancel.update(|c| *c = false);
+                        if let (Some(id), Some(api)) = (&inference_id, authorized_api.get()) {
+                            if let Err(e) = api.inference_cancel(id).await {
+                                log::error!("failed to cancel inference {id} - {e}");
+                            }
+                        }
                         break;
                     }
                     match reader.next().await {
                         Some(Ok(chunk)) => {
-                            let array: Vec<_> = js_sys::Array::from(&chunk)
-                                .iter()
-                                .map(|v| v.as_f64().unwrap_or_default() as u8)
-                                .collect();
-                            let token = String::from_utf8_lossy(&array[..]);
-                            response_view.update(|rsp| {
-                                rsp.push_str(&token);
-                            });
+                            buffer.extend(
+                                js_sys::Array::from(&chunk)
+                                    .iter()
+                                    .map(|v| v.as_f64().unwrap_or_default() as u8),
+                            );
+                            for event in drain_events(&mut buffer) {
+                                match event {
+                                    Ok(InferenceEvent::Queued(queue)) => {
+                                        if let Some(msg) = describe_queue_position(&queue) {
+                                            status_message.update(|m| *m = Message::Success(msg));
+                                        }
+                                    }
+                                    Ok(InferenceEvent::Token(token)) => {
+                                        response_view.update(|rsp| {
+                                            rsp.push_str(&token.token);
+                                        });
+                                    }
+                                    Ok(InferenceEvent::Error(error)) => {
+                                        status_message
+                                            .update(|m| *m = Message::Error(error.message));
+                                        break 'outer;
+                                    }
+                                    Ok(InferenceEvent::Done(done)) => {
+                                        log::debug!(
+                                            "inference finished ({:?}) - {:?}",
+                                            done.finish_reason,
+                                            done.usage
+                                        );
+                                        break 'outer;
+                                    }
+                                    Err(e) => {
+                                        status_message.update(|m| {
+                                            *m = Message::Error(format!(
+                                                "failed to parse inference event - {e}"
+                                            ))
+                                        });
+                                        break 'outer;
+                                    }
+                                }
+                            }
                         }
                         Some(Err(e)) => {
                             status_message
@@ -60,3 +100,40 @@ pub async fn read_inference_stream(
         }
     }
 }
+
+/// Removes all complete Server-Sent Events from the buffer and parses them, leaving any
+/// incomplete event in the buffer. Comments and unknown events are skipped.
+fn drain_events(buffer: &mut Vec<u8>) -> Vec<Result<InferenceEvent, serde_json::Error>> {
+    let mut events = vec![];
+    while let Some(end) = buffer.windows(2).position(|w| w == b"\n\n") {
+        let raw: Vec<u8> = buffer.drain(..end + 2).collect();
+        let raw = String::from_utf8_lossy(&raw);
+
+        let mut name = "message";
+        let mut data = vec![];
+        for line in raw.lines() {
+            if let Some(value) = line.strip_prefix("event:") {
+                name = value.trim();
+            } else if let Some(value) = line.strip_prefix("data:") {
+                data.push(value.strip_prefix(' ').unwrap_or(value));
+            }
+        }
+
+        if let Some(event) = InferenceEvent::from_sse(name, &data.join("\n")) {
+            events.push(event);
+        }
+    }
+    events
+}
+
+/// Describes the position of a job in the queue. Returns `None` if the job doesn't have to wait.
+pub fn describe_queue_position(queue: &QueuePosition) -> Option<String> {
+    if queue.position == 0 && queue.running < queue.max_running {
+        return None;
+    }
+    let mut msg = format!("queued at position {}", queue.position + 1);
+    if let Some(eta) = queue.eta_seconds {
+        msg.push_str(&format!(", estimated wait {eta:.0}s"));
+    }
+    Some(msg)
+}
@@ -1,9 +1,11 @@
 use crate::{
     api,
-    components::{modal::*, status_message::*},
-    pages, Page, PageStack,
+    components::{list_page_control::*, list_search::*, modal::*, status_message::*},
+    pages, web_util, Page, PageStack,
+};
+use airtifex_core::llm::{
+    ChatExport, ChatImportQuery, ChatListEntry, ChatListQuery, ChatStartRequest, InferenceSettings,
 };
-use airtifex_core::llm::{ChatListEntry, ChatStartRequest, InferenceSettings};
 
 use leptos::*;
 
@@ -17,6 +19,8 @@ pub fn Chat(
     page_stack: RwSignal<PageStack>,
 ) -> impl IntoView {
     let current_list_page = create_rw_signal::<u32>(cx, 1);
+    let page_size = create_rw_signal::<usize>(cx, 25);
+    let search = create_rw_signal(cx, String::new());
 
     let status_message = create_rw_signal(cx, Message::Empty);
     let remove_chat_id = create_rw_signal(cx, None::<String>);
@@ -34,10 +38,16 @@ pub fn Chat(
 
     let chats = create_resource(
         cx,
-        move || current_list_page.get(),
-        move |_current_list_page| async move {
+        move || (current_list_page.get(), search.get()),
+        move |(current_list_page, search)| async move {
+            let query = ChatListQuery {
+                page: Some(current_list_page),
+                page_size: Some(page_size.get() as u32),
+                search: Some(search).filter(|s| !s.is_empty()),
+                ..Default::default()
+            };
             match authorized_api.get() {
-                Some(api) => match api.chat_list().await {
+                Some(api) => match api.chat_list(query).await {
                     Ok(chats) => chats,
                     Err(e) => {
                         let e = e.to_string();
@@ -113,9 +123,84 @@ pub fn Chat(
         }
     });
 
+    let import_chat_action = create_action(cx, move |file: &web_sys::File| {
+        let file = file.clone();
+        async move {
+            let api = if let Some(api) = authorized_api.get() {
+                api
+            } else {
+                status_message.update(|m| {
+                    *m = Message::Error("failed to connect to API".into());
+                });
+                return;
+            };
+            let chat = match web_util::read_file(file).await {
+                Ok(data) => match serde_json::from_slice::<ChatExport>(&data) {
+                    Ok(chat) => chat,
+                    Err(e) => {
+                        status_message.update(|m| {
+                            *m = Message::Error(format!("invalid chat export - {e}"));
+                        });
+                        return;
+                    }
+                },
+                Err(e) => {
+                    status_message.update(|m| {
+                        *m = Message::Error(format!(
+                            "failed to read chat export - {}",
+                            e.as_string().unwrap_or_default()
+                        ));
+                    });
+                    return;
+                }
+            };
+            match api.chat_import(chat, ChatImportQuery::default()).await {
+                Ok(response) => {
+                    pages::goto(cx, format!("/chat/{}", response.chat_id)).expect("chat page");
+                }
+                Err(e) => {
+                    let e = e.to_string();
+                    pages::goto_login_if_expired(cx, &e, authorized_api);
+                    status_message.update(|m| {
+                        *m = Message::Error(format!("failed to import chat - {e}"));
+                    });
+                }
+            }
+        }
+    });
+
+    let export_all_action = create_action(cx, move |_| async move {
+        if let Some(api) = authorized_api.get() {
+            let result = match api.user_export().await {
+                Ok(data) => {
+                    web_util::download_file("airtifex-export.zip", "application/zip", &data)
+                        .map_err(|e| e.as_string().unwrap_or_default())
+                }
+                Err(e) => {
+                    let e = e.to_string();
+                    pages::goto_login_if_expired(cx, &e, authorized_api);
+                    Err(e)
+                }
+            };
+            if let Err(e) = result {
+                status_message.update(|m| {
+                    *m = Message::Error(format!("failed to export data - {e}"));
+                });
+            }
+        } else {
+            status_message.update(|m| {
+                *m = Message::Error("failed to connect to API".into());
+            });
+        }
+    });
+
     let dispatch_new_chat_action = move || new_chat_action.dispatch(());
     let dispatch_remove_chat_action = move || remove_chat_action.dispatch(());
 
+    let elem_count = Signal::derive(cx, move || {
+        chats.read(cx).map(|chats| chats.len()).unwrap_or_default()
+    });
+
     let remove_confirm_modal = move || {
         view! { cx,
           <RemoveModal
@@ -143,7 +228,34 @@ pub fn Chat(
                  />
                  <div class="card bg-darker m-3">
                     <StatusMessage message=status_message />
+                    <div class="d-flex flex-row px-5 pt-3">
+                      <div class="input-group me-3">
+                        <label class="input-group-text">
+                          <img class="me-2" src="/icons/upload.svg" />
+                          "Import chat"
+                        </label>
+                        <input
+                          type="file"
+                          accept="application/json"
+                          class="form-control"
+                          on:change = move |ev| {
+                              if let Some(file) = web_util::extract_file_from_html_input(ev) {
+                                  import_chat_action.dispatch(file);
+                              }
+                          }
+                        />
+                      </div>
+                      <button
+                        class="btn btn-outline-lighter text-nowrap"
+                        on:click=move |_| export_all_action.dispatch(())
+                      >
+                        <img class="me-2" src="/icons/download.svg" />
+                        "Export all data"
+                      </button>
+                    </div>
+                    <ListSearch search current_list_page />
                     <ChatListEntries chats remove_chat_id=remove_chat_id remove_chat_title=remove_chat_title />
+                    <ListPageControl current_list_page elem_count page_size=page_size.read_only() />
                  </div>
            </main>
            {remove_confirm_modal}
@@ -417,7 +529,7 @@ where
 #[component]
 fn ChatListEntries(
     cx: Scope,
-    chats: Resource<u32, Vec<ChatListEntry>>,
+    chats: Resource<(u32, String), Vec<ChatListEntry>>,
     remove_chat_id: RwSignal<Option<String>>,
     remove_chat_title: RwSignal<Option<String>>,
 ) -> impl IntoView {
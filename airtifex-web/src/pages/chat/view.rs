@@ -4,7 +4,9 @@ use crate::{
     inference::read_inference_stream,
     pages, web_util, Page, PageStack,
 };
-use airtifex_core::llm::ChatResponseRequest;
+use airtifex_core::llm::{
+    ChatEntryListEntry, ChatEntryType, ChatExportFormat, ChatExportQuery, ChatResponseRequest,
+};
 
 use leptos::*;
 use leptos_router::*;
@@ -16,6 +18,14 @@ pub enum Entry {
     None,
 }
 
+/// The inference requested by the user.
+#[derive(Clone, Debug)]
+enum ChatAction {
+    Prompt(String),
+    Regenerate,
+    Edit { entry_id: String, prompt: String },
+}
+
 #[derive(Params, PartialEq, Clone, Debug)]
 pub struct ChatParams {
     chat_id: Option<String>,
@@ -32,7 +42,10 @@ pub fn ChatView(
     let current_list_page = create_rw_signal::<u32>(cx, 1);
     let dummy_chat_signal = create_rw_signal::<u32>(cx, 1);
     let prompt = create_rw_signal(cx, String::new());
+    // saved entries of the active branch and the entries of the running inference
+    let entries = create_rw_signal(cx, Vec::<ChatEntryListEntry>::new());
     let responses = create_rw_signal(cx, vec![]);
+    let editing = create_rw_signal(cx, None::<String>);
     let last_response = create_rw_signal(cx, (Entry::None, String::new()));
     let infered_response = create_rw_signal(cx, String::new());
     let status_message = create_rw_signal(cx, Message::Empty);
@@ -106,18 +119,8 @@ pub fn ChatView(
 
     create_effect(cx, move |_| {
         if let Some(history) = history.read(cx) {
-            responses.update(|rsp| {
-                *rsp = history
-                    .into_iter()
-                    .map(|entry| {
-                        let ty = match entry.entry_type {
-                            airtifex_core::llm::ChatEntryType::Bot => Entry::Chat,
-                            airtifex_core::llm::ChatEntryType::User => Entry::User,
-                        };
-                        (ty, entry.content)
-                    })
-                    .collect();
-            });
+            entries.update(|e| *e = history);
+            responses.update(|rsp| rsp.clear());
         }
     });
 
@@ -127,9 +130,8 @@ pub fn ChatView(
         }
     });
 
-    let prompt_submit_action = create_action(cx, move |p: &String| {
-        let p = p.clone();
-        let request = ChatResponseRequest { prompt: p };
+    let prompt_submit_action = create_action(cx, move |action: &ChatAction| {
+        let action = action.clone();
         async move {
             let id = if let Some(id) = chat_id.get() {
                 id
@@ -151,10 +153,36 @@ pub fn ChatView(
                     *e = Entry::Chat;
                     rsp.clear();
                 });
-                responses.update(|rsp| {
-                    rsp.push((Entry::User, request.prompt.clone()));
-                });
-                let resp = api.chat_get_response(request, &id).await;
+                let resp = match action {
+                    ChatAction::Prompt(prompt) => {
+                        responses.update(|rsp| {
+                            rsp.push((Entry::User, prompt.clone()));
+                        });
+                        api.chat_get_response(ChatResponseRequest { prompt }, &id)
+                            .await
+                    }
+                    ChatAction::Regenerate => {
+                        entries.update(|entries| {
+                            if matches!(entries.last(), Some(entry) if entry.entry_type == ChatEntryType::Bot)
+                            {
+                                entries.pop();
+                            }
+                        });
+                        api.chat_regenerate(&id).await
+                    }
+                    ChatAction::Edit { entry_id, prompt } => {
+                        entries.update(|entries| {
+                            if let Some(position) = entries.iter().position(|e| e.id == entry_id) {
+                                entries.truncate(position);
+                            }
+                        });
+                        responses.update(|rsp| {
+                            rsp.push((Entry::User, prompt.clone()));
+                        });
+                        api.chat_edit_entry(ChatResponseRequest { prompt }, &id, &entry_id)
+                            .await
+                    }
+                };
                 read_inference_stream(
                     cx,
                     resp,
@@ -171,6 +199,11 @@ pub fn ChatView(
                 });
 
                 is_inference_running.update(|r| *r = false);
+
+                // the entries are saved after the inference finishes, reload the branch so that
+                // the new entries can be edited
+                let _ = web_util::sleep(100).await;
+                current_list_page.update(|p| *p += 1);
             } else {
                 status_message.update(|m| {
                     *m = Message::Error("failed to connect to API".into());
@@ -179,11 +212,174 @@ pub fn ChatView(
         }
     });
 
+    let branch_action = create_action(cx, move |(entry_id, fork): &(String, bool)| {
+        let (entry_id, fork) = (entry_id.clone(), *fork);
+        async move {
+            let (api, id) = match (authorized_api.get(), chat_id.get()) {
+                (Some(api), Some(id)) => (api, id),
+                _ => {
+                    status_message.update(|m| {
+                        *m = Message::Error("failed to connect to API".into());
+                    });
+                    return;
+                }
+            };
+            let branch = if fork {
+                api.chat_fork(&id, &entry_id).await
+            } else {
+                api.chat_select_branch(&id, &entry_id).await
+            };
+            match branch {
+                Ok(branch) => {
+                    entries.update(|e| *e = branch);
+                    responses.update(|rsp| rsp.clear());
+                    editing.update(|e| *e = None);
+                }
+                Err(e) => {
+                    let e = e.to_string();
+                    pages::goto_login_if_expired(cx, &e, authorized_api);
+                    status_message.update(|m| {
+                        *m = Message::Error(format!("failed to switch branch - {e}"));
+                    });
+                }
+            }
+        }
+    });
+
+    let export_action = create_action(cx, move |format: &ChatExportFormat| {
+        let format = *format;
+        async move {
+            let (api, id) = match (authorized_api.get(), chat_id.get()) {
+                (Some(api), Some(id)) => (api, id),
+                _ => {
+                    status_message.update(|m| {
+                        *m = Message::Error("failed to connect to API".into());
+                    });
+                    return;
+                }
+            };
+            let query = ChatExportQuery {
+                format: Some(format),
+            };
+            let (filename, content_type) = match format {
+                ChatExportFormat::Json => (format!("chat-{id}.json"), "application/json"),
+                ChatExportFormat::Markdown => (format!("chat-{id}.md"), "text/markdown"),
+            };
+            let result = match api.chat_export(&id, query).await {
+                Ok(data) => web_util::download_file(&filename, content_type, &data)
+                    .map_err(|e| e.as_string().unwrap_or_default()),
+                Err(e) => {
+                    let e = e.to_string();
+                    pages::goto_login_if_expired(cx, &e, authorized_api);
+                    Err(e)
+                }
+            };
+            if let Err(e) = result {
+                status_message.update(|m| {
+                    *m = Message::Error(format!("failed to export chat - {e}"));
+                });
+            }
+        }
+    });
+
     let dispatch_prompt_submit = move || {
-        prompt_submit_action.dispatch(prompt.get());
+        let action = match editing.get() {
+            Some(entry_id) => ChatAction::Edit {
+                entry_id,
+                prompt: prompt.get(),
+            },
+            None => ChatAction::Prompt(prompt.get()),
+        };
+        prompt_submit_action.dispatch(action);
+        editing.update(|e| *e = None);
         prompt.update(|v| *v = "".into())
     };
 
+    let entry_toolbar = move |entry: &ChatEntryListEntry, is_last: bool| {
+        let position = entry.siblings.iter().position(|id| id == &entry.id);
+        let previous = position
+            .and_then(|p| p.checked_sub(1))
+            .and_then(|p| entry.siblings.get(p).cloned());
+        let next = position.and_then(|p| entry.siblings.get(p + 1).cloned());
+        let (has_previous, has_next) = (previous.is_some(), next.is_some());
+        let alternatives = if entry.siblings.len() > 1 {
+            view! { cx,
+              <button
+                class="btn btn-sm btn-outline-lighter"
+                prop:disabled = !has_previous
+                on:click=move |_| if let Some(previous) = previous.clone() {
+                    branch_action.dispatch((previous, false))
+                }
+              >
+                <img src="/icons/arrow-left.svg" />
+              </button>
+              <span class="btn btn-sm text-secondary">
+                {format!("{}/{}", position.unwrap_or_default() + 1, entry.siblings.len())}
+              </span>
+              <button
+                class="btn btn-sm btn-outline-lighter"
+                prop:disabled = !has_next
+                on:click=move |_| if let Some(next) = next.clone() {
+                    branch_action.dispatch((next, false))
+                }
+              >
+                <img src="/icons/arrow-right.svg" />
+              </button>
+            }
+            .into_view(cx)
+        } else {
+            view! { cx, <></> }.into_view(cx)
+        };
+        let action = match entry.entry_type {
+            ChatEntryType::User => {
+                let (entry_id, content) = (entry.id.clone(), entry.content.clone());
+                view! { cx,
+                  <button
+                    class="btn btn-sm btn-outline-lighter"
+                    prop:disabled = move || is_inference_running.get()
+                    on:click=move |_| {
+                        editing.update(|e| *e = Some(entry_id.clone()));
+                        prompt.update(|p| *p = content.clone());
+                    }
+                  >
+                    <img class="me-2" src="/icons/edit.svg" />
+                    "Edit"
+                  </button>
+                }
+                .into_view(cx)
+            }
+            ChatEntryType::Bot if is_last => view! { cx,
+              <button
+                class="btn btn-sm btn-outline-lighter"
+                prop:disabled = move || is_inference_running.get()
+                on:click=move |_| prompt_submit_action.dispatch(ChatAction::Regenerate)
+              >
+                <img class="me-2" src="/icons/refresh-cw.svg" />
+                "Regenerate"
+              </button>
+            }
+            .into_view(cx),
+            ChatEntryType::Bot => view! { cx, <></> }.into_view(cx),
+        };
+        let entry_id = entry.id.clone();
+        view! { cx,
+          <div class="btn-group ms-3 mb-2" role="entry toolbar" aria-label="entry toolbar">
+            {alternatives}
+            {action}
+            <button
+              class="btn btn-sm btn-outline-lighter"
+              title="Continue the conversation from here"
+              prop:disabled = move || is_inference_running.get()
+              on:click=move |_| branch_action.dispatch((entry_id.clone(), true))
+            >
+              <img class="me-2" src="/icons/files.svg" />
+              "Fork"
+            </button>
+          </div>
+        }
+        .into_view(cx)
+    };
+
     let settings = move || {
         if let Some(Some(chat)) = chat.read(cx) {
             let icon = if is_details_open.get() {
@@ -192,13 +388,31 @@ pub fn ChatView(
                 "/icons/plus-circle.svg"
             };
             view! { cx,
-             <button
-                class="btn-btn-airtifex btn-outline rounded me-auto ms-2 mb-2"
-                on:click=move|_|is_details_open.update(|o| *o = !*o)
-             >
-              <img class="me-2" src=icon />
-              "Details"
-             </button>
+             <div class="d-flex flex-row ms-2 mb-2">
+               <button
+                  class="btn-btn-airtifex btn-outline rounded me-auto"
+                  on:click=move|_|is_details_open.update(|o| *o = !*o)
+               >
+                <img class="me-2" src=icon />
+                "Details"
+               </button>
+               <div class="btn-group me-2" role="export toolbar" aria-label="export toolbar">
+                 <button
+                    class="btn btn-outline-lighter"
+                    on:click=move|_|export_action.dispatch(ChatExportFormat::Json)
+                 >
+                  <img class="me-2" src="/icons/download.svg" />
+                  "Export JSON"
+                 </button>
+                 <button
+                    class="btn btn-outline-lighter"
+                    on:click=move|_|export_action.dispatch(ChatExportFormat::Markdown)
+                 >
+                  <img class="me-2" src="/icons/download.svg" />
+                  "Export Markdown"
+                 </button>
+               </div>
+             </div>
              { if is_details_open.get() {
                  view!{ cx,
                  <div class="card bg-darker">
@@ -262,6 +476,21 @@ pub fn ChatView(
              <div class="d-flex justify-content-between flex-column h-100 w-100 overflow-auto">
                  <div class="px-5 py-2">
                    <div class="w-100 h-100">
+                       { move || {
+                           let saved = entries.get();
+                           let last = saved.len().saturating_sub(1);
+                           saved.iter().enumerate().map(|(n, entry)| {
+                               let (class, prefix) = match entry.entry_type {
+                                   ChatEntryType::User => ("fs-5","User: "),
+                                   ChatEntryType::Bot => ("text-airtifex-light fs-5", "Chat: "),
+                               };
+                               let is_last = n == last && responses.get().is_empty();
+                               view!{cx,
+                                 <p><strong class=class>{prefix}</strong><pre class="fs-6 ms-3">{entry.content.clone()}</pre></p>
+                                 {entry_toolbar(entry, is_last)}
+                               }.into_view(cx)
+                           }).collect::<Vec<_>>()
+                       }}
                        { move || {
                            responses.get().iter().chain([last_response.get()].iter()).map(|(entry, rsp)| {
                                let (class, prefix) = match entry {
@@ -281,6 +510,24 @@ pub fn ChatView(
                          on:submit=|ev|ev.prevent_default()
                          class="row text-start"
                        >
+                           {move || if editing.get().is_some() {
+                               view!{ cx,
+                               <div class="d-flex flex-row mb-2">
+                                 <span class="text-secondary me-auto">"Editing a previous prompt, the original is kept as a branch"</span>
+                                 <button
+                                   class="btn btn-sm btn-outline-lighter"
+                                   on:click=move |_| {
+                                       editing.update(|e| *e = None);
+                                       prompt.update(|p| p.clear());
+                                   }
+                                 >
+                                   <img src="/icons/x.svg" />
+                                 </button>
+                               </div>
+                               }.into_view(cx)
+                           } else {
+                               view!{ cx, <></> }.into_view(cx)
+                           }}
                            <div class="input-group">
                                <textarea
                                  class = "form-control"
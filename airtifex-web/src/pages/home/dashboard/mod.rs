@@ -6,10 +6,13 @@ use image::*;
 
 use crate::{api::AuthorizedApi, components::status_message::*, pages, web_util};
 use airtifex_core::{
-    image::ImageModelListEntry,
-    llm::{LlmListEntry, UserChatCounters},
+    image::{ImageListQuery, ImageModelListEntry},
+    llm::{ChatListQuery, LlmListEntry, UserChatCounters},
 };
 
+/// One more than the number of displayed entries to know if there are more
+const RECENT_PAGE_SIZE: u32 = 6;
+
 use leptos::*;
 
 #[component]
@@ -25,11 +28,14 @@ pub fn Dashboard(
         move || (),
         move |_| async move {
             match authorized_api.get() {
-                Some(api) => match api.chat_list().await {
-                    Ok(mut chats) => {
-                        chats.reverse();
-                        chats
-                    }
+                Some(api) => match api
+                    .chat_list(ChatListQuery {
+                        page_size: Some(RECENT_PAGE_SIZE),
+                        ..Default::default()
+                    })
+                    .await
+                {
+                    Ok(chats) => chats,
                     Err(e) => {
                         let e = e.to_string();
                         pages::goto_login_if_expired(cx, &e, authorized_api);
@@ -51,11 +57,14 @@ pub fn Dashboard(
         move || (),
         move |_| async move {
             match authorized_api.get() {
-                Some(api) => match api.image_list().await {
-                    Ok(mut images) => {
-                        images.reverse();
-                        images
-                    }
+                Some(api) => match api
+                    .image_list(ImageListQuery {
+                        page_size: Some(RECENT_PAGE_SIZE),
+                        ..Default::default()
+                    })
+                    .await
+                {
+                    Ok(images) => images,
                     Err(e) => {
                         let e = e.to_string();
                         pages::goto_login_if_expired(cx, &e, authorized_api);
@@ -1,9 +1,12 @@
 use crate::{
     api,
-    components::{modal::*, status_message::*},
+    components::{
+        list_page_control::*, list_search::*, modal::*, status_message::*, stored_image::*,
+    },
+    inference::describe_queue_position,
     pages, web_util, Page, PageStack,
 };
-use airtifex_core::image::{ImageGenerateRequest, ImageInspect, InputImage};
+use airtifex_core::image::{ImageGenerateRequest, ImageInspect, ImageListQuery, InputImage};
 
 use leptos::*;
 
@@ -18,6 +21,8 @@ pub fn GenerateImage(
     page_stack: RwSignal<PageStack>,
 ) -> impl IntoView {
     let current_list_page = create_rw_signal::<u32>(cx, 1);
+    let page_size = create_rw_signal::<usize>(cx, 25);
+    let search = create_rw_signal(cx, String::new());
 
     let status_message = create_rw_signal(cx, Message::Empty);
     let remove_image_id = create_rw_signal(cx, None::<String>);
@@ -29,6 +34,7 @@ pub fn GenerateImage(
     let width = create_rw_signal(cx, None::<i64>);
     let height = create_rw_signal(cx, None::<i64>);
     let prompt = create_rw_signal(cx, String::new());
+    let negative_prompt = create_rw_signal(cx, String::new());
     let selected_model = create_rw_signal(cx, String::new());
     let n_steps = create_rw_signal(cx, None::<usize>);
     let seed = create_rw_signal(cx, None::<i64>);
@@ -37,10 +43,16 @@ pub fn GenerateImage(
 
     let images = create_resource(
         cx,
-        move || current_list_page.get(),
-        move |_current_list_page| async move {
+        move || (current_list_page.get(), search.get()),
+        move |(current_list_page, search)| async move {
+            let query = ImageListQuery {
+                page: Some(current_list_page),
+                page_size: Some(page_size.get() as u32),
+                search: Some(search).filter(|s| !s.is_empty()),
+                ..Default::default()
+            };
             match authorized_api.get() {
-                Some(api) => match api.image_list().await {
+                Some(api) => match api.image_list(query).await {
                     Ok(images) => images,
                     Err(e) => {
                         let e = e.to_string();
@@ -123,6 +135,7 @@ pub fn GenerateImage(
             });
             let request = ImageGenerateRequest {
                 prompt: prompt.get(),
+                negative_prompt: Some(negative_prompt.get()).filter(|p| !p.is_empty()),
                 input_image,
                 model: selected_model.get(),
                 width: width.get(),
@@ -131,14 +144,19 @@ pub fn GenerateImage(
                 seed: seed.get(),
                 num_samples: num_samples.get(),
                 guidance_scale: guidance_scale.get(),
+                scheduler: None,
             };
             match api.image_generate(request).await {
                 Ok(response) => {
+                    let mut msg = format!("successfuly registered image {}", response.image_id);
+                    if let Some(queue) = response.queue.as_ref().and_then(describe_queue_position) {
+                        msg.push_str(&format!(", {queue}"));
+                    }
+                    for warning in &response.warnings {
+                        msg.push_str(&format!(", {warning}"));
+                    }
                     status_message.update(|m| {
-                        *m = Message::Success(format!(
-                            "successfuly registered image {}",
-                            response.image_id
-                        ));
+                        *m = Message::Success(msg);
                     });
                     current_list_page.update(|p| *p += 1);
                     current_list_page.update(|p| *p -= 1);
@@ -159,6 +177,13 @@ pub fn GenerateImage(
     let dispatch_new_image_action = move || new_image_action.dispatch(());
     let dispatch_remove_image_action = move || remove_image_action.dispatch(());
 
+    let elem_count = Signal::derive(cx, move || {
+        images
+            .read(cx)
+            .map(|images| images.len())
+            .unwrap_or_default()
+    });
+
     let remove_confirm_modal = move || {
         view! { cx,
           <RemoveModal
@@ -181,13 +206,16 @@ pub fn GenerateImage(
                      <h1 class="display-5 p-1">{Page::GenerateImage.title()}</h1>
                  </div>
                  <GenerateImageForm
-                     authorized_api status_message prompt width height n_steps seed num_samples
+                     authorized_api status_message prompt negative_prompt width height n_steps seed
+                     num_samples
                      selected_model dispatch_new_image_action guidance_scale input_image mask
                      strength
                  />
                  <div class="card bg-darker m-3">
                     <StatusMessage message=status_message />
-                    <ImageListEntries images remove_image_id />
+                    <ListSearch search current_list_page />
+                    <ImageListEntries authorized_api images remove_image_id />
+                    <ListPageControl current_list_page elem_count page_size=page_size.read_only() />
                  </div>
            </main>
            {remove_confirm_modal}
@@ -202,6 +230,7 @@ fn GenerateImageForm<F>(
     authorized_api: RwSignal<Option<api::AuthorizedApi>>,
     status_message: RwSignal<Message>,
     prompt: RwSignal<String>,
+    negative_prompt: RwSignal<String>,
     width: RwSignal<Option<i64>>,
     height: RwSignal<Option<i64>>,
     n_steps: RwSignal<Option<usize>>,
@@ -293,6 +322,27 @@ where
                          />
                       </div>
 
+                      <div class="input-group mb-3">
+                         <label class="input-group-text">"Negative Prompt"</label>
+                         <textarea
+                           class = "form-control"
+                           rows="1"
+                           value=move || negative_prompt.get()
+                           placeholder = "..."
+                           on:keyup = move |ev: ev::KeyboardEvent| {
+                                match (&*ev.key(), ev.shift_key()) {
+                                    ("Enter", false) => {
+                                        dispatch_new_image_action();
+                                    }
+                                    _=> {
+                                        let val = event_target_value(&ev);
+                                        negative_prompt.update(|v|*v = val);
+                                    }
+                                }
+                           }
+                         />
+                      </div>
+
                       <div class="input-group mb-3">
                         <label class="input-group-text">"Model"</label>
                         <select
@@ -529,7 +579,8 @@ where
 #[component]
 fn ImageListEntries(
     cx: Scope,
-    images: Resource<u32, Vec<ImageInspect>>,
+    authorized_api: RwSignal<Option<api::AuthorizedApi>>,
+    images: Resource<(u32, String), Vec<ImageInspect>>,
     remove_image_id: RwSignal<Option<String>>,
 ) -> impl IntoView {
     view! { cx, { move || {
@@ -555,7 +606,7 @@ fn ImageListEntries(
                     <tbody>
                    {
                       images.into_iter().map(|image| {
-                          view!{cx, <ImageListEntry image remove_image_id />}.into_view(cx)
+                          view!{cx, <ImageListEntry authorized_api image remove_image_id />}.into_view(cx)
                       }).collect::<Vec<_>>()
                    }
                     </tbody>
@@ -572,6 +623,7 @@ fn ImageListEntries(
 #[component]
 fn ImageListEntry(
     cx: Scope,
+    authorized_api: RwSignal<Option<api::AuthorizedApi>>,
     image: ImageInspect,
     remove_image_id: RwSignal<Option<String>>,
 ) -> impl IntoView {
@@ -587,9 +639,8 @@ fn ImageListEntry(
               >
                   <td class="fitwidth">
                   { move || {
-                    if let Some(thumbnail) = &image.thumbnail {
-                        let image = web_util::encode_image_base64(thumbnail);
-                        view! { cx, <img src=image />}.into_view(cx)
+                    if let Some(url) = image.thumbnail_url.clone() {
+                        view! { cx, <StoredImage authorized_api url />}.into_view(cx)
                     } else {
                         view! { cx, <></> }.into_view(cx)
                     }
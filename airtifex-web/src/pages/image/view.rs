@@ -1,8 +1,9 @@
 use crate::{
     api,
-    components::{status_message::*, titled_child_page::*},
-    pages, web_util, Page, PageStack,
+    components::{status_message::*, stored_image::*, titled_child_page::*},
+    pages, Page, PageStack,
 };
+use airtifex_core::image::ImageVisibility;
 
 use leptos::*;
 use leptos_router::*;
@@ -26,6 +27,29 @@ pub fn ImageView(
 
     let image_id = Signal::derive(cx, move || params.get().ok().and_then(|p| p.image_id));
 
+    let visibility_action = create_action(cx, move |visibility: &ImageVisibility| {
+        let visibility = *visibility;
+        async move {
+            match (authorized_api.get(), image_id.get()) {
+                (Some(api), Some(id)) => {
+                    if let Err(e) = api.image_visibility(&id, visibility).await {
+                        let e = e.to_string();
+                        pages::goto_login_if_expired(cx, &e, authorized_api);
+                        status_message.update(|msg| {
+                            *msg = Message::Error(format!("failed to change visibility - {e}"))
+                        });
+                    } else {
+                        dummy_images_signal.update(|s| *s += 1);
+                    }
+                }
+                _ => {
+                    status_message
+                        .update(|msg| *msg = Message::Error("connection to API failed".into()));
+                }
+            }
+        }
+    });
+
     let metadata = create_resource(
         cx,
         move || dummy_images_signal.get(),
@@ -127,6 +151,38 @@ pub fn ImageView(
                                     <td class="fitwidth text-white">"Finished: "</td>
                                     <td class="text-airtifex-yellow text-center">{is_finished}</td>
                                 </tr>
+                                <tr class="no-border">
+                                    <td class="fitwidth text-white">"Status: "</td>
+                                    <td class="text-airtifex-yellow text-center">{metadata.status.to_str()}</td>
+                                </tr>
+                                <tr class="no-border">
+                                    <td class="fitwidth text-white">"Visibility: "</td>
+                                    <td class="text-center">
+                                        <select
+                                          class="form-select form-select-sm"
+                                          on:change = move |ev| {
+                                            if let Some(visibility) = ImageVisibility::parse_str(event_target_value(&ev)) {
+                                                visibility_action.dispatch(visibility);
+                                            }
+                                          }
+                                        >
+                                        {[ImageVisibility::Private, ImageVisibility::Shared, ImageVisibility::Public].into_iter().map(|visibility| {
+                                            let value = visibility.to_str();
+                                            if visibility == metadata.visibility {
+                                                view!{ cx, <option value=value selected>{value}</option> }.into_view(cx)
+                                            } else {
+                                                view!{ cx, <option value=value>{value}</option> }.into_view(cx)
+                                            }
+                                        }).collect::<Vec<_>>()}
+                                        </select>
+                                    </td>
+                                </tr>
+                                {metadata.error.clone().map(|error| view! { cx,
+                                    <tr class="no-border">
+                                        <td class="fitwidth text-white">"Error: "</td>
+                                        <td class="text-airtifex-yellow text-center">{error}</td>
+                                    </tr>
+                                })}
                                 <tr class="no-border">
                                     <td class="fitwidth text-white">"Width: "</td>
                                     <td class="text-airtifex-yellow text-center">{metadata.width}</td>
@@ -139,6 +195,10 @@ pub fn ImageView(
                                     <td class="fitwidth text-white">"Guidance Scale: "</td>
                                     <td class="text-airtifex-yellow text-center">{metadata.guidance_scale}</td>
                                 </tr>
+                                <tr class="no-border">
+                                    <td class="fitwidth text-white">"Negative Prompt: "</td>
+                                    <td class="text-airtifex-yellow text-center">{metadata.negative_prompt.clone().unwrap_or_default()}</td>
+                                </tr>
                             </tbody>
                         </table>
                         <table class="table table-hover table-responsive text-white">
@@ -190,17 +250,14 @@ pub fn ImageView(
              {details}
              {move || {
                 if let Some(Some(metadata)) = metadata.read(cx) {
-                    if let Some(input_image) = metadata.input_image {
-                        let src= web_util::encode_image_base64(&input_image);
+                    if let Some(input_image_url) = metadata.input_image_url {
                         let size = size.get();
 
-                        let mask = if let Some(mask) = metadata.mask {
-                            let src= web_util::encode_image_base64(&mask);
-
+                        let mask = if let Some(mask_url) = metadata.mask_url {
                             view!{cx,
                                 <div class="d-flex flex-column">
                                     <h2>"Mask:"</h2>
-                                    <img class="p-2" src=src width=size.0 height=size.1></img>
+                                    <StoredImage authorized_api url=mask_url class="p-2" size />
                                 </div>
                             }.into_view(cx)
                         } else {
@@ -211,7 +268,7 @@ pub fn ImageView(
                             <div class="mx-auto p-3">
                                 <div class="d-flex flex-column">
                                     <h2>"Input Image:"</h2>
-                                    <img class="p-2" src=src width=size.0 height=size.1></img>
+                                    <StoredImage authorized_api url=input_image_url class="p-2" size />
                                 </div>
                                 {mask}
                             </div>
@@ -226,8 +283,7 @@ pub fn ImageView(
                 let size = size.get();
                 if let Some(Some(images)) = images.read(cx) {
                      images.into_iter().map(|i| {
-                        let src= web_util::encode_image_base64(&i.data);
-                        view!{cx, <img class="p-2" src=src width=size.0 height=size.1></img>}.into_view(cx)
+                        view!{cx, <StoredImage authorized_api url=i.url class="p-2" size />}.into_view(cx)
                     }).collect::<Vec<_>>()
                 } else {
                     vec![]
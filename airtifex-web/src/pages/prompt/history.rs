@@ -1,5 +1,9 @@
-use crate::{api, components::status_message::*, pages, web_util, Page, PageStack};
-use airtifex_core::llm::PromptInspect;
+use crate::{
+    api,
+    components::{list_page_control::*, list_search::*, status_message::*},
+    pages, web_util, Page, PageStack,
+};
+use airtifex_core::llm::{PromptInspect, PromptListQuery};
 
 use leptos::*;
 
@@ -11,13 +15,22 @@ pub fn PromptList(
 ) -> impl IntoView {
     let status_message = create_rw_signal(cx, Message::Empty);
     let remove_prompt_id = create_rw_signal(cx, None);
+    let current_list_page = create_rw_signal::<u32>(cx, 1);
+    let page_size = create_rw_signal::<usize>(cx, 25);
+    let search = create_rw_signal(cx, String::new());
 
     let prompts = create_resource(
         cx,
-        move || (),
-        move |_| async move {
+        move || (current_list_page.get(), search.get()),
+        move |(current_list_page, search)| async move {
+            let query = PromptListQuery {
+                page: Some(current_list_page),
+                page_size: Some(page_size.get() as u32),
+                search: Some(search).filter(|s| !s.is_empty()),
+                ..Default::default()
+            };
             match authorized_api.get() {
-                Some(api) => match api.prompt_list().await {
+                Some(api) => match api.prompt_list(query).await {
                     Ok(prompts) => prompts,
                     Err(e) => {
                         let e = e.to_string();
@@ -35,11 +48,19 @@ pub fn PromptList(
         },
     );
 
+    let elem_count = Signal::derive(cx, move || {
+        prompts
+            .read(cx)
+            .map(|prompts| prompts.len())
+            .unwrap_or_default()
+    });
+
     view! {cx, {move || {
       page_stack.update(|p| p.push(Page::PromptList));
       view! { cx,
         <main class="bg-dark text-white d-flex flex-column p-3 overflow-auto" >
             <div class="card bg-darker">
+                <ListSearch search current_list_page />
                 <div class="card-body d-flex flex-column">
                 <table class="table table-hover table-striped table-responsive text-white">
                     <thead>
@@ -59,6 +80,7 @@ pub fn PromptList(
                     }
                     </tbody>
                 </table>
+                <ListPageControl current_list_page elem_count page_size=page_size.read_only() />
                 </div>
             </div>
         </main>
@@ -3,7 +3,7 @@ use leptos::*;
 use leptos_router::*;
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::{prelude::Closure, JsCast, JsValue};
-use web_sys::{Event, FileReader, HtmlInputElement};
+use web_sys::{Blob, BlobPropertyBag, Event, FileReader, HtmlAnchorElement, HtmlInputElement, Url};
 
 pub fn extract_file_from_html_input(event: Event) -> Option<web_sys::File> {
     // Get the event target
@@ -68,6 +68,27 @@ pub fn encode_image_base64(image: &[u8]) -> String {
     format!("data:image/png;base64,{encoded}")
 }
 
+/// Makes the browser save the data as a file with the given name.
+pub fn download_file(filename: &str, content_type: &str, data: &[u8]) -> Result<(), JsValue> {
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(data));
+    let mut options = BlobPropertyBag::new();
+    options.type_(content_type);
+    let blob = Blob::new_with_u8_array_sequence_and_options(&parts, &options)?;
+    let url = Url::create_object_url_with_blob(&blob)?;
+
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or("Failed to get document object")?;
+    let anchor = document
+        .create_element("a")?
+        .dyn_into::<HtmlAnchorElement>()?;
+    anchor.set_href(&url);
+    anchor.set_download(filename);
+    anchor.click();
+
+    Url::revoke_object_url(&url)
+}
+
 #[derive(Clone, Copy, Default, Deserialize, Serialize, Debug)]
 pub struct WindowSize {
     pub width: u32,